@@ -0,0 +1,202 @@
+/*****************************************
+    archive.rs
+-----------------
+Description: Handles using a .tar or
+.tar.gz archive as the source, entries are
+extracted into the destination with the
+same excludes/filters as a normal copy
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use flate2::read::GzDecoder;
+
+use crate::copy::{create_dir_all_racy, get_copy_stats, new_progress_bar, report_failure};
+use crate::utils::{canonicalize_partial, count_exclude_hit, format_bytes, is_excluded_name, is_excluded_path, display_path, print_line, report_done, report_progress, set_active_bar, show_current_file, CopyCounters, CopyOptions, CopyStats};
+
+//Function to check if a source looks like an archive we know how to read
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.ends_with(".tar") || is_gzipped(path)
+}
+
+fn is_gzipped(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+pub fn extract_archive(src: &Path, dst: &Path, options: &CopyOptions) -> io::Result<CopyStats> {
+    let file = File::open(src)?;
+    let reader: Box<dyn Read> = if is_gzipped(src) {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut archive = tar::Archive::new(reader);
+    //--data-only leaves modes and times at the defaults here too, like a directory copy with it (see unpack_contents)
+    archive.set_preserve_permissions(!options.data_only);
+    archive.set_preserve_mtime(!options.data_only);
+
+    let counters = CopyCounters::default();
+    //We only learn the sizes as we go, so the bar's length grows with it
+    let pb = new_progress_bar(options);
+
+    if !options.dry_run {
+        create_dir_all_racy(dst)?;
+    }
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let size = entry.size();
+        pb.inc_length(size);
+
+        show_current_file(&pb, &path);
+        if let Err(err) = extract_entry(&mut entry, &path, dst, options, &counters) {
+            report_failure(options, &counters, &path, "Error Extracting", &err);
+        }
+        pb.inc(size);
+        report_progress(&pb, &counters, options);
+    }
+
+    report_done(&pb, &counters, options);
+    pb.finish_with_message("Done extracting.");
+    set_active_bar(None);
+    Ok(get_copy_stats(&counters))
+}
+
+fn extract_entry<R: Read>(entry: &mut tar::Entry<R>, path: &Path, dst: &Path, options: &CopyOptions, counters: &CopyCounters) -> io::Result<()> {
+    //Never let an entry write outside the destination (../../etc/passwd, /etc/passwd). That covers the entry's
+    //name, unpack_in below also catches one that goes through a symlink an earlier entry made (link -> /etc, link/passwd)
+    let Some(rel_path) = safe_relative_path(path) else {
+        return Err(escapes_destination());
+    };
+    //The archive's own root (./) is the destination itself
+    if rel_path.as_os_str().is_empty() {
+        return Ok(());
+    }
+
+    let entry_type = entry.header().entry_type();
+    let is_dir = entry_type.is_dir();
+    if !passes_filters(&rel_path, is_dir, options, counters) {
+        return Ok(());
+    }
+
+    let dest_path = dst.join(&rel_path);
+    if is_dir {
+        counters.dirs.fetch_add(1, Ordering::Relaxed);
+        if options.dry_run {
+            print_line(format_args!("[DRY RUN] mkdir {}", display_path(&dest_path, options).display()));
+            return Ok(());
+        }
+        //A directory entry on top of a symlink would have its permissions set on wherever the link points
+        if fs::symlink_metadata(&dest_path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(escapes_destination());
+        }
+        if options.data_only {
+            unpack_contents(entry, &rel_path, dst, true)?;
+        } else {
+            unpack_inside(entry, dst)?;
+        }
+        if options.show_dirs {
+            print_line(format_args!("[DIR] {}", display_path(&dest_path, options).display()));
+        }
+        return Ok(());
+    }
+
+    if entry_type.is_symlink() {
+        counters.symlinks.fetch_add(1, Ordering::Relaxed);
+    } else {
+        counters.files.fetch_add(1, Ordering::Relaxed);
+    }
+    if options.dry_run || options.structure_only {
+        if options.structure_only {
+            counters.skipped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            counters.bytes.fetch_add(entry.size(), Ordering::Relaxed);
+            print_line(format_args!("[DRY RUN] extract {} -> {} ({})", rel_path.display(), display_path(&dest_path, options).display(), format_bytes(entry.size(), options.si)));
+        }
+        return Ok(());
+    }
+
+    //Archives don't always list a file's parent directories before the file, unpack_in creates them
+    if options.data_only && entry_type.is_file() {
+        unpack_contents(entry, &rel_path, dst, false)?;
+    } else {
+        unpack_inside(entry, dst)?;
+    }
+    counters.bytes.fetch_add(entry.size(), Ordering::Relaxed);
+    if options.show_files {
+        print_line(format_args!("[FILE] {} -> {}", rel_path.display(), display_path(&dest_path, options).display()));
+    }
+    Ok(())
+}
+
+//Same filtering a walked entry gets: excludes, --files-from and --no-hidden
+fn passes_filters(rel_path: &Path, is_dir: bool, options: &CopyOptions, counters: &CopyCounters) -> bool {
+    if options.no_hidden && rel_path.components().any(|c| c.as_os_str().to_string_lossy().starts_with('.')) {
+        counters.hidden_skipped.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    if !is_dir && is_excluded_path(rel_path, &options.excludes) {
+        count_exclude_hit(rel_path, options, counters);
+        return false;
+    }
+    if rel_path.components().any(|c| is_excluded_name(c.as_os_str(), &options.exclude_names)) {
+        return false;
+    }
+    options.files_from.as_ref().is_none_or(|list| list.wants(rel_path, is_dir))
+}
+
+//Function to unpack an entry under dst, tar checks that the directory it lands in resolves to somewhere inside
+//dst (so through no symlink that leads out) and refuses it otherwise
+fn unpack_inside<R: Read>(entry: &mut tar::Entry<R>, dst: &Path) -> io::Result<()> {
+    match entry.unpack_in(dst) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(escapes_destination()),
+        Err(err) if err.to_string().contains("outside of destination") => Err(escapes_destination()),
+        Err(err) => Err(err),
+    }
+}
+
+//Function to unpack a file's contents (or create a directory) for --data-only. tar always applies an entry's mode,
+//this leaves the defaults a new file gets. Same guard as unpack_in, where it lands has to resolve to inside dst
+fn unpack_contents<R: Read>(entry: &mut tar::Entry<R>, rel_path: &Path, dst: &Path, is_dir: bool) -> io::Result<()> {
+    let dest_path = dst.join(rel_path);
+    let dir = if is_dir { dest_path.as_path() } else { dest_path.parent().unwrap_or(dst) };
+    if !canonicalize_partial(dir)?.starts_with(fs::canonicalize(dst)?) {
+        return Err(escapes_destination());
+    }
+    create_dir_all_racy(dir)?;
+    if is_dir {
+        return Ok(());
+    }
+    //Writing through a symlink an earlier entry left here could land anywhere, it's replaced like tar would
+    if fs::symlink_metadata(&dest_path).is_ok_and(|m| m.file_type().is_symlink()) {
+        fs::remove_file(&dest_path)?;
+    }
+    io::copy(entry, &mut File::create(&dest_path)?)?;
+    Ok(())
+}
+
+fn escapes_destination() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "entry path escapes the destination, skipped")
+}
+
+//Function to strip ./ and refuse anything absolute or containing .., None means the entry escapes
+fn safe_relative_path(path: &Path) -> Option<PathBuf> {
+    let mut rel = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => rel.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(rel)
+}
@@ -0,0 +1,80 @@
+/*****************************************
+    bench.rs
+-----------------
+Description: Handles the --bench mode, which
+copies the source into a throwaway temp
+directory with a few different thread counts
+and reports the throughput of each run
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::copy::copy_parallel;
+use crate::utils::{format_bytes, CopyOptions};
+
+//Removes the temp destination when it goes out of scope, so we clean up even if a run fails
+struct TempDest(PathBuf);
+
+impl Drop for TempDest {
+    fn drop(&mut self) {
+        if self.0.exists()
+            && let Err(e) = fs::remove_dir_all(&self.0) {
+            eprintln!("Warning: failed to clean up {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+//Thread counts to try, 1, 2, 4, ... up to what the machine has (always including that)
+fn thread_counts() -> Vec<usize> {
+    let max = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut counts: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|n| *n < max)
+        .collect();
+    counts.push(max);
+    counts
+}
+
+pub fn run_bench(src: &Path, options: &CopyOptions) {
+    if !src.is_dir() {
+        eprintln!("Error: --bench needs a source directory");
+        std::process::exit(1);
+    }
+
+    println!("Benchmarking copy of {}\n", src.display());
+    println!("{:>8}  {:>12}  {:>10}  {:>12}", "threads", "copied", "time", "throughput");
+
+    for threads in thread_counts() {
+        let temp = TempDest(std::env::temp_dir().join(format!("rcpy-bench-{}-{}", std::process::id(), threads)));
+
+        let pool = match rayon::ThreadPoolBuilder::new().num_threads(threads).build() {
+            Ok(pool) => pool,
+            Err(e) => {
+                eprintln!("Error: couldn't start {} threads: {}", threads, e);
+                continue;
+            }
+        };
+
+        let start = Instant::now();
+        let result = pool.install(|| copy_parallel(src, &temp.0, options));
+        let elapsed = start.elapsed();
+
+        match result {
+            Ok(stats) => {
+                let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+                let per_sec = (stats.bytes as f64 / secs) as u64;
+                println!(
+                    "{:>8}  {:>12}  {:>10.2?}  {:>10}/s",
+                    threads,
+                    format_bytes(stats.bytes, options.si),
+                    elapsed,
+                    format_bytes(per_sec, options.si)
+                );
+            }
+            Err(e) => eprintln!("Error: benchmark run with {} thread(s) failed: {}", threads, e),
+        }
+    }
+}
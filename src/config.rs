@@ -0,0 +1,207 @@
+/*****************************************
+    config.rs
+-----------------
+Description: Handles option defaults from
+a TOML config file, --config FILE or the
+nearest .rcpyrc above the current
+directory. The file's settings are turned
+into arguments placed before the command
+line ones so the command line always wins
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use serde::Deserialize;
+
+//Name looked for in the current directory and every parent when there's no --config
+const CONFIG_NAME: &str = ".rcpyrc";
+
+//How many on/off settings a config file can have
+const FLAG_COUNT: usize = 31;
+
+//Keys are the long option names, e.g.
+//  exclude = ["tmp", "log"]
+//  no-hidden = true
+//  preserve = "timestamps,xattr"
+//Precedence, lowest first: built-in defaults, the config file, the command line. Only one file is read,
+//--config replaces the .rcpyrc search and --no-config skips both. Lists like exclude are added to the
+//command line's. A flag set to true here is turned off for one run with its negation (see negation)
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    single_thread: bool,
+    ordered: bool,
+    verbose: bool,
+    only_files: bool,
+    only_dirs: bool,
+    no_recursive: bool,
+    exclude: Vec<String>,
+    exclude_vcs: bool,
+    exclude_empty: bool,
+    skip_recent: Option<u64>,
+    exclude_symlinks: bool,
+    no_hidden: bool,
+    si: bool,
+    quiet_errors: bool,
+    abort_on_walk_error: bool,
+    ignore_error: Vec<String>,
+    verify: bool,
+    verify_size: bool,
+    hash: Option<String>,
+    atomic: bool,
+    fsync: bool,
+    no_clobber_newer: bool,
+    clobber_empty_only: bool,
+    rename_conflicts: bool,
+    repair: bool,
+    throughput: bool,
+    preserve: Option<String>,
+    preserve_flags: bool,
+    preserve_crtime: bool,
+    dereference: bool,
+    copy_contents: bool,
+    keep_dest_dir_perms: bool,
+    keep_dest_dir_times: bool,
+    strict_excludes: bool,
+    skip_too_long: bool,
+    chmod: Option<String>,
+    chmod_dir: Option<String>,
+    collision: Option<String>,
+    order: Option<String>,
+    parallel: Option<String>,
+    progress: Option<String>,
+    progress_refresh: Option<u8>,
+    progress_interval: Option<f64>,
+    eta_smoothing: Option<f64>,
+    ionice: Option<String>,
+    cpu_affinity: Option<String>,
+    format: Option<String>,
+    absolute_paths: bool,
+    max_files: Option<u64>,
+    max_bytes: Option<String>,
+    buffer_size: Option<String>,
+}
+
+impl Config {
+    //Every on/off setting by its long option name, the --no-* switches that turn them back off come from this too
+    fn flags(&self) -> [(&'static str, bool); FLAG_COUNT] {
+        [
+            ("single-thread", self.single_thread),
+            ("ordered", self.ordered),
+            ("verbose", self.verbose),
+            ("only-files", self.only_files),
+            ("only-dirs", self.only_dirs),
+            ("no-recursive", self.no_recursive),
+            ("exclude-vcs", self.exclude_vcs),
+            ("exclude-empty", self.exclude_empty),
+            ("exclude-symlinks", self.exclude_symlinks),
+            ("no-hidden", self.no_hidden),
+            ("si", self.si),
+            ("quiet-errors", self.quiet_errors),
+            ("abort-on-walk-error", self.abort_on_walk_error),
+            ("verify", self.verify),
+            ("verify-size", self.verify_size),
+            ("atomic", self.atomic),
+            ("fsync", self.fsync),
+            ("no-clobber-newer", self.no_clobber_newer),
+            ("clobber-empty-only", self.clobber_empty_only),
+            ("rename-conflicts", self.rename_conflicts),
+            ("repair", self.repair),
+            ("throughput", self.throughput),
+            ("preserve-flags", self.preserve_flags),
+            ("preserve-crtime", self.preserve_crtime),
+            ("dereference", self.dereference),
+            ("copy-contents", self.copy_contents),
+            ("keep-dest-dir-perms", self.keep_dest_dir_perms),
+            ("keep-dest-dir-times", self.keep_dest_dir_times),
+            ("strict-excludes", self.strict_excludes),
+            ("skip-too-long", self.skip_too_long),
+            ("absolute-paths", self.absolute_paths),
+        ]
+    }
+
+    //Function to turn the settings into the arguments they stand for
+    fn to_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        args.extend(self.flags().iter().filter(|(_, set)| *set).map(|(name, _)| OsString::from(format!("--{}", name))));
+
+        let lists = [("exclude", &self.exclude), ("ignore-error", &self.ignore_error)];
+        for (name, values) in lists {
+            args.extend(values.iter().map(|value| OsString::from(format!("--{}={}", name, value))));
+        }
+
+        let values = [
+            ("hash", self.hash.clone()),
+            ("preserve", self.preserve.clone()),
+            ("chmod", self.chmod.clone()),
+            ("chmod-dir", self.chmod_dir.clone()),
+            ("collision", self.collision.clone()),
+            ("order", self.order.clone()),
+            ("parallel", self.parallel.clone()),
+            ("progress", self.progress.clone()),
+            ("progress-refresh", self.progress_refresh.map(|hz| hz.to_string())),
+            ("skip-recent", self.skip_recent.map(|secs| secs.to_string())),
+            ("progress-interval", self.progress_interval.map(|secs| secs.to_string())),
+            ("eta-smoothing", self.eta_smoothing.map(|factor| factor.to_string())),
+            ("ionice", self.ionice.clone()),
+            ("cpu-affinity", self.cpu_affinity.clone()),
+            ("format", self.format.clone()),
+            ("max-files", self.max_files.map(|n| n.to_string())),
+            ("max-bytes", self.max_bytes.clone()),
+            ("buffer-size", self.buffer_size.clone()),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value {
+                args.push(OsString::from(format!("--{}={}", name, value)));
+            }
+        }
+        args
+    }
+}
+
+//Long names of every flag a config file can turn on
+pub fn config_flags() -> impl Iterator<Item = &'static str> {
+    Config::default().flags().into_iter().map(|(name, _)| name)
+}
+
+//Function to name the switch that turns a config file flag back off: --no-verify for verify, a flag that's
+//already a no- drops it instead (--hidden for no-hidden)
+pub fn negation(flag: &str) -> String {
+    match flag.strip_prefix("no-") {
+        Some(positive) => positive.to_string(),
+        None => format!("no-{}", flag),
+    }
+}
+
+//Function to find and read the config file for this run, the arguments it adds come back ready to go in front
+//of the command line's along with the file they came from. None when there is no file to read
+pub fn config_args(matches: &ArgMatches) -> Result<Option<(PathBuf, Vec<OsString>)>, String> {
+    if matches.get_flag("no_config") {
+        return Ok(None);
+    }
+    let path = match matches.get_one::<String>("config") {
+        Some(path) => PathBuf::from(path),
+        None => match find_config() {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+    let args = read_config(&path)?.to_args();
+    Ok(Some((path, args)))
+}
+
+//The nearest .rcpyrc from the current directory up
+fn find_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors().map(|dir| dir.join(CONFIG_NAME)).find(|path| path.is_file())
+}
+
+fn read_config(path: &Path) -> Result<Config, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("can't read config {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+}
@@ -9,26 +9,62 @@ Author: Dylan Morgan
 Date 4/11/2025
 *****************************************/
 
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::ProgressBar;
 use walkdir::DirEntry;
 use std::fs;
 
-use std::io;
-use std::path::Path;
-use std::time::Instant;
+use std::io::{self, Read, Write};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 use rayon::prelude::*;
 
+use crate::platform::{copy_file_flags, copy_ownership, copy_resource_fork, copy_xattrs, is_device, long_path, name_length, preferred_block_size, recreate_device};
 use crate::utils::CopyOptions;
-use crate::utils::{is_excluded, CopyStats, display_complete};
+use crate::prune::{prune_destination, remove_git_deleted};
+use crate::restore::check_manifest;
+use crate::verify::{contents_match, should_verify};
+use crate::utils::{format_bytes, CollisionPolicy, ModeSpec, ParallelStrategy, SummaryFormat, WalkOrder, is_excluded_name, count_exclude_hit, should_copy, skip_reason, SkipReason, is_hidden, print_error, print_line, set_active_bar, normalize_path, canonicalize_partial, path_to_bytes, same_file, display_path, CopyCounters, CopyStats, display_complete, phase_progress_bar, report_done, report_every, report_progress, progress_style, set_progress_refresh, show_current_file};
 
-fn finish_progress(pb: &ProgressBar) {
+//Chunk size used when we stream a copy ourselves instead of using fs::copy
+const COPY_BUFFER_SIZE: usize = 128 * 1024;
+//Block sizes bigger than this are more likely a bogus report than something worth a buffer that size
+const MAX_ALIGNED_BUFFER: u64 = 16 * 1024 * 1024;
+
+fn finish_progress(pb: &ProgressBar, counters: &CopyCounters, options: &CopyOptions) {
+    report_done(pb, counters, options);
     pb.finish_with_message("Done copying.");
+    set_active_bar(None);
+}
+
+//Function to stream a single source file to stdout for `rcpy file -`. The bytes are the only thing written to
+//stdout, anything rcpy has to say goes to stderr. A reader that stops early (| head) isn't an error
+pub fn copy_to_stdout(src: &Path, options: &CopyOptions) -> io::Result<()> {
+    if !should_copy(src, options) {
+        eprintln!("Nothing to copy: {} is filtered out.", normalize_path(src).display());
+        return Ok(());
+    }
+    if options.dry_run {
+        eprintln!("Would have copied: {} -> stdout ({})", display_path(src, options).display(), format_bytes(fs::metadata(src)?.len(), options.si));
+        return Ok(());
+    }
+
+    let mut source = fs::File::open(long_path(src))?;
+    let mut out = io::BufWriter::with_capacity(options.buffer_size.unwrap_or(COPY_BUFFER_SIZE), io::stdout().lock());
+    match io::copy(&mut source, &mut out).and_then(|_| out.flush()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
 }
 
-pub fn copied_single(src: &Path, dst: &Path, start_time: &Instant, dry_run: bool) -> bool {
+pub fn copied_single(src: &Path, dst: &Path, start_time: &Instant, options: &CopyOptions) -> bool {
     	//Getting metadata so we can check if we are copying a single file
-	let metadata = match std::fs::metadata(&src) {
+	let metadata = match std::fs::metadata(src) {
 		Ok(m) => m,
 		Err(e) => {
 			eprintln!("Error reading source: {}", e);
@@ -38,175 +74,1693 @@ pub fn copied_single(src: &Path, dst: &Path, start_time: &Instant, dry_run: bool
 
 	//Handle case of copying a single file!
 	if metadata.is_file() {
-		if dst.is_dir() {
+        if options.structure_only {
+            println!("Structure only mode: {} is a single file, nothing to copy.", normalize_path(src).display());
+            return true;
+        }
+
+        //Same filters a file found by the walk goes through (--exclude, --newer-than-file...)
+        if !should_copy(src, options) {
+            println!("Nothing to copy: {} is filtered out.", normalize_path(src).display());
+            return true;
+        }
+
+		//Work out where the file lands
+		let target = if let Some(base) = &options.base {
+			//Keep the file's path relative to --base, e.g. base/src/a.rs -> dst/src/a.rs
+			match relative_to_base(src, base) {
+				Ok(rel) => dst.join(rel),
+				Err(e) => {
+					eprintln!("Error: {}", e);
+					std::process::exit(1);
+				}
+			}
+		} else if dst.is_dir() {
 			//If destination is a folder, append filename
 			let filename = src.file_name().unwrap();
-			let target = dst.join(filename);
+			dst.join(filename)
+		} else {
+			dst.to_path_buf()
+		};
 
-            if dry_run {
-                let duration = start_time.elapsed();
-                println!("\n\n------------DRY RUN COMPLETE------------\n");
-                println!("\nWould have copied: {} -> {}", src.display(), target.display());
-                println!("Duration: {:.2?}", duration);
-                println!("\n-----------------------------------------\n");
-                return true;
-            }
-    
-			match fs::copy(&src, &target) {
-				Ok(_) =>{ 
-				let duration = start_time.elapsed();
-                    println!("\n\n--------------COPY COMPLETE--------------\n");
-                    println!("\nCopied: {} -> {}", src.display(), target.display());
-                    println!("Duration: {:.2?}", duration);
-                    println!("\n-----------------------------------------\n");
-                },
-				Err(e) => eprintln!("Error copying file: {}", e)
-			}
+		//Copying a file onto itself (./a.txt onto ../dir/a.txt, or a hard link of it) would truncate it to nothing
+		if same_file(src, &target) {
+			eprintln!("Error: {} and {} are the same file, not copying it onto itself", src.display(), target.display());
+			std::process::exit(1);
+		}
+
+		//Nothing else is copying at the same time, the first free number will do
+		let renamed_conflicts = u64::from(options.rename_conflicts && !can_take(src, &target, options));
+		let target = if renamed_conflicts > 0 {
+			(2..).map(|n| numbered_path(&target, n)).find(|candidate| can_take(src, candidate, options)).unwrap()
 		} else {
+			target
+		};
 
-            if dry_run {
-                let duration = start_time.elapsed();
-                println!("\n\n------------DRY RUN COMPLETE------------\n");
-                println!("\nWould have copied: {} -> {}", src.display(), dst.display());
-                println!("Duration: {:.2?}", duration);
-                println!("\n-----------------------------------------\n");
-                return true;
-            }
+		if let Some(veto) = overwrite_veto(src, &target, options) {
+			eprintln!("Warning: leaving {} alone, {} ({})", display_path(&target, options).display(), veto.reason(), veto.flag());
+			return true;
+		}
 
-			match fs::copy(&src, &dst) {
-				Ok(_) =>{
-					let duration = start_time.elapsed();
-                    println!("\n\n--------------COPY COMPLETE--------------\n");
-                    println!("Copied: {} -> {}", src.display(), dst.display());
-                    println!("Duration: {:.2?}", duration);
-                    println!("\n-----------------------------------------\n");
-				},
-				Err(e) => eprintln!("Error copying file: {}", e)
+		if options.repair && fs::metadata(&target).is_ok_and(|existing| existing.is_file() && existing.len() == metadata.len()) {
+			println!("Nothing to repair: {} already has the source's size.", display_path(&target, options).display());
+			return true;
+		}
+
+        if options.dry_run && options.summary != SummaryFormat::Text {
+            let stats = CopyStats { files: 1, bytes: metadata.len(), ..Default::default() };
+            stats.print_compact(start_time.elapsed(), options);
+            return true;
+        }
+        if options.dry_run {
+            let duration = start_time.elapsed();
+            println!("\n\n------------DRY RUN COMPLETE------------\n");
+            println!("\nWould have copied: {} -> {} ({})", display_path(src, options).display(), display_path(&target, options).display(), format_bytes(metadata.len(), options.si));
+            println!("Duration: {:.2?}", duration);
+            println!("\n-----------------------------------------\n");
+            return true;
+        }
 
+		//With --base the parent directories may not exist yet
+		if options.base.is_some()
+			&& let Some(parent) = target.parent()
+			&& let Err(e) = create_dir_all_racy(parent) {
+			eprintln!("Error creating {}: {}", parent.display(), e);
+			return true;
+		}
+
+		let (fs_src, fs_target) = (long_path(src), long_path(&target));
+		let counters = CopyCounters::default();
+		let copied = copy_file_forced(&fs_src, &fs_target, options, &counters).and_then(|bytes| {
+			//An appended file is meant to end up bigger than the source, there's nothing to compare
+			if options.verify_size && !options.append {
+				check_copied_size(&fs_src, &fs_target, &counters)?;
+			}
+			Ok(bytes)
+		});
+		match copied {
+			Ok(bytes) =>{
+				if options.preserve_crtime {
+					copy_creation_time(&fs_src, &fs_target);
+				}
+				copy_attributes(&fs_src, &fs_target, options);
+				if options.fsync {
+					let parent = fs_target.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+					if let Err(e) = sync_directory(parent) {
+						eprintln!("Failed to fsync directory {}: {}", parent.display(), e);
+					}
+				}
+				if should_verify(src, options) {
+					match contents_match(&fs_src, &fs_target, options.hash) {
+						Ok(true) => println!("Verified: copy matches the source"),
+						Ok(false) => {
+							eprintln!("Verification failed: {} doesn't match the source", target.display());
+							std::process::exit(1);
+						}
+						Err(e) => eprintln!("Couldn't verify {}: {}", target.display(), e),
+					}
+				}
+				let duration = start_time.elapsed();
+				let forced_overwrites = counters.forced_overwrites.load(Ordering::Relaxed);
+				let stats = CopyStats { files: 1, bytes, forced_overwrites, renamed_conflicts, ..Default::default() };
+				if let Some(file) = &options.progress_file {
+					file.finish(&stats, duration, options);
+				}
+				if stats.print_compact(duration, options) {
+					return true;
+				}
+                println!("\n\n--------------COPY COMPLETE--------------\n");
+                println!("\nCopied: {} -> {} ({})", display_path(src, options).display(), display_path(&target, options).display(), format_bytes(bytes, options.si));
+                if forced_overwrites > 0 {
+                    println!("The destination was read-only and has been replaced.");
+                }
+                if renamed_conflicts > 0 {
+                    println!("The destination already held a different file, the copy was written under a numbered name.");
+                }
+                println!("Duration: {:.2?}", duration);
+                println!("\n-----------------------------------------\n");
+			},
+			Err(e) => {
+				if !is_error_ignored(options, src) {
+					eprintln!("Error copying file: {}", e);
+					log_failure(options, src, &e);
+					std::process::exit(1);
+				}
 			}
 		}
-		 return true;
+		 true
 	}
     else {
         false
     }
 }
 
+//Path of a single source file relative to --base, errors if the file isn't actually under it
+fn relative_to_base(src: &Path, base: &Path) -> io::Result<PathBuf> {
+    let real_src = fs::canonicalize(src)?;
+    let real_base = fs::canonicalize(base)?;
+    match real_src.strip_prefix(&real_base) {
+        Ok(rel) => Ok(rel.to_path_buf()),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not inside --base {}", src.display(), base.display()),
+        )),
+    }
+}
+
 pub fn copy_parallel(
         src: &Path,
         dst: &Path,
         options: &CopyOptions
     ) -> io::Result<CopyStats> {
 
-        //Setup our walker based on whether or not we are performing a recursive copy
-        let walker = if options.recursive {
-            WalkDir::new(src)
-        } else {
-            WalkDir::new(src).max_depth(1)
-        };
-    
-        //Get entries via our walker
-        let entries: Vec<_> = walker.into_iter().collect::<Result<_, _>>()?;
-    
-        //Setting up our progress bar
-        let pb = ProgressBar::new(entries.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{bar:40.cyan/blue} {pos}/{len} [{elapsed_precise}]")
-                .unwrap(),
-        );
-
-        //Getting our files and directories
-        let (dirs, files): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.file_type().is_dir());
-        
-        //Loop through directories
-        for dir in &dirs {
-            let path = dir.path().strip_prefix(src).unwrap();
-            if let Err(err) = create_directories(path, dst, options, &pb) {
-                eprint!("Error Copying Directory: {}", err);
+        //Shared counters our workers update while copying
+        let counters = CopyCounters::default();
+
+        //Setting up our progress bar, its length grows as the walk finds entries
+        let pb = new_progress_bar(options);
+
+        //Refuse runaway copies before anything gets written
+        check_file_limit(src, options)?;
+
+        //First walk creates the directories so every file has somewhere to land
+        create_all_directories(src, dst, options, &pb, &counters)?;
+
+        //Second walk streams the files straight into rayon, nothing is collected up front
+        copy_phase(&pb, options, &counters, || {
+            if by_subtree(options) {
+                copy_subtrees(src, dst, options, &pb, &counters);
+                return;
             }
+            walk_source(src, options, None)
+                .filter_map(Result::ok)
+                .filter(|e| !is_dir_entry(e))
+                .par_bridge() //This runs in parallel! Thanks Rayon!
+                .for_each(|entry| {
+                    copy_entry(&entry, src, dst, options, &pb, &counters);
+                    report_progress(&pb, &counters, options);
+                });
+        });
+
+        finish_progress(&pb, &counters, options);
+        print_planned_tree(dst, options, &counters);
+        verify_all(options, &counters, true);
+        check_manifest(src, dst, options, &counters, true);
+        sync_directories(options, &counters);
+        if options.delete {
+            prune_destination(src, dst, options, &counters);
         }
-        
-        //Here is where we will loop through files and use rayon to parse in parallel
-        files
-            .par_iter() //This runs in parallel! Thanks Rayon!
+        remove_git_deleted(dst, options, &counters);
+        apply_directory_times(&counters);
+        apply_directory_permissions(options, &counters);
+    
+        Ok(get_copy_stats(&counters))
+ }
+
+ //--parallel dirs only makes sense when there are subtrees to hand out. With --copy-contents a subtree's walk
+ //can't see the directories above it, so a link back up the tree wouldn't be caught as a loop
+ fn by_subtree(options: &CopyOptions) -> bool {
+    options.parallel == ParallelStrategy::Dirs && options.recursive && !options.copy_contents
+ }
+
+ //--parallel dirs: one rayon task per top-level entry, each walking and copying its own subtree in order, instead
+ //of every file going into one shared pool. The counters are shared, so the stats come out the same either way
+ fn copy_subtrees(src: &Path, dst: &Path, options: &CopyOptions, pb: &ProgressBar, counters: &CopyCounters) {
+    let top_level: Vec<DirEntry> = walk_tree(src, src, 1, options, None)
+        .filter_map(Result::ok)
+        .filter(|e| e.depth() == 1)
+        .collect();
+
+    top_level.par_iter().for_each(|top| {
+        if !is_dir_entry(top) {
+            copy_entry(top, src, dst, options, pb, counters);
+            report_progress(pb, counters, options);
+            return;
+        }
+        walk_tree(src, top.path(), usize::MAX, options, None)
+            .filter_map(Result::ok)
+            .filter(|e| !is_dir_entry(e))
             .for_each(|entry| {
-                if is_excluded(entry, &options.excludes) {
-                    pb.inc(1);
-                    return;
-                }
-                let path = entry.path().strip_prefix(src).unwrap();
-                if let Err(err) =create_files(path, dst, options, &pb) {
-                    eprint!("Error Copying File: {}", err);
-                }
+                copy_entry(&entry, src, dst, options, pb, counters);
+                report_progress(pb, counters, options);
             });
+    });
+ }
 
-        finish_progress(&pb);
-    
-        Ok(get_copy_stats(files, dirs, options))
+ //Runs the file copying phase and times it for the throughput figures. With --throughput a sampler thread
+ //reads the byte counter a few times a second for the peak, with --progress-interval another one reports
+ //progress on its timer. Both stop the moment the copy returns
+ fn copy_phase(pb: &ProgressBar, options: &CopyOptions, counters: &CopyCounters, copy: impl FnOnce()) {
+    let started = Instant::now();
+    let mut peak = 0;
+    std::thread::scope(|scope| {
+        let (stop_sampler, sampler_stopped) = mpsc::channel::<()>();
+        let (stop_reporter, reporter_stopped) = mpsc::channel::<()>();
+        let sampler = options.throughput.then(|| scope.spawn(|| sample_peak_rate(&counters.bytes, sampler_stopped)));
+        if let Some(interval) = options.progress_interval {
+            scope.spawn(move || report_every(interval, pb, counters, options, reporter_stopped));
+        }
+        copy();
+        drop((stop_sampler, stop_reporter));
+        if let Some(sampler) = sampler {
+            peak = sampler.join().unwrap_or(0);
+        }
+    });
+    let millis = started.elapsed().as_millis() as u64;
+    counters.copy_millis.store(millis, Ordering::Relaxed);
+    //A copy too short for a full sample still had a peak, it just can't be below the average
+    if options.throughput && millis > 0 {
+        peak = peak.max(counters.bytes.load(Ordering::Relaxed).saturating_mul(1000) / millis);
+    }
+    counters.peak_rate.store(peak, Ordering::Relaxed);
+ }
+
+ //Highest bytes per second over a sliding window until told to stop (the sender dropped). Bytes are only
+ //counted once a file is done, so the window is a few seconds wide, one tick would make a single big file
+ //finishing look like an impossible burst. Windows shorter than a second aren't trusted at all
+ fn sample_peak_rate(bytes: &AtomicU64, stopped: mpsc::Receiver<()>) -> u64 {
+    const TICK: Duration = Duration::from_millis(250);
+    const WINDOW: Duration = Duration::from_secs(3);
+
+    let mut samples: VecDeque<(Instant, u64)> = VecDeque::new();
+    samples.push_back((Instant::now(), bytes.load(Ordering::Relaxed)));
+    let mut peak = 0;
+    while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(TICK) {
+        let now = (Instant::now(), bytes.load(Ordering::Relaxed));
+        while samples.len() > 1 && now.0.duration_since(samples[1].0) >= WINDOW {
+            samples.pop_front();
+        }
+        let (since, from) = samples[0];
+        let span = now.0.duration_since(since).as_secs_f64();
+        if span >= 1.0 {
+            peak = peak.max(((now.1 - from) as f64 / span) as u64);
+        }
+        samples.push_back(now);
+    }
+    peak
+ }
+
+ //Function to print the relative path of every file the copy would write for --list, same walk and filters as
+ //the copy itself. Nothing else goes to stdout so it can be piped, prefix is the match name for glob sources
+ pub fn list_copy_set(src: &Path, prefix: &Path, null_separated: bool, options: &CopyOptions) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let separator = if null_separated { b'\0' } else { b'\n' };
+
+    let entries = walk_source(src, options, None)
+        .filter_map(Result::ok)
+        .filter(|e| !is_dir_entry(e) && skip_reason(e.path(), options).is_none());
+    for entry in entries {
+        let rel_path = entry.path().strip_prefix(src).unwrap();
+        if options.nested_dest.as_ref().is_some_and(|nested| fs::canonicalize(entry.path()).is_ok_and(|real| real.starts_with(nested))) {
+            continue;
+        }
+        //A single file source is its own (empty) relative path, list it by name
+        let listed = if rel_path.as_os_str().is_empty() { PathBuf::from(entry.file_name()) } else { prefix.join(rel_path) };
+        out.write_all(&path_to_bytes(&listed))?;
+        out.write_all(&[separator])?;
+    }
+    out.flush()
+ }
+
+ //Function to pick the count most recently modified files for --newest out of everything the copy would take,
+ //after every exclude and filter. Only the pick is sorted, ties go to the path that sorts first so the same tree
+ //always gives the same files. A file whose time can't be read counts as the oldest. Also returns how many there were
+ pub fn newest_files(src: &Path, options: &CopyOptions, count: usize) -> (Vec<PathBuf>, usize) {
+    let mut candidates: Vec<(SystemTime, PathBuf)> = walk_source(src, options, None)
+        .filter_map(Result::ok)
+        .filter(|e| !is_dir_entry(e) && should_copy(e.path(), options))
+        .map(|e| {
+            let modified = fs::metadata(e.path()).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, e.path().strip_prefix(src).unwrap().to_path_buf())
+        })
+        .collect();
+    let total = candidates.len();
+
+    let newest_first = |a: &(SystemTime, PathBuf), b: &(SystemTime, PathBuf)| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1));
+    if count < total {
+        candidates.select_nth_unstable_by(count, newest_first);
+        candidates.truncate(count);
+    }
+    candidates.sort_by(newest_first);
+    (candidates.into_iter().map(|(_, path)| path).collect(), total)
+ }
+
+ //Walker which varies depending on if we are doing recursive copy or not, --files-from is applied here too
+ //Hidden entries are pruned here with --no-hidden (whole hidden directories are never descended into),
+ //pass the counters to have them tallied, only one of the walks should do that
+ //With --copy-contents symlinked directories are descended into, WalkDir keeps the (dev, ino) of every
+ //directory above the current one so a link back up the tree is caught as a loop and left out
+ //With --order breadth-first the entries have to be collected and sorted by depth, so that mode isn't lazy
+ //With --ordered each directory's entries come sorted by name, WalkDir reads a whole directory to sort it
+ pub fn walk_source<'a>(src: &'a Path, options: &'a CopyOptions, counters: Option<&'a CopyCounters>) -> Box<dyn Iterator<Item = walkdir::Result<DirEntry>> + Send + 'a> {
+    walk_tree(src, src, if options.recursive { usize::MAX } else { 1 }, options, counters)
+ }
+
+ //Same walk started from root somewhere inside the source, for --parallel dirs walking one subtree per task
+ //Entries are still filtered relative to src, root itself should already have passed the filters
+ fn walk_tree<'a>(src: &'a Path, root: &Path, max_depth: usize, options: &'a CopyOptions, counters: Option<&'a CopyCounters>) -> Box<dyn Iterator<Item = walkdir::Result<DirEntry>> + Send + 'a> {
+    let walker = WalkDir::new(root).max_depth(max_depth);
+    //--ordered sorts every directory's entries by name so the destination is created in the same order every run
+    let walker = if options.ordered { walker.sort_by_file_name() } else { walker };
+
+    let entries = walker.follow_links(options.copy_contents).into_iter().filter_entry(move |entry| {
+        //Excluded names (--exclude-vcs) are pruned here so their directories are never descended into
+        if entry.depth() > 0 && is_excluded_name(entry.file_name(), &options.exclude_names) {
+            return false;
+        }
+        //The source root is always copied, even when it's hidden itself
+        if !options.no_hidden || entry.depth() == 0 || !is_hidden(entry) {
+            return true;
+        }
+        if let Some(counters) = counters {
+            counters.hidden_skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        false
+    })
+    .filter(move |entry| {
+        let Err(err) = entry else {
+            return true;
+        };
+        let Some(ancestor) = err.loop_ancestor() else {
+            return true;
+        };
+        if let Some(counters) = counters {
+            counters.symlink_loops.fetch_add(1, Ordering::Relaxed);
+            if !options.quiet_errors {
+                print_error(format_args!("Skipping symlink loop: {} leads back to {}",
+                    display_path(err.path().unwrap_or(ancestor), options).display(), display_path(ancestor, options).display()));
+            }
+        }
+        false
+    })
+    .filter(move |entry| match (entry, &options.files_from) {
+        (Ok(e), Some(list)) => list.wants(e.path().strip_prefix(src).unwrap(), is_dir_entry(e)),
+        _ => true,
+    });
+
+    match options.order {
+        WalkOrder::DepthFirst => Box::new(entries),
+        WalkOrder::BreadthFirst => {
+            //Stable sort keeps WalkDir's order for entries at the same depth
+            let mut collected: Vec<_> = entries.collect();
+            collected.sort_by_key(|entry| match entry {
+                Ok(e) => e.depth(),
+                Err(e) => e.depth(),
+            });
+            Box::new(collected.into_iter())
+        }
+    }
+ }
+
+ //WalkDir follows a symlinked source root but still reports the root entry as a symlink,
+ //so treat that one as the directory it points to
+ pub fn is_dir_entry(entry: &DirEntry) -> bool {
+    entry.file_type().is_dir() || (entry.depth() == 0 && entry.path_is_symlink() && entry.path().is_dir())
+ }
+
+ pub fn new_progress_bar(options: &CopyOptions) -> ProgressBar {
+    //Length is the total bytes to copy so indicatif's ETA is based on the real amount of work
+    let pb = ProgressBar::new(0);
+    set_progress_refresh(&pb, options);
+    set_active_bar(Some(&pb));
+    //wide_msg is the current file, cut down to whatever width the terminal has left
+    pb.set_style(progress_style("{bar:40.cyan/blue} {bytes}/{total_bytes} ETA {eta} [{elapsed_precise}] {wide_msg}", options));
+    pb
+ }
+
+ //Walks the source once creating directories as they are found, files are only sized up for the progress bar
+ //so we never hold the whole tree in memory. The bar's length is complete before the first file is copied
+ //A directory that can't be read is reported and left out, the rest of the tree is still copied. Only an
+ //unreadable source root (or any of them with --abort-on-walk-error) stops the copy
+ fn create_all_directories(src: &Path, dst: &Path, options: &CopyOptions, pb: &ProgressBar, counters: &CopyCounters) -> io::Result<()> {
+    for entry in walk_source(src, options, Some(counters)) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) if err.depth() == 0 || options.abort_on_walk_error => return Err(err.into()),
+            Err(err) => {
+                skip_unreadable(&err, src, options, counters);
+                continue;
+            }
+        };
+        if !is_dir_entry(&entry) {
+            if should_copy(entry.path(), options) {
+                pb.inc_length(entry_size(&entry));
+            }
+            continue;
+        }
+
+        let path = entry.path().strip_prefix(src).unwrap();
+        //No point trying (and failing again) under a directory we couldn't create
+        if under_failed_dir(path, counters).is_some() {
+            continue;
+        }
+
+        //Directories --strip-components removes entirely aren't created, their contents land higher up
+        if dest_rel_path(path, options).is_none() {
+            continue;
+        }
+        //A directory that can't be named at the destination takes its contents with it
+        if !name_fits(path, false, options, counters) {
+            counters.failed_dirs.lock().unwrap().insert(path.to_path_buf(), 0);
+            continue;
+        }
+        if let Err(err) = create_directories(path, dst, options, counters) {
+            report_failure(options, counters, path, "Error Copying Directory", err.as_ref());
+            counters.failed_dirs.lock().unwrap().insert(path.to_path_buf(), 0);
+        }
+    }
+    Ok(())
  }
 
- fn get_copy_stats(files: Vec<DirEntry>, dirs: Vec<DirEntry>, options: &CopyOptions) -> CopyStats {
+ //With --max-files the tree gets counted first and the copy refused if it has too many files
+ //This is its own walk since the directory pass already writes to the destination
+ fn check_file_limit(src: &Path, options: &CopyOptions) -> io::Result<()> {
+    let Some(max_files) = options.max_files else {
+        return Ok(());
+    };
+
+    let found = walk_source(src, options, None)
+        .filter_map(Result::ok)
+        .filter(|e| !is_dir_entry(e) && should_copy(e.path(), options))
+        .count() as u64;
+    if found > max_files {
+        return Err(io::Error::other(format!(
+            "found {} files, more than --max-files {} (raise the limit or pass --force to copy anyway)",
+            found, max_files
+        )));
+    }
+    Ok(())
+ }
+
+ //Copies one walked file entry, shared by the parallel and single threaded loops
+ fn copy_entry(entry: &DirEntry, src: &Path, dst: &Path, options: &CopyOptions, pb: &ProgressBar, counters: &CopyCounters) {
+    match skip_reason(entry.path(), options) {
+        None => {}
+        Some(SkipReason::Empty) => { counters.skipped_empty.fetch_add(1, Ordering::Relaxed); return; }
+        Some(SkipReason::Symlink) => { counters.skipped_symlinks.fetch_add(1, Ordering::Relaxed); return; }
+        Some(SkipReason::NotExecutable) => { counters.skipped_not_executable.fetch_add(1, Ordering::Relaxed); return; }
+        Some(SkipReason::Recent) => { counters.skipped_recent.fetch_add(1, Ordering::Relaxed); return; }
+        Some(SkipReason::Excluded) => { count_exclude_hit(entry.path(), options, counters); return; }
+        Some(_) => return,
+    }
+
+    //Files under a directory that couldn't be created are skipped and reported once per directory
+    let path = entry.path().strip_prefix(src).unwrap();
+    if let Some(failed_dir) = under_failed_dir(path, counters) {
+        *counters.failed_dirs.lock().unwrap().get_mut(&failed_dir).unwrap() += 1;
+        pb.inc(entry_size(entry));
+        return;
+    }
+
+    //Finished by an earlier run according to the checkpoint
+    if options.checkpoint.as_ref().is_some_and(|checkpoint| checkpoint.is_done(path)) {
+        counters.resumed.fetch_add(1, Ordering::Relaxed);
+        pb.inc(entry_size(entry));
+        return;
+    }
+
+    if dest_rel_path(path, options).is_none() {
+        stripped_away(path, options, counters);
+        pb.inc(entry_size(entry));
+        return;
+    }
+
+    if !name_fits(path, true, options, counters) {
+        pb.inc(entry_size(entry));
+        return;
+    }
+
+    //Past --max-bytes the file is left for the next run
+    if !take_budget(entry_size(entry), options, counters) {
+        pb.inc(entry_size(entry));
+        return;
+    }
+
+    //With --devices a device node is recreated, never opened and read
+    if options.devices && is_device(&entry.file_type()) {
+        if let Err(err) = copy_device(path, dst, options, counters) {
+            report_failure(options, counters, path, "Error Copying Device", &err);
+        }
+        pb.inc(entry_size(entry));
+        return;
+    }
+
+    show_current_file(pb, path);
+    if let Err(err) = create_files(path, dst, options, counters) {
+        report_failure(options, counters, path, "Error Copying File", err.as_ref());
+    }
+    //Advance by the same size that went into the bar's length, whether the copy worked or not
+    //Empty files add nothing here, the bar's message still moves on to the next file so it doesn't look stuck
+    pb.inc(entry_size(entry));
+ }
+
+ //Reports an entry the walk couldn't read, whatever was inside it is skipped. Only the first walk reports
+ //them, the later ones just leave the same entries out
+ fn skip_unreadable(err: &walkdir::Error, src: &Path, options: &CopyOptions, counters: &CopyCounters) {
+    counters.unreadable.fetch_add(1, Ordering::Relaxed);
+    let path = err.path().map_or(src, |path| path.strip_prefix(src).unwrap_or(path));
+    match err.io_error() {
+        Some(io_err) => report_failure(options, counters, path, "Error Reading", io_err),
+        None => report_failure(options, counters, path, "Error Reading", err),
+    }
+ }
+
+ //Reserves a file's size against --max-bytes before it starts, false means it's left for a later run
+ //Sizes are reserved up front so parallel workers can't overshoot together, the only file allowed past the
+ //limit is the very first one, otherwise a file bigger than the limit would never get copied at all.
+ //Once one file doesn't fit nothing new starts, so a resumed run picks up where this one stopped
+ fn take_budget(size: u64, options: &CopyOptions, counters: &CopyCounters) -> bool {
+    let Some(max_bytes) = options.max_bytes else {
+        return true;
+    };
+    if !counters.budget_full.load(Ordering::Relaxed) {
+        let before = counters.budget_used.fetch_add(size, Ordering::Relaxed);
+        if before == 0 || before + size <= max_bytes {
+            return true;
+        }
+        counters.budget_used.fetch_sub(size, Ordering::Relaxed);
+        counters.budget_full.store(true, Ordering::Relaxed);
+    }
+    counters.deferred.fetch_add(1, Ordering::Relaxed);
+    counters.deferred_bytes.fetch_add(size, Ordering::Relaxed);
+    false
+ }
+
+ //Recreates one device node at the destination, not having permission is only warned about
+ fn copy_device(path: &Path, dst: &Path, options: &CopyOptions, counters: &CopyCounters) -> io::Result<()> {
+    let src_path = options.source.join(path);
+    let Some(dest_rel) = dest_rel_path(path, options) else {
+        stripped_away(path, options, counters);
+        return Ok(());
+    };
+    let dest_path = dst.join(dest_rel);
+    if options.dry_run {
+        plan_dry_run(format_args!("[DRY RUN] mknod {} -> {}", display_path(&src_path, options).display(), display_path(&dest_path, options).display()),
+            dst, &dest_path, Some(0), options, counters);
+        counters.devices.fetch_add(1, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    match recreate_device(&src_path, &long_path(&dest_path)) {
+        Ok(()) => {
+            counters.devices.fetch_add(1, Ordering::Relaxed);
+            if options.show_files {
+                print_line(format_args!("[DEV] {} -> {}", display_path(&src_path, options).display(), display_path(&dest_path, options).display()));
+            }
+            Ok(())
+        }
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            print_error(format_args!("Warning: no permission to create device {} (needs root), skipped", display_path(&dest_path, options).display()));
+            counters.skipped.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+ }
+
+ //--repair: whether a file has to be copied again. A missing copy is copied as usual, one at the wrong size
+ //(truncated by a crash) is counted as repaired and copied over, one at the right size is left alone. Only
+ //the size is compared, there's no checkpoint to go by
+ fn needs_repair(src: &Path, fs_dest: &Path, dest_path: &Path, options: &CopyOptions, counters: &CopyCounters) -> bool {
+    let Some(existing) = fs::metadata(fs_dest).ok().filter(fs::Metadata::is_file) else {
+        return true;
+    };
+    let size = fs::metadata(src).map(|m| m.len()).unwrap_or(0);
+    if existing.len() == size {
+        counters.repair_intact.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    counters.repaired.fetch_add(1, Ordering::Relaxed);
+    if options.verbose {
+        print_error(format_args!("Repairing {}, it has {} of {}", display_path(dest_path, options).display(),
+            format_bytes(existing.len(), options.si), format_bytes(size, options.si)));
+    }
+    true
+ }
+
+ //Checks the name an entry gets in the destination against the filesystem's limit. A name that's too long is
+ //warned about up front, the copy would only fail on it with a less helpful error, and left out with --skip-too-long
+ //Only the entry's own name is checked, the directories above it were checked on the directory pass. A file's
+ //name is the one --rename gives it, a rule that can't be applied is left for the copy to report
+ fn name_fits(rel_path: &Path, is_file: bool, options: &CopyOptions, counters: &CopyCounters) -> bool {
+    let dest_rel = dest_rel_path(rel_path, options).map(|dest_rel| if is_file { renamed(dest_rel.clone(), options).unwrap_or(dest_rel) } else { dest_rel });
+    let Some(name) = dest_rel.and_then(|dest_rel| dest_rel.file_name().map(OsString::from)) else {
+        return true;
+    };
+    let length = name_length(&name);
+    if length <= options.name_max {
+        return true;
+    }
+
+    print_error(format_args!("Warning: {} has a {} byte name, the destination only allows {}{}", rel_path.display(), length, options.name_max,
+        if options.skip_too_long { ", skipped" } else { " (--skip-too-long leaves such names out)" }));
+    if !options.skip_too_long {
+        return true;
+    }
+    counters.skipped_too_long.fetch_add(1, Ordering::Relaxed);
+    false
+ }
+
+ //Function to find the failed directory (if any) a path lives under
+ fn under_failed_dir(path: &Path, counters: &CopyCounters) -> Option<PathBuf> {
+    let failed = counters.failed_dirs.lock().unwrap();
+    if failed.is_empty() {
+        return None;
+    }
+    path.ancestors().skip(1).find(|dir| failed.contains_key(*dir)).map(Path::to_path_buf)
+ }
+
+ //Size of a walked file for progress, symlinks are followed since their target is what gets copied
+ //Zero byte files (.gitkeep and friends) go through the same copy as everything else and are always
+ //created, only --exclude-empty leaves them out
+ fn entry_size(entry: &DirEntry) -> u64 {
+    fs::metadata(entry.path()).map(|m| m.len()).unwrap_or(0)
+ }
+
+ pub fn get_copy_stats(counters: &CopyCounters) -> CopyStats {
     CopyStats {
-        files: files
-            .iter()
-            .filter(|e| !is_excluded(e, &options.excludes))
-            .count() as u64,
-        dirs: dirs.len() as u64
+        files: counters.files.load(Ordering::Relaxed),
+        dirs: counters.dirs.load(Ordering::Relaxed),
+        symlinks: counters.symlinks.load(Ordering::Relaxed),
+        timed_out: counters.timed_out.load(Ordering::Relaxed),
+        skipped: counters.skipped.load(Ordering::Relaxed),
+        bytes: counters.bytes.load(Ordering::Relaxed),
+        errors: counters.errors.load(Ordering::Relaxed),
+        ignored_errors: counters.ignored_errors.load(Ordering::Relaxed),
+        collisions: counters.collisions.load(Ordering::Relaxed),
+        hidden_skipped: counters.hidden_skipped.load(Ordering::Relaxed),
+        symlink_loops: counters.symlink_loops.load(Ordering::Relaxed),
+        unreadable: counters.unreadable.load(Ordering::Relaxed),
+        verified: counters.verified.load(Ordering::Relaxed),
+        verify_mismatches: counters.verify_mismatches.load(Ordering::Relaxed),
+        deleted: counters.deleted.load(Ordering::Relaxed),
+        trashed: counters.trashed.load(Ordering::Relaxed),
+        resumed: counters.resumed.load(Ordering::Relaxed),
+        devices: counters.devices.load(Ordering::Relaxed),
+        deduplicated: counters.deduplicated.load(Ordering::Relaxed),
+        size_mismatches: counters.size_mismatches.load(Ordering::Relaxed),
+        forced_overwrites: counters.forced_overwrites.load(Ordering::Relaxed),
+        kept_newer: counters.kept_newer.load(Ordering::Relaxed),
+        kept_nonempty: counters.kept_nonempty.load(Ordering::Relaxed),
+        linked_previous: counters.linked_previous.load(Ordering::Relaxed),
+        renamed_conflicts: counters.renamed_conflicts.load(Ordering::Relaxed),
+        repaired: counters.repaired.load(Ordering::Relaxed),
+        repair_intact: counters.repair_intact.load(Ordering::Relaxed),
+        copy_millis: counters.copy_millis.load(Ordering::Relaxed),
+        peak_rate: counters.peak_rate.load(Ordering::Relaxed),
+        skipped_empty: counters.skipped_empty.load(Ordering::Relaxed),
+        skipped_symlinks: counters.skipped_symlinks.load(Ordering::Relaxed),
+        skipped_not_executable: counters.skipped_not_executable.load(Ordering::Relaxed),
+        skipped_recent: counters.skipped_recent.load(Ordering::Relaxed),
+        skipped_too_long: counters.skipped_too_long.load(Ordering::Relaxed),
+        permission_failures: counters.permission_failures.load(Ordering::Relaxed),
+        deferred: counters.deferred.load(Ordering::Relaxed),
+        deferred_bytes: counters.deferred_bytes.load(Ordering::Relaxed),
+        failed_dirs: {
+            let mut failed: Vec<_> = counters.failed_dirs.lock().unwrap().drain().collect();
+            failed.sort();
+            failed
+        },
+        exclude_hits: {
+            let mut hits: Vec<_> = counters.exclude_hits.lock().unwrap().drain().collect();
+            hits.sort();
+            hits
+        },
     }
  }
 
- fn create_directories(path: &Path, dst: &Path, options: &CopyOptions, pb: &ProgressBar) -> Result<(), Box<dyn std::error::Error>>{
+ fn create_directories(path: &Path, dst: &Path, options: &CopyOptions, counters: &CopyCounters) -> Result<(), Box<dyn std::error::Error>>{
     let rel_path = path;
-    let dest_path = dst.join(rel_path);
+
+    //Never copy the destination into itself when it lives inside the source
+    if let Some(nested) = &options.nested_dest {
+        let real_path = fs::canonicalize(options.source.join(path))?;
+        if real_path.starts_with(nested) {
+            if &real_path == nested {
+                print_error(format_args!("Warning: skipping {} because it is the destination", normalize_path(&options.source.join(path)).display()));
+            }
+            return Ok(());
+        }
+    }
+
+    let Some(dest_rel) = dest_rel_path(rel_path, options) else {
+        return Ok(());
+    };
+    let dest_path = dst.join(dest_rel);
     if options.dry_run {
-        println!("[DRY RUN] mkdir {}", dest_path.display());
+        plan_dry_run(format_args!("[DRY RUN] mkdir {}", display_path(&dest_path, options).display()), dst, &dest_path, None, options, counters);
+        //--strict checks the real copy could actually write here
+        if options.strict {
+            probe_writable(&long_path(&dest_path))?;
+        }
     } else {
         //Create directories
-        fs::create_dir_all(&dest_path)?;
+        let fs_dest = long_path(&dest_path);
+        let existed = fs_dest.is_dir();
+        //Copying files in changes a directory's times, so they're set at the end: the ones it had for a directory that
+        //was already there with --keep-dest-dir-times, the source's for a new one with --preserve timestamps
+        let times = if existed && options.keep_dest_dir_times {
+            dir_times(&fs_dest)
+        } else if !existed && options.preserve_times {
+            dir_times(&long_path(&options.source.join(path)))
+        } else {
+            None
+        };
+        create_dir_all_racy(&fs_dest)?;
+        if let Some(times) = times {
+            counters.dir_times.lock().unwrap().push((fs_dest.clone(), times));
+        }
 
-        //Ensure directory permissions are copied
-        copy_permissions(&path, &dest_path);
+        //Directory permissions are copied once everything is in place, a read-only source directory copied
+        //straight away would stop its own files being written. Until then it's kept writable for us
+        //--keep-dest-dir-perms leaves ones that were already there alone
+        if options.permissions && !(existed && options.keep_dest_dir_perms) {
+            keep_writable(&fs_dest)?;
+            counters.dir_permissions.lock().unwrap().push((long_path(&options.source.join(path)), fs_dest.clone()));
+        }
         if options.show_dirs {
-            println!("[DIR] {}", dest_path.display());
+            print_line(format_args!("[DIR] {}", display_path(&dest_path, options).display()));
         }
     }
-    pb.inc(1);
+    //Counted once it's really there (or would be), so filtering and failures never inflate the summary
+    counters.dirs.fetch_add(1, Ordering::Relaxed);
     Ok(())
  }
 
- fn create_files(path: &Path, dst: &Path, options: &CopyOptions, pb: &ProgressBar)  -> Result<(), Box<dyn std::error::Error>>{
+ //Function to check a directory could be written to without writing anything real, a marker file is created
+ //and removed again. A directory that doesn't exist yet is checked through the closest parent that does
+ fn probe_writable(dir: &Path) -> io::Result<()> {
+    //Removes the marker however the probe ends
+    struct Marker(PathBuf);
+    impl Drop for Marker {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    let existing = dir.ancestors().find(|p| p.exists()).unwrap_or(Path::new("."));
+    if !existing.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{} is in the way and isn't a directory", existing.display())));
+    }
+
+    static PROBES: AtomicU64 = AtomicU64::new(0);
+    let marker = Marker(existing.join(format!(".rcpy-probe-{}-{}", std::process::id(), PROBES.fetch_add(1, Ordering::Relaxed))));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&marker.0)
+        .map_err(|e| io::Error::new(e.kind(), format!("{} isn't writable: {}", existing.display(), e)))?;
+    Ok(())
+ }
+
+ fn create_files(path: &Path, dst: &Path, options: &CopyOptions, counters: &CopyCounters)  -> Result<(), Box<dyn std::error::Error>>{
+    //Structure only mode never writes file contents, just count what we left out
+    if options.structure_only {
+        counters.skipped.fetch_add(1, Ordering::Relaxed);
+        return Ok(());
+    }
+
     let rel_path = path;
-    let src_path = options.source.join(path); // full absolute source path
+    let src_path = options.source.join(path); //Source path as the user typed it, used for output
     let real_path = fs::canonicalize(&src_path)?; //Need to do this for Windows long paths
 
-    let dest_path = dst.join(rel_path);
-    if options.dry_run {
-        println!("[DRY RUN] {} -> {}",real_path.display(), dest_path.display());
+    //Files already sitting in a destination nested inside the source are not copied again
+    if options.nested_dest.as_ref().is_some_and(|nested| real_path.starts_with(nested)) {
+        return Ok(());
+    }
+
+    //Name transforms can map two sources onto the same destination, sort that out before writing
+    //Callers already skipped (and warned about) files --strip-components leaves nothing of
+    let Some(dest_rel) = dest_rel_path(rel_path, options) else {
+        return Ok(());
+    };
+    let dest_rel = renamed(dest_rel, options)?;
+    let dest_rel = if options.lowercase_dest || options.strip_components > 0 || !options.renames.is_empty() {
+        claim_dest_path(rel_path, dest_rel, options, counters)?
+    } else {
+        dest_rel
+    };
+
+    let dest_rel = if options.rename_conflicts { unconflicted_path(&real_path, rel_path, dst, dest_rel, options, counters) } else { dest_rel };
+    let previous = options.link_dest.as_ref().map(|link_dest| link_dest.join(&dest_rel));
+    let dest_path = dst.join(dest_rel);
+    if let Some(veto) = overwrite_veto(&real_path, &long_path(&dest_path), options) {
+        let kept = match veto {
+            OverwriteVeto::Newer => &counters.kept_newer,
+            OverwriteVeto::NotEmpty => &counters.kept_nonempty,
+        };
+        kept.fetch_add(1, Ordering::Relaxed);
+        if options.verbose {
+            print_error(format_args!("Keeping {}, {}", display_path(&dest_path, options).display(), veto.reason()));
+        }
+        return Ok(());
+    }
+    if options.repair && !needs_repair(&real_path, &long_path(&dest_path), &dest_path, options, counters) {
+        return Ok(());
+    }
+    //Unchanged since the --link-dest backup, the new backup shares that file instead of getting another copy
+    let previous = previous.filter(|previous| unchanged_since(&src_path, &real_path, &long_path(previous), options));
+    if options.dry_run && let Some(previous) = &previous {
+        counters.linked_previous.fetch_add(1, Ordering::Relaxed);
+        count_copied(&src_path, counters);
+        plan_dry_run(format_args!("[DRY RUN] link {} -> {}", display_path(previous, options).display(), display_path(&dest_path, options).display()),
+            dst, &dest_path, Some(0), options, counters);
+    } else if options.dry_run {
+        let size = fs::metadata(&real_path).map(|m| m.len()).unwrap_or(0);
+        counters.bytes.fetch_add(size, Ordering::Relaxed);
+        count_copied(&src_path, counters);
+        plan_dry_run(format_args!("[DRY RUN] {} -> {} ({})", display_path(&src_path, options).display(), display_path(&dest_path, options).display(), format_bytes(size, options.si)),
+            dst, &dest_path, Some(size), options, counters);
     } else {
-        //File Copy Happens Here
-        if let Err(err) = fs::copy(&real_path, &dest_path) {
-            eprintln!("Failed to copy {}: {}", path.display(), err); 
+        //File Copy Happens Here, the prefixed path is only for the filesystem calls, output shows dest_path
+        let fs_dest = long_path(&dest_path);
+        //With --dereference a target reached through several links is only copied once, the rest are hard links to that copy
+        //--preserve links does the same for files hard linked together in the source
+        if (options.dereference && link_to_earlier_copy(&real_path, &fs_dest, counters))
+            || (options.preserve_links && link_to_earlier_inode(&real_path, &fs_dest, counters)) {
+            count_copied(&src_path, counters);
+            if options.show_files {
+                print_line(format_args!("[LINK] {} -> {}", display_path(&src_path, options).display(), display_path(&dest_path, options).display()));
+            }
+            return Ok(());
+        }
+        //The link shares the previous backup's file, attributes aren't touched since that would change the old backup too
+        if let Some(previous) = &previous
+            && link_previous(&long_path(previous), &fs_dest) {
+            counters.linked_previous.fetch_add(1, Ordering::Relaxed);
+            if let Some(checkpoint) = &options.checkpoint {
+                checkpoint.record(rel_path);
+            }
+            count_copied(&src_path, counters);
+            if options.show_files {
+                print_line(format_args!("[LINK] {} -> {}", display_path(previous, options).display(), display_path(&dest_path, options).display()));
+            }
+            return Ok(());
+        }
+        let copied = copy_file_forced(&real_path, &fs_dest, options, counters).and_then(|bytes| {
+            if options.verify_size {
+                check_copied_size(&real_path, &fs_dest, counters)?;
+            }
+            Ok(bytes)
+        });
+        if let Ok(bytes) = copied {
+            counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+        if let Err(err) = copied {
+            if err.kind() == io::ErrorKind::TimedOut {
+                counters.timed_out.fetch_add(1, Ordering::Relaxed);
+            }
+            report_failure(options, counters, path, "Failed to copy", &err);
         } else {
-           copy_permissions(&real_path, &dest_path);
+            if options.preserve_crtime {
+                copy_creation_time(&real_path, &fs_dest);
+            }
+            if options.permissions {
+                copy_permissions(&real_path, &fs_dest, options.chmod.as_ref(), options, counters);
+            }
+            //Checked in their own phase once everything is copied
+            if should_verify(rel_path, options) {
+                counters.to_verify.lock().unwrap().push((real_path.clone(), fs_dest.clone(), rel_path.to_path_buf()));
+            }
+            if options.preserve_resource_fork {
+                copy_resource_fork(&real_path, &fs_dest);
+            }
+            copy_attributes(&real_path, &fs_dest, options);
+            //Flags go last, once a file is immutable nothing else can be changed on it
+            if options.preserve_flags {
+                copy_file_flags(&real_path, &fs_dest);
+            }
+            if options.fsync
+                && let Some(parent) = fs_dest.parent() {
+                counters.to_sync.lock().unwrap().insert(parent.to_path_buf());
+            }
+            if let Some(checkpoint) = &options.checkpoint {
+                checkpoint.record(rel_path);
+            }
+            count_copied(&src_path, counters);
             //Show output of what file gets copied if we should
             if options.show_files 
             {
-                println!("[FILE] {} -> {}",real_path.display(), dest_path.display());
+                print_line(format_args!("[FILE] {} -> {}", display_path(&src_path, options).display(), display_path(&dest_path, options).display()));
             }
         }   
     }
-    pb.inc(1);
     Ok(())
  }
 
- fn copy_permissions(path: &Path, dest_path: &Path) {
-    if let Ok(metadata) = fs::metadata(&path) {
-        let perms = metadata.permissions(); 
-        if let Err(err) = fs::set_permissions(&dest_path, perms) {
-            eprintln!("Failed to write permissions for {}: {}", dest_path.display(), err);
+ //Files are only counted once they have been copied (or linked, or would be in a dry run), failures and
+ //anything skipped along the way stay out of the summary's totals
+ //Symlinks get resolved by canonicalize above, they are counted on their own so the summary shows it
+ fn count_copied(src_path: &Path, counters: &CopyCounters) {
+    if src_path.is_symlink() {
+        counters.symlinks.fetch_add(1, Ordering::Relaxed);
+    } else {
+        counters.files.fetch_add(1, Ordering::Relaxed);
+    }
+ }
+
+ //A dry run prints each planned operation as it goes, with --tree they're kept (relative to the destination,
+ //with the size of files) and drawn as one tree at the end instead
+ fn plan_dry_run(line: std::fmt::Arguments, dst: &Path, dest_path: &Path, size: Option<u64>, options: &CopyOptions, counters: &CopyCounters) {
+    if !options.tree {
+        print_line(line);
+        return;
+    }
+    let rel = dest_path.strip_prefix(dst).unwrap_or(dest_path);
+    if !rel.as_os_str().is_empty() {
+        counters.planned.lock().unwrap().push((rel.to_path_buf(), size));
+    }
+ }
+
+ //Draws the --tree plan like the tree command, directories end in a slash and files show their size
+ //Workers plan in any order so it all has to be collected and sorted first, only paths and sizes are kept
+ fn print_planned_tree(dst: &Path, options: &CopyOptions, counters: &CopyCounters) {
+    if !options.tree {
+        return;
+    }
+    let mut planned = std::mem::take(&mut *counters.planned.lock().unwrap());
+    planned.sort();
+
+    //An entry is the last of its siblings when nothing else at its depth comes after it before its parent ends,
+    //easiest to see walking backwards
+    let mut last = vec![false; planned.len()];
+    let mut seen_at_depth: Vec<bool> = Vec::new();
+    for (i, (path, _)) in planned.iter().enumerate().rev() {
+        let depth = path.components().count();
+        seen_at_depth.truncate(depth + 1);
+        seen_at_depth.resize(depth + 1, false);
+        last[i] = !seen_at_depth[depth];
+        seen_at_depth[depth] = true;
+    }
+
+    println!("{}/", display_path(dst, options).display());
+    //Whether the ancestor at each depth still has siblings to come, those keep their line going
+    let mut open_at_depth: Vec<bool> = Vec::new();
+    for ((path, size), is_last) in planned.iter().zip(last) {
+        let depth = path.components().count();
+        let mut line: String = (1..depth)
+            .map(|d| if open_at_depth.get(d).copied().unwrap_or(false) { "│   " } else { "    " })
+            .collect();
+        line.push_str(if is_last { "└── " } else { "├── " });
+        line.push_str(&path.file_name().unwrap_or_default().to_string_lossy());
+        match size {
+            Some(size) => line.push_str(&format!(" ({})", format_bytes(*size, options.si))),
+            None => line.push('/'),
         }
+        println!("{}", line);
+        open_at_depth.truncate(depth);
+        open_at_depth.resize(depth + 1, false);
+        open_at_depth[depth] = !is_last;
+    }
+    println!();
+ }
+
+ //Where a source relative path lands under the destination once name transforms are applied
+ //None when --strip-components leaves nothing of it, the source root itself always maps to the destination
+ fn dest_rel_path(rel_path: &Path, options: &CopyOptions) -> Option<PathBuf> {
+    let components = rel_path.components().skip(options.strip_components);
+    let dest_rel: PathBuf = if options.lowercase_dest {
+        components.map(|c| c.as_os_str().to_string_lossy().to_lowercase()).collect()
+    } else {
+        components.collect()
+    };
+    if dest_rel.as_os_str().is_empty() && !rel_path.as_os_str().is_empty() {
+        return None;
     }
+    Some(dest_rel)
+ }
+
+ //Runs a file's name through the --rename rules, directories are never renamed so the tree keeps its shape
+ fn renamed(dest_rel: PathBuf, options: &CopyOptions) -> io::Result<PathBuf> {
+    if options.renames.is_empty() {
+        return Ok(dest_rel);
+    }
+    let Some(name) = dest_rel.file_name() else {
+        return Ok(dest_rel);
+    };
+    let original = name.to_string_lossy();
+    let name = options.renames.iter().fold(original.to_string(), |name, rule| rule.apply(&name));
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--rename turns {} into {:?}, which isn't a file name", original, name),
+        ));
+    }
+    Ok(dest_rel.with_file_name(name))
+ }
+
+ //Files too shallow for --strip-components are skipped with a warning, like tar does
+ fn stripped_away(rel_path: &Path, options: &CopyOptions, counters: &CopyCounters) {
+    counters.skipped.fetch_add(1, Ordering::Relaxed);
+    print_error(format_args!("Warning: skipping {}, nothing is left of it after --strip-components {}", rel_path.display(), options.strip_components));
+ }
+
+ //Function to catch a copy that came out shorter (or longer) than its source, e.g. a write cut short by a full
+ //disk that still reported success. Only sizes are compared so it costs a stat per file, unlike --verify
+ fn check_copied_size(src: &Path, dest: &Path, counters: &CopyCounters) -> io::Result<()> {
+    let expected = fs::metadata(src)?.len();
+    let actual = fs::metadata(dest)?.len();
+    if expected != actual {
+        counters.size_mismatches.fetch_add(1, Ordering::Relaxed);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("copy is {} bytes but the source is {} (disk full?)", actual, expected),
+        ));
+    }
+    Ok(())
+ }
+
+ //True when real_path was already copied this run and dest is now a hard link to that copy
+ fn link_to_earlier_copy(real_path: &Path, dest: &Path, counters: &CopyCounters) -> bool {
+    link_to_first_copy(&counters.dereferenced, real_path.to_path_buf(), dest, counters)
+ }
+
+ //For --preserve links, true when real_path is another name for a file copied earlier this run and dest is now
+ //a hard link to that copy. Only files with more than one link can have been seen before
+ #[cfg(unix)]
+ fn link_to_earlier_inode(real_path: &Path, dest: &Path, counters: &CopyCounters) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match fs::metadata(real_path) {
+        Ok(metadata) if metadata.nlink() > 1 => link_to_first_copy(&counters.hard_linked, (metadata.dev(), metadata.ino()), dest, counters),
+        _ => false,
+    }
+ }
+
+ #[cfg(not(unix))]
+ fn link_to_earlier_inode(_real_path: &Path, _dest: &Path, _counters: &CopyCounters) -> bool {
+    false
+ }
+
+ //The first file to claim a key copies it, if the link can't be made (another filesystem, or the copy
+ //isn't finished yet with --atomic) the file just gets copied again
+ fn link_to_first_copy<K: Eq + Hash>(copies: &Mutex<HashMap<K, PathBuf>>, key: K, dest: &Path, counters: &CopyCounters) -> bool {
+    let first_copy = {
+        let mut copied = copies.lock().unwrap();
+        match copied.get(&key) {
+            Some(first_copy) => first_copy.clone(),
+            None => {
+                copied.insert(key, dest.to_path_buf());
+                return false;
+            }
+        }
+    };
+    if first_copy == dest {
+        return false;
+    }
+
+    let _ = fs::remove_file(dest);
+    if fs::hard_link(&first_copy, dest).is_ok() {
+        counters.deduplicated.fetch_add(1, Ordering::Relaxed);
+        return true;
+    }
+    false
+ }
+
+ //For --link-dest, true when the previous backup's file has the same contents as the source: same size and
+ //modification time when timestamps are preserved, otherwise same size and hash. A backup made without --preserve
+ //timestamps has copy times, a source edited in the same clock tick would match them.
+ //Symlinks are never linked, they're recreated as usual
+ fn unchanged_since(src_path: &Path, real_path: &Path, previous: &Path, options: &CopyOptions) -> bool {
+    if src_path.is_symlink() {
+        return false;
+    }
+    let (Ok(src), Ok(old)) = (fs::metadata(real_path), fs::symlink_metadata(previous)) else {
+        return false;
+    };
+    if !old.is_file() || src.len() != old.len() {
+        return false;
+    }
+    if options.preserve_times && src.modified().is_ok_and(|modified| old.modified().is_ok_and(|old_modified| old_modified == modified)) {
+        return true;
+    }
+    contents_match(real_path, previous, options.hash).unwrap_or(false)
+ }
+
+ //Hard links dest to the previous backup's file, false when that can't be done (another filesystem, no hard
+ //links there...) and the file should just be copied
+ fn link_previous(previous: &Path, dest: &Path) -> bool {
+    if fs::symlink_metadata(dest).is_ok() && fs::remove_file(dest).is_err() {
+        return false;
+    }
+    fs::hard_link(previous, dest).is_ok()
+ }
+
+ //Claims a transformed destination for one source file, the first file to claim a name wins
+ //(in parallel mode that's whichever gets there first) and later ones fail or get numbered per --collision
+ fn claim_dest_path(rel_path: &Path, dest_rel: PathBuf, options: &CopyOptions, counters: &CopyCounters) -> io::Result<PathBuf> {
+    let mut claimed = counters.claimed.lock().unwrap();
+    let Some(owner) = claimed.get(&dest_rel) else {
+        claimed.insert(dest_rel.clone(), rel_path.to_path_buf());
+        return Ok(dest_rel);
+    };
+
+    counters.collisions.fetch_add(1, Ordering::Relaxed);
+    match options.collision {
+        CollisionPolicy::Fail => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("destination {} is already used by {}", dest_rel.display(), owner.display()),
+        )),
+        CollisionPolicy::Rename => {
+            let renamed = (2..)
+                .map(|n| numbered_path(&dest_rel, n))
+                .find(|candidate| !claimed.contains_key(candidate))
+                .unwrap();
+            print_error(format_args!("Warning: {} collides with {}, writing it as {}", rel_path.display(), owner.display(), renamed.display()));
+            claimed.insert(renamed.clone(), rel_path.to_path_buf());
+            Ok(renamed)
+        }
+    }
+ }
+
+ //With --rename-conflicts a file whose destination holds something else goes to the first numbered name that's
+ //free (or already has the same contents, so a rerun of the merge lands on the same names). Names are claimed
+ //before they're looked at so two workers never settle on the same one
+ fn unconflicted_path(real_path: &Path, rel_path: &Path, dst: &Path, dest_rel: PathBuf, options: &CopyOptions, counters: &CopyCounters) -> PathBuf {
+    if can_take(real_path, &long_path(&dst.join(&dest_rel)), options) {
+        return dest_rel;
+    }
+    let renamed = (2..)
+        .map(|n| numbered_path(&dest_rel, n))
+        .find(|candidate| {
+            let mut claimed = counters.claimed.lock().unwrap();
+            if claimed.contains_key(candidate) {
+                return false;
+            }
+            claimed.insert(candidate.clone(), rel_path.to_path_buf());
+            drop(claimed);
+            can_take(real_path, &long_path(&dst.join(candidate)), options)
+        })
+        .unwrap();
+    counters.renamed_conflicts.fetch_add(1, Ordering::Relaxed);
+    if options.show_files {
+        print_line(format_args!("[RENAME] {} -> {} ({} holds a different file)", rel_path.display(), renamed.display(), dest_rel.display()));
+    }
+    renamed
+ }
+
+ //Whether the file can be written to dest without losing anything, true when there's nothing there or it's the same file contents
+ fn can_take(src: &Path, dest: &Path, options: &CopyOptions) -> bool {
+    match fs::symlink_metadata(dest) {
+        Err(_) => true,
+        Ok(metadata) if metadata.is_file() => contents_match(src, dest, options.hash).unwrap_or(false),
+        Ok(_) => false,
+    }
+ }
+
+ //file.txt -> file (2).txt
+ fn numbered_path(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(name)
+ }
+
+ //Verification phase, runs after the copy with its own bar since hashing a big tree takes a while too
+ fn verify_all(options: &CopyOptions, counters: &CopyCounters, parallel: bool) {
+    let pending = std::mem::take(&mut *counters.to_verify.lock().unwrap());
+    if pending.is_empty() {
+        return;
+    }
+
+    let pb = phase_progress_bar("Verifying...", pending.len() as u64, options);
+    let verify = |(src, dest, rel_path): &(PathBuf, PathBuf, PathBuf)| {
+        verify_copy(src, dest, rel_path, options, counters);
+        pb.inc(1);
+    };
+    if parallel {
+        pending.par_iter().for_each(verify);
+    } else {
+        pending.iter().for_each(verify);
+    }
+    pb.finish_and_clear();
+    set_active_bar(None);
+ }
+
+ //Hashes the source and its copy and reports a mismatch as a failure
+ fn verify_copy(src: &Path, dest: &Path, rel_path: &Path, options: &CopyOptions, counters: &CopyCounters) {
+    counters.verified.fetch_add(1, Ordering::Relaxed);
+    match contents_match(src, dest, options.hash) {
+        Ok(true) => {}
+        Ok(false) => {
+            counters.verify_mismatches.fetch_add(1, Ordering::Relaxed);
+            let err = io::Error::new(io::ErrorKind::InvalidData, "copy doesn't match the source");
+            report_failure(options, counters, rel_path, "Verification failed for", &err);
+        }
+        Err(err) => report_failure(options, counters, rel_path, "Couldn't verify", &err),
+    }
+ }
+
+ //Reports a failed entry, unless it matches --ignore-error in which case it's quietly skipped
+ //Reported failures are counted so the run exits non-zero
+ pub fn report_failure(options: &CopyOptions, counters: &CopyCounters, path: &Path, context: &str, err: &(dyn std::error::Error + 'static)) {
+    if is_error_ignored(options, path) {
+        counters.ignored_errors.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    counters.errors.fetch_add(1, Ordering::Relaxed);
+    //--quiet-errors still counts (and logs) the failure, it just doesn't print it
+    if !options.quiet_errors {
+        print_error(format_args!("{} {}: {}", context, path.display(), err));
+    }
+    log_failure(options, path, err);
+ }
+
+ //Function to check a failed path against the --ignore-error globs, both the path relative
+ //to the source and the full source path are tried
+ fn is_error_ignored(options: &CopyOptions, path: &Path) -> bool {
+    if options.ignore_errors.is_empty() {
+        return false;
+    }
+    let full = options.source.join(path);
+    options.ignore_errors.iter().any(|pattern| pattern.matches_path(path) || pattern.matches_path(&full))
+ }
+
+ //Records a failure in the --error-log file if one was asked for
+ fn log_failure(options: &CopyOptions, path: &Path, err: &(dyn std::error::Error + 'static)) {
+    if let Some(log) = &options.error_log {
+        let kind = err.downcast_ref::<io::Error>().map(|e| e.kind()).unwrap_or(io::ErrorKind::Other);
+        log.record(path, kind, &err.to_string());
+    }
+ }
+
+ //Copies the contents of one file, when --atomic is set the data is written to a temp file first
+ //and renamed over the destination so readers never see a half written file
+ //With --overwrite-readonly a copy refused because the destination is read-only gets one more try
+ //once that file is out of the way, the same as cp -f
+ fn copy_file_forced(src: &Path, dest: &Path, options: &CopyOptions, counters: &CopyCounters) -> io::Result<u64> {
+    match copy_file(src, dest, options) {
+        Err(err) if options.overwrite_readonly
+            && err.kind() == io::ErrorKind::PermissionDenied
+            && fs::symlink_metadata(dest).is_ok_and(|m| m.permissions().readonly()) => {
+            remove_readonly(dest).map_err(|e| io::Error::new(e.kind(), format!("{} (removing the read-only destination: {})", err, e)))?;
+            let bytes = copy_file(src, dest, options)?;
+            counters.forced_overwrites.fetch_add(1, Ordering::Relaxed);
+            Ok(bytes)
+        }
+        result => result,
+    }
+ }
+
+ //Why an existing destination file has to be left alone, the checks that win over every other overwrite setting
+ #[derive(Debug, Clone, Copy)]
+ enum OverwriteVeto {
+    Newer, //--no-clobber-newer
+    NotEmpty, //--clobber-empty-only
+ }
+
+ impl OverwriteVeto {
+    fn reason(self) -> &'static str {
+        match self {
+            OverwriteVeto::Newer => "it's newer than the source",
+            OverwriteVeto::NotEmpty => "it isn't empty",
+        }
+    }
+
+    fn flag(self) -> &'static str {
+        match self {
+            OverwriteVeto::Newer => "--no-clobber-newer",
+            OverwriteVeto::NotEmpty => "--clobber-empty-only",
+        }
+    }
+ }
+
+ //Function to decide if copying over dest is allowed, a destination that doesn't exist yet can always be written
+ fn overwrite_veto(src: &Path, dest: &Path, options: &CopyOptions) -> Option<OverwriteVeto> {
+    if !options.no_clobber_newer && !options.clobber_empty_only {
+        return None;
+    }
+    let metadata = fs::metadata(dest).ok()?;
+    if options.clobber_empty_only && metadata.len() > 0 {
+        return Some(OverwriteVeto::NotEmpty);
+    }
+    if options.no_clobber_newer && dest_is_newer(src, dest) {
+        return Some(OverwriteVeto::Newer);
+    }
+    None
+ }
+
+ //For --no-clobber-newer, a destination that's missing or whose time can't be read isn't newer
+ fn dest_is_newer(src: &Path, dest: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified());
+    match (modified(src), modified(dest)) {
+        (Ok(src_time), Ok(dest_time)) => dest_time > src_time,
+        _ => false,
+    }
+ }
+
+ //Windows won't delete a file with the read-only attribute, on Unix only the directory's permissions matter
+ fn remove_readonly(path: &Path) -> io::Result<()> {
+    if cfg!(windows) {
+        let mut perms = fs::symlink_metadata(path)?.permissions();
+        //Only reached on Windows, where this just clears the attribute rather than making it world writable
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(path, perms)?;
+    }
+    fs::remove_file(path)
+ }
+
+ fn copy_file(src: &Path, dest: &Path, options: &CopyOptions) -> io::Result<u64> {
+    if !options.atomic {
+        return copy_contents(src, dest, options);
+    }
+
+    let tmp = temp_path_for(dest, options.temp_dir.as_deref());
+    let result = copy_contents(src, &tmp, options).and_then(|bytes| {
+        fs::rename(&tmp, dest)?;
+        Ok(bytes)
+    });
+
+    //Never leave stray temp files behind if something went wrong
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+ }
+
+ //Picks how to move the bytes, fs::copy can't be interrupted or append so those need our own loop
+ fn copy_contents(src: &Path, dest: &Path, options: &CopyOptions) -> io::Result<u64> {
+    if options.timeout.is_some() || options.append {
+        copy_streaming(src, dest, options.timeout, options.append, options.fsync, streaming_buffer_size(dest, options))
+    } else if options.data_only {
+        //fs::copy sets the source's permission bits on the copy, a new file from File::create just gets the defaults
+        let mut reader = fs::File::open(src)?;
+        let mut writer = fs::File::create(dest)?;
+        let bytes = io::copy(&mut reader, &mut writer)?;
+        if options.fsync {
+            writer.sync_all()?;
+        }
+        Ok(bytes)
+    } else {
+        let bytes = fs::copy(src, dest)?;
+        //fs::copy doesn't hand us its handle, so the copy is opened again just to flush it
+        if options.fsync {
+            sync_file(dest)?;
+        }
+        Ok(bytes)
+    }
+ }
+
+ //Buffer for the streaming copy. --buffer-size is used as given, otherwise the default is rounded up to a whole
+ //number of the destination's preferred IO block size so every full write covers whole blocks. 128 KiB already
+ //is for the usual 4 KiB blocks, it's filesystems reporting big or odd sizes (network mounts, some RAID setups)
+ //where it matters. When the block size can't be read the default is used as is
+ fn streaming_buffer_size(dest: &Path, options: &CopyOptions) -> usize {
+    if let Some(size) = options.buffer_size {
+        return size;
+    }
+    match preferred_block_size(dest) {
+        Some(block) if block <= MAX_ALIGNED_BUFFER => {
+            let block = block as usize;
+            COPY_BUFFER_SIZE.div_ceil(block) * block
+        }
+        _ => COPY_BUFFER_SIZE,
+    }
+ }
+
+ //Copies in chunks, giving up once the timeout (if any) has passed. On failure the destination is put
+ //back how it was: removed when we created it, truncated to its old length when appending
+ //NOTE: a single read that blocks forever can't be interrupted, we can only check between chunks
+ fn copy_streaming(src: &Path, dest: &Path, timeout: Option<Duration>, append: bool, fsync: bool, buffer_size: usize) -> io::Result<u64> {
+    let started = Instant::now();
+    let mut reader = fs::File::open(src)?;
+    let mut writer = if append {
+        fs::OpenOptions::new().append(true).create(true).open(dest)?
+    } else {
+        fs::File::create(dest)?
+    };
+    let original_len = writer.metadata()?.len();
+    let mut buf = vec![0u8; buffer_size];
+    let mut total = 0u64;
+
+    let result = loop {
+        if let Some(timeout) = timeout
+            && started.elapsed() > timeout {
+            break Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {:.2?}", timeout),
+            ));
+        }
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break Ok(total),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        };
+        if let Err(e) = writer.write_all(&buf[..read]) {
+            break Err(e);
+        }
+        total += read as u64;
+    };
+    //--fsync flushes through the handle we wrote with, before it's closed
+    let result = result.and_then(|total| {
+        if fsync {
+            writer.sync_all()?;
+        }
+        Ok(total)
+    });
+
+    if result.is_err() {
+        if append {
+            let _ = writer.set_len(original_len);
+        } else {
+            drop(writer);
+            let _ = fs::remove_file(dest);
+        }
+    }
+    result
+ }
+
+ //Windows can only flush a handle that's allowed to write
+ #[cfg(windows)]
+ fn sync_file(path: &Path) -> io::Result<()> {
+    fs::OpenOptions::new().write(true).open(path)?.sync_all()
+ }
+
+ #[cfg(not(windows))]
+ fn sync_file(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()
+ }
+
+ fn dir_times(dir: &Path) -> Option<fs::FileTimes> {
+    let metadata = fs::metadata(dir).ok()?;
+    Some(fs::FileTimes::new().set_modified(metadata.modified().ok()?).set_accessed(metadata.accessed().ok()?))
+ }
+
+ //Function to set directory times once nothing else writes into them. Before their permissions, a directory
+ //that ends up unreadable couldn't be opened to set them
+ fn apply_directory_times(counters: &CopyCounters) {
+    let dirs = std::mem::take(&mut *counters.dir_times.lock().unwrap());
+    for (dir, times) in &dirs {
+        if let Err(err) = open_for_times(dir).and_then(|dir| dir.set_times(*times)) {
+            print_error(format_args!("Failed to set times for {}: {}", dir.display(), err));
+        }
+    }
+ }
+
+ //Function to give copied directories their real permissions, the last phase of a copy. Deepest first, so a
+ //parent that ends up without write or search permission doesn't get in the way of the ones below it
+ fn apply_directory_permissions(options: &CopyOptions, counters: &CopyCounters) {
+    let mut dirs = std::mem::take(&mut *counters.dir_permissions.lock().unwrap());
+    if dirs.is_empty() {
+        return;
+    }
+    dirs.sort_by_key(|(_, dest)| std::cmp::Reverse(dest.components().count()));
+    for (src, dest) in &dirs {
+        copy_permissions(src, dest, options.chmod_dir.as_ref(), options, counters);
+    }
+ }
+
+ //Owner read/write/search on a directory we're about to fill, whatever the umask or an earlier run left it with
+ #[cfg(unix)]
+ fn keep_writable(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut perms = fs::metadata(dir)?.permissions();
+    if perms.mode() & 0o700 != 0o700 {
+        perms.set_mode(perms.mode() | 0o700);
+        fs::set_permissions(dir, perms)?;
+    }
+    Ok(())
+ }
+
+ //A read-only attribute on a Windows directory doesn't stop anything being written into it
+ #[cfg(not(unix))]
+ fn keep_writable(_dir: &Path) -> io::Result<()> {
+    Ok(())
+ }
+
+ //Function to fsync every directory a file was copied into for --fsync, without it a new file's directory entry
+ //can still be lost in a power cut even though its data made it. Done once per directory after the copy
+ fn sync_directories(options: &CopyOptions, counters: &CopyCounters) {
+    let dirs = std::mem::take(&mut *counters.to_sync.lock().unwrap());
+    if dirs.is_empty() {
+        return;
+    }
+    let pb = phase_progress_bar("Syncing...", dirs.len() as u64, options);
+    for dir in &dirs {
+        if let Err(err) = sync_directory(dir) {
+            print_error(format_args!("Failed to fsync directory {}: {}", dir.display(), err));
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    set_active_bar(None);
+ }
+
+ #[cfg(unix)]
+ fn sync_directory(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+ }
+
+ //Directories can't be opened like files on Windows and NTFS journals the entries anyway
+ #[cfg(not(unix))]
+ fn sync_directory(_dir: &Path) -> io::Result<()> {
+    Ok(())
+ }
+
+ //Builds a unique hidden temp file name next to the destination (or inside --temp-dir)
+ fn temp_path_for(dest: &Path, temp_dir: Option<&Path>) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let name = dest.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_name = format!(".{}.rcpy-{}-{}.tmp", name, std::process::id(), unique);
+
+    match temp_dir {
+        Some(dir) => dir.join(tmp_name),
+        None => dest.with_file_name(tmp_name),
+    }
+ }
+
+ //create_dir_all that tolerates other workers creating the same directories at the same time
+ //AlreadyExists counts as success as long as a directory is there, and transient NotFound
+ //errors (a parent being created underneath us) get retried a few times
+ pub fn create_dir_all_racy(path: &Path) -> io::Result<()> {
+    const MAX_ATTEMPTS: u32 = 5;
+    let mut attempt = 0;
+    loop {
+        match fs::create_dir_all(path) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && path.is_dir() => return Ok(()),
+            Err(e) if attempt + 1 < MAX_ATTEMPTS
+                && matches!(e.kind(), io::ErrorKind::AlreadyExists | io::ErrorKind::NotFound) => {
+                attempt += 1;
+                std::thread::yield_now();
+            }
+            Err(e) => return Err(e),
+        }
+    }
+ }
+
+ //Owner, extended attributes and then times from --preserve, times last since the others can touch the file
+ fn copy_attributes(path: &Path, dest_path: &Path, options: &CopyOptions) {
+    if options.preserve_owner {
+        copy_ownership(path, dest_path);
+    }
+    if options.preserve_xattrs {
+        copy_xattrs(path, dest_path);
+    }
+    if options.preserve_times
+        && let Err(err) = set_file_times(path, dest_path) {
+        print_error(format_args!("Failed to set times for {}: {}", dest_path.display(), err));
+    }
+ }
+
+ //Modification and access times, the copy may already be read-only so it's only opened as far as needed to set them
+ fn set_file_times(path: &Path, dest_path: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    let times = fs::FileTimes::new().set_modified(metadata.modified()?).set_accessed(metadata.accessed()?);
+    open_for_times(dest_path)?.set_times(times)
+ }
+
+ //Backup semantics is what lets Windows open a directory, files don't mind it
+ #[cfg(windows)]
+ fn open_for_times(path: &Path) -> io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    fs::OpenOptions::new().access_mode(FILE_WRITE_ATTRIBUTES).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(path)
+ }
+
+ //futimens only needs us to own the file, a read-only handle is enough
+ #[cfg(not(windows))]
+ fn open_for_times(path: &Path) -> io::Result<fs::File> {
+    fs::File::open(path)
+ }
+
+ //Carries the creation (birth) time over for --preserve-crtime, warns instead of failing the copy
+ fn copy_creation_time(path: &Path, dest_path: &Path) {
+    if let Err(err) = set_creation_time(path, dest_path) {
+        print_error(format_args!("Failed to set creation time for {}: {}", dest_path.display(), err));
+    }
+ }
+
+ //Only Windows (SetFileTime) and macOS (setattrlist) let us set a file's creation time, std wraps both
+ #[cfg(any(windows, target_os = "macos"))]
+ fn set_creation_time(path: &Path, dest_path: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    use std::os::windows::fs::FileTimesExt;
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::FileTimesExt;
+
+    let created = fs::metadata(path)?.created()?;
+    let file = fs::OpenOptions::new().write(true).open(dest_path)?;
+    file.set_times(fs::FileTimes::new().set_created(created))
+ }
+
+ //Everywhere else there is no way to set it, main warns once and turns the option off
+ #[cfg(not(any(windows, target_os = "macos")))]
+ fn set_creation_time(_path: &Path, _dest_path: &Path) -> io::Result<()> {
+    Ok(())
+ }
+
+ //Copies the source's permissions over, or applies --chmod/--chmod-dir instead when given
+ //Failures are only counted, the summary has one line for all of them (a destination that can't hold
+ //permissions would otherwise print one per file). --verbose still prints each one
+ fn copy_permissions(path: &Path, dest_path: &Path, mode: Option<&ModeSpec>, options: &CopyOptions, counters: &CopyCounters) {
+    if let Ok(metadata) = fs::metadata(path) {
+        let perms = match mode {
+            Some(mode) => permissions_from_spec(&metadata, mode),
+            None => full_permissions(&metadata),
+        };
+        if let Err(err) = fs::set_permissions(dest_path, perms) {
+            counters.permission_failures.fetch_add(1, Ordering::Relaxed);
+            if options.verbose {
+                print_error(format_args!("Failed to write permissions for {}: {}", dest_path.display(), err));
+            }
+        }
+    }
+ }
+
+ //On Unix carry the whole mode over, setuid/setgid/sticky included, not just rwx
+ //Setting setuid/setgid can need privileges, if the kernel refuses them it just drops the bits
+ //(or errors, which copy_permissions turns into a warning)
+ #[cfg(unix)]
+ fn full_permissions(metadata: &fs::Metadata) -> fs::Permissions {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    fs::Permissions::from_mode(metadata.mode() & 0o7777)
+ }
+
+ #[cfg(unix)]
+ fn permissions_from_spec(metadata: &fs::Metadata, mode: &ModeSpec) -> fs::Permissions {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    fs::Permissions::from_mode(mode.apply(metadata.mode(), metadata.is_dir()))
+ }
+
+ //Windows only has a read-only flag, so that's all a mode can change there
+ #[cfg(not(unix))]
+ fn permissions_from_spec(metadata: &fs::Metadata, mode: &ModeSpec) -> fs::Permissions {
+    let base = if metadata.permissions().readonly() { 0o555 } else { 0o755 };
+    let mut perms = metadata.permissions();
+    perms.set_readonly(mode.apply(base, metadata.is_dir()) & 0o200 == 0);
+    perms
+ }
+
+ #[cfg(not(unix))]
+ fn full_permissions(metadata: &fs::Metadata) -> fs::Permissions {
+    metadata.permissions()
  }
  
  pub fn copy_single_threaded(
@@ -215,48 +1769,115 @@ pub fn copy_parallel(
      options: &CopyOptions
  ) -> io::Result<CopyStats> {
      
-     //Walker which varies depending on if we are doing recursive copy or not
-     let walker = if options.recursive {
-         WalkDir::new(src)
-     } else {
-         WalkDir::new(src).max_depth(1)
-     };
-     
-     //Get entries
-     let entries: Vec<_> = walker.into_iter().collect::<Result<_, _>>()?;
-        
+     //Counters updated while copying
+     let counters = CopyCounters::default();
+
      //Setup progress bar
-     let pb = ProgressBar::new(entries.len() as u64);
-     pb.set_style(
-         ProgressStyle::default_bar()
-             .template("{bar:40.cyan/blue} {pos}/{len} [{elapsed_precise}]")
-             .unwrap(),
-     );
-
-    let (dirs, files): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.file_type().is_dir());
-     
-        //Loop through all entries
-    for dir in &dirs {
-        let path = dir.path().strip_prefix(src).unwrap();
-        if let Err(err) = create_directories(path, dst, options, &pb) {
-            eprint!("Error Copying Directory: {}", err);
+     let pb = new_progress_bar(options);
+
+     check_file_limit(src, options)?;
+
+     //Directories first, then walk again for the files
+     create_all_directories(src, dst, options, &pb, &counters)?;
+
+     copy_phase(&pb, options, &counters, || {
+         walk_source(src, options, None)
+             .filter_map(Result::ok)
+             .filter(|e| !is_dir_entry(e))
+             .for_each(|entry| {
+                 copy_entry(&entry, src, dst, options, &pb, &counters);
+                 report_progress(&pb, &counters, options);
+             });
+     });
+     finish_progress(&pb, &counters, options);
+     print_planned_tree(dst, options, &counters);
+     verify_all(options, &counters, false);
+     check_manifest(src, dst, options, &counters, false);
+     sync_directories(options, &counters);
+     if options.delete {
+         prune_destination(src, dst, options, &counters);
+     }
+     remove_git_deleted(dst, options, &counters);
+     apply_directory_times(&counters);
+     apply_directory_permissions(options, &counters);
+ 
+     Ok(get_copy_stats(&counters))
+ }
+
+ //Copies every match of a glob source into dst as dst/<match name>, like cp with a shell glob
+ //Each directory is its own walk (so its own progress bar), the stats are added up into one summary
+ //Two matches with the same name would land on the same place, the later one is reported and skipped
+ pub fn copy_matches(sources: &[PathBuf], dst: &Path, options: &mut CopyOptions, single_threaded: bool) -> io::Result<CopyStats> {
+    if !options.dry_run {
+        create_dir_all_racy(dst)?;
+    }
+    let real_dst = canonicalize_partial(dst).ok();
+
+    let mut total = CopyStats::default();
+    let mut claimed: HashMap<OsString, &Path> = HashMap::new();
+    for src in sources {
+        let Some(name) = src.file_name() else { continue };
+        //A pattern like * run from the destination's parent matches the destination itself
+        if real_dst.is_some() && fs::canonicalize(src).ok() == real_dst {
+            print_error(format_args!("Warning: skipping {} because it is the destination", src.display()));
+            continue;
         }
+        if let Some(owner) = claimed.get(name) {
+            total.collisions += 1;
+            let counters = CopyCounters::default();
+            let err = io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is already copied from {}", name.to_string_lossy(), owner.display()));
+            report_failure(options, &counters, src, "Skipping match", &err);
+            total.add(get_copy_stats(&counters));
+            continue;
+        }
+        claimed.insert(name.to_os_string(), src);
+
+        let target = dst.join(name);
+        let stats = if src.is_dir() {
+            options.source = src.clone();
+            options.nested_dest = match (fs::canonicalize(src), canonicalize_partial(&target)) {
+                (Ok(real_src), Ok(real_target)) if real_target.starts_with(&real_src) => Some(real_target),
+                _ => None,
+            };
+            if single_threaded {
+                copy_single_threaded(src, &target, options)?
+            } else {
+                copy_parallel(src, &target, options)?
+            }
+        } else {
+            //A file goes through the same path a walked file does, as name under its parent
+            options.source = src.parent().map(Path::to_path_buf).unwrap_or_default();
+            options.nested_dest = None;
+            copy_matched_file(Path::new(name), dst, options)
+        };
+        total.add(stats);
     }
-    files
-        .iter()
-        .for_each(|entry| {
-            if is_excluded(entry, &options.excludes) {
-                pb.inc(1);
-                return;
+    Ok(total)
+ }
+
+ fn copy_matched_file(name: &Path, dst: &Path, options: &CopyOptions) -> CopyStats {
+    let counters = CopyCounters::default();
+    match skip_reason(&options.source.join(name), options) {
+        None if dest_rel_path(name, options).is_none() => stripped_away(name, options, &counters),
+        None => {
+            let size = fs::metadata(options.source.join(name)).map(|m| m.len()).unwrap_or(0);
+            if !take_budget(size, options, &counters) {
+                return get_copy_stats(&counters);
             }
-            let path = entry.path().strip_prefix(src).unwrap();
-            if let Err(err) = create_files(path, dst, options, &pb) {
-                eprint!("Error Copying File: {}", err);
+            if let Err(err) = create_files(name, dst, options, &counters) {
+                report_failure(options, &counters, name, "Error Copying File", err.as_ref());
             }
-        });
-    finish_progress(&pb);
- 
-     Ok(get_copy_stats(files, dirs, options))
+            verify_all(options, &counters, false);
+            sync_directories(options, &counters);
+        }
+        Some(SkipReason::Empty) => { counters.skipped_empty.fetch_add(1, Ordering::Relaxed); }
+        Some(SkipReason::Symlink) => { counters.skipped_symlinks.fetch_add(1, Ordering::Relaxed); }
+        Some(SkipReason::NotExecutable) => { counters.skipped_not_executable.fetch_add(1, Ordering::Relaxed); }
+        Some(SkipReason::Recent) => { counters.skipped_recent.fetch_add(1, Ordering::Relaxed); }
+        Some(SkipReason::Excluded) => count_exclude_hit(&options.source.join(name), options, &counters),
+        Some(_) => {}
+    }
+    get_copy_stats(&counters)
  }
 
  pub fn run_copy(
@@ -266,25 +1887,102 @@ pub fn copy_parallel(
     options: &CopyOptions,
     start_time: Instant
 ) {
-    if single_threaded {
-        println!("Single Threaded Copying...\n");
-		match copy_single_threaded(&src, &dst, &options) {
-			Ok(stats) => {
-				display_complete(stats, start_time, options.dry_run);
-			} Err(e) => {
-				eprintln!("Error: {}", e);
-				std::process::exit(1);
-			}
-		}
+    let result = if single_threaded {
+        if options.summary == SummaryFormat::Text {
+            println!("Single Threaded Copying...\n");
+        }
+        copy_single_threaded(src, dst, options)
     } else {
-        println!("Multi-Threaded Copying...\n");
-		match copy_parallel(&src, &dst, options) {
-			Ok(stats) => {
-				display_complete(stats, start_time, options.dry_run);
-			} Err(e) => {
-				eprintln!("Error: {}", e);
+        if options.summary == SummaryFormat::Text {
+            println!("Multi-Threaded Copying...\n");
+        }
+        copy_parallel(src, dst, options)
+    };
+    finish_run(result, start_time, options);
+}
+
+ //Prints the summary and picks the exit code, shared by every kind of run
+ pub fn finish_run(result: io::Result<CopyStats>, start_time: Instant, options: &CopyOptions) {
+	match result {
+		Ok(mut stats) => {
+			//An exclude that skipped nothing is most likely a typo (.jpeg for .jpg), --strict-excludes fails the run over it
+			if options.strict_excludes {
+				for pattern in stats.unused_excludes(options) {
+					print_error(format_args!("Error: --exclude {} matched nothing (--strict-excludes)", pattern));
+					stats.errors += 1;
+				}
+			}
+			if let Some(file) = &options.progress_file {
+				file.finish(&stats, start_time.elapsed(), options);
+			}
+			let failed = stats.errors > 0;
+			let finished = stats.deferred == 0;
+			display_complete(stats, start_time, options);
+			//A run stopped by --max-bytes keeps its checkpoint for the next chunk
+			if !failed && finished && let Some(checkpoint) = &options.checkpoint {
+				checkpoint.finish();
+			}
+			//Any failure that wasn't ignored makes the run fail
+			if failed {
 				std::process::exit(1);
 			}
+		} Err(e) => {
+			eprintln!("Error: {}", e);
+			std::process::exit(1);
 		}
+	}
+}
+
+ #[cfg(test)]
+ mod tests {
+    use super::*;
+
+    //Scratch directory for one test, removed again when it's dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("rcpy-unit-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn racing_threads_create_a_shared_subtree_without_errors() {
+        for round in 0..20 {
+            let temp = TempDir::new(&format!("racy-{}", round));
+            let shared = temp.0.join("new").join("shared").join("subtree");
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..32).map(|worker| {
+                    let dir = shared.join(format!("leaf{}", worker % 4)).join("deeper");
+                    scope.spawn(move || {
+                        create_dir_all_racy(&dir)?;
+                        fs::write(dir.join(format!("file{}", worker)), b"copied")
+                    })
+                }).collect();
+                for worker in workers {
+                    worker.join().unwrap().unwrap();
+                }
+            });
+            for worker in 0..32 {
+                assert!(shared.join(format!("leaf{}", worker % 4)).join("deeper").join(format!("file{}", worker)).is_file());
+            }
+        }
+    }
+
+    #[test]
+    fn a_file_in_the_way_is_still_an_error() {
+        let temp = TempDir::new("racy-file");
+        fs::write(temp.0.join("taken"), b"").unwrap();
+        assert!(create_dir_all_racy(&temp.0.join("taken")).is_err());
+        assert!(create_dir_all_racy(&temp.0.join("taken").join("below")).is_err());
+    }
+ }
@@ -0,0 +1,103 @@
+/*****************************************
+    dedupe.rs
+-----------------
+Description: Handles the --dedupe-report
+mode, which walks the source with the
+copy's filters, hashes files that share a
+size and lists the groups of identical
+files. Nothing is copied or changed
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::copy::{is_dir_entry, walk_source};
+use crate::utils::{format_bytes, should_copy, CopyOptions};
+use crate::verify::hash_file;
+
+//Files with the same contents, paths are relative to the source
+struct DuplicateGroup {
+    size: u64,
+    paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    //Every copy past the first could go
+    fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+pub fn run_dedupe_report(src: &Path, options: &CopyOptions) {
+    if !src.is_dir() {
+        eprintln!("Error: --dedupe-report needs a source directory");
+        std::process::exit(1);
+    }
+
+    let groups = find_duplicates(src, options);
+    if groups.is_empty() {
+        println!("No duplicate files in {}", src.display());
+        return;
+    }
+
+    for group in &groups {
+        println!("{} copies of {} ({} wasted):", group.paths.len(), format_bytes(group.size, options.si), format_bytes(group.wasted(), options.si));
+        for path in &group.paths {
+            println!("  {}", path.display());
+        }
+    }
+    let redundant: usize = groups.iter().map(|group| group.paths.len() - 1).sum();
+    let reclaimable: u64 = groups.iter().map(DuplicateGroup::wasted).sum();
+    println!("\n{} group(s) of duplicates, {} redundant file(s), {} reclaimable.", groups.len(), redundant, format_bytes(reclaimable, options.si));
+}
+
+//Only files sharing a size can match, so only those get hashed. Empty files are all the same
+//and take no space, they're left out. Groups come back with the most wasted space first
+fn find_duplicates(src: &Path, options: &CopyOptions) -> Vec<DuplicateGroup> {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for entry in walk_source(src, options, None).filter_map(Result::ok) {
+        //A symlink takes no space of its own, it would only ever be reported as a duplicate of its own target
+        if is_dir_entry(&entry) || entry.path_is_symlink() || !should_copy(entry.path(), options) {
+            continue;
+        }
+        match entry.metadata() {
+            Ok(metadata) if metadata.len() > 0 => by_size.entry(metadata.len()).or_default().push(entry.into_path()),
+            Ok(_) => {}
+            Err(e) => eprintln!("Error reading {}: {}", entry.path().display(), e),
+        }
+    }
+
+    let candidates: Vec<(u64, PathBuf)> = by_size.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+        .collect();
+    let hashed: Vec<((u64, Vec<u8>), PathBuf)> = candidates.into_par_iter()
+        .filter_map(|(size, path)| match hash_file(&path, options.hash) {
+            Ok(hash) => Some(((size, hash), path)),
+            Err(e) => {
+                eprintln!("Error hashing {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect();
+
+    let mut by_contents: HashMap<(u64, Vec<u8>), Vec<PathBuf>> = HashMap::new();
+    for (key, path) in hashed {
+        by_contents.entry(key).or_default().push(path);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_contents.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((size, _), paths)| {
+            let mut paths: Vec<PathBuf> = paths.iter().map(|path| path.strip_prefix(src).unwrap_or(path).to_path_buf()).collect();
+            paths.sort();
+            DuplicateGroup { size, paths }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.paths.cmp(&b.paths)));
+    groups
+}
@@ -0,0 +1,68 @@
+/*****************************************
+    git.rs
+-----------------
+Description: Handles --since-git, which
+asks git for the files changed in the
+source since a commit so only those get
+copied (and, with --since-git-delete,
+deleted ones removed from the destination)
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::utils::path_from_bytes;
+
+//What changed under the source since the commit, paths are relative to the source
+#[derive(Debug, Default)]
+pub struct GitChanges {
+    pub changed: Vec<PathBuf>,
+    pub deleted: Vec<PathBuf>,
+}
+
+//Function to diff the working tree at src against a commit. --relative keeps it to src (even when src is only
+//part of the repository) and makes the paths relative to it, a rename counts as deleting the old path and
+//adding the new one. Files git doesn't track aren't part of a diff so they're never included
+pub fn changed_since(src: &Path, commit: &str) -> Result<GitChanges, String> {
+    let inside = git(src, &["rev-parse", "--is-inside-work-tree"])?;
+    if inside.trim_ascii() != b"true" {
+        return Err(format!("{} isn't inside a git working tree", src.display()));
+    }
+    let diff = git(src, &["diff", "--name-status", "-z", "-M", "--relative", "--end-of-options", commit, "--"])?;
+
+    //-z output is STATUS\0PATH\0, or STATUS\0OLD\0NEW\0 for renames and copies
+    let mut changes = GitChanges::default();
+    let mut fields = diff.split(|b| *b == b'\0').filter(|field| !field.is_empty());
+    while let Some(status) = fields.next() {
+        let mut next_path = || fields.next().map(path_from_bytes).ok_or_else(|| "unexpected end of git diff output".to_string());
+        match status.first() {
+            Some(b'D') => changes.deleted.push(next_path()?),
+            Some(b'R') => {
+                changes.deleted.push(next_path()?);
+                changes.changed.push(next_path()?);
+            }
+            Some(b'C') => {
+                next_path()?;
+                changes.changed.push(next_path()?);
+            }
+            _ => changes.changed.push(next_path()?),
+        }
+    }
+    Ok(changes)
+}
+
+//Runs git in dir, its error output becomes the error when it fails
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("couldn't run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
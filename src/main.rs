@@ -1,10 +1,28 @@
+mod archive;
+mod bench;
+mod config;
+mod dedupe;
+mod git;
+mod manifest;
 mod copy;
+mod platform;
+mod prune;
+mod restore;
+mod verify;
 mod utils;
 
-use std::path::PathBuf;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use archive::{extract_archive, is_archive};
+use bench::run_bench;
+use dedupe::run_dedupe_report;
+use git::changed_since;
+use manifest::{print_changes, read_manifest, scan_source, write_manifest};
+use platform::{allowed_cores, fat_filesystem, lower_io_priority, max_name_length, pin_thread, DEFAULT_NAME_MAX};
+use restore::IncludeManifest;
 use copy::*;
 use utils::*;
+use verify::HashAlgorithm;
 
 
 fn main() {
@@ -12,29 +30,290 @@ fn main() {
 	let matches = get_arg_matches();
 
 	//Setting values based on arguments
-	let src = PathBuf::from(matches.get_one::<String>("source").unwrap());
-	let dst = PathBuf::from(matches.get_one::<String>("destination").unwrap());
+	let source = matches.get_one::<String>("source").unwrap();
+	let src = PathBuf::from(source);
+	let mut dst = matches.get_one::<String>("destination").map(PathBuf::from).unwrap_or_default(); //Only missing with --bench and --dedupe-report
 
 	//Ensure source is not destination!
-	if src == dst {
-		eprint!("Error: Source and destination paths are the same!");
+	if let Err(e) = validate_paths(&src, &dst) {
+		eprintln!("Error: {}", e);
 		std::process::exit(1);
 	}
 
+	//A destination of - streams a single file to stdout, none of the destination handling below applies to it
+	let to_stdout = dst == Path::new("-");
+	if to_stdout && !src.is_file() {
+		eprintln!("Error: copying to stdout (-) needs a single source file, {} isn't one", src.display());
+		std::process::exit(1);
+	}
+
+	//A quoted glob source is expanded here rather than by the shell, every match gets copied into the destination
+	let globbed = match expand_source(source) {
+		Ok(globbed) => globbed,
+		Err(e) => {
+			eprintln!("Error: {}", e);
+			std::process::exit(1);
+		}
+	};
+	if globbed.is_some()
+		&& let Some(flag) = ["append", "bench", "dedupe_report", "base", "diff_manifest", "manifest", "newest"].into_iter().find(|flag| matches.contains_id(flag) && matches.value_source(flag) == Some(clap::parser::ValueSource::CommandLine)) {
+		eprintln!("Error: --{} needs a single source, not a glob", flag.replace('_', "-"));
+		std::process::exit(1);
+	}
+
+	//A source that is a symlink to a directory gets followed unless told otherwise
+	if src.is_symlink() && src.is_dir() && matches.get_flag("no_follow_source") {
+		eprintln!("Error: source {} is a symlink to a directory (drop --no-follow-source to copy what it points to)", src.display());
+		std::process::exit(1);
+	}
+
+	//From here on dir/ is the same source as dir, the symlink check above still saw the slash (link/ means follow it)
+	let src = trim_trailing_separators(&src);
+
+	//A destination that's a symlink is followed, everything is written into what it points to (the link itself is
+	//never replaced, not even by --atomic's rename). --no-follow-dest refuses it instead
+	if !to_stdout && dst.is_symlink() {
+		if matches.get_flag("no_follow_dest") {
+			eprintln!("Error: destination {} is a symlink (drop --no-follow-dest to write into what it points to)", dst.display());
+			std::process::exit(1);
+		}
+		match std::fs::canonicalize(&dst) {
+			Ok(target) => dst = target,
+			Err(e) => {
+				eprintln!("Error: destination {} is a symlink that can't be followed: {}", dst.display(), e);
+				std::process::exit(1);
+			}
+		}
+	}
+
+	//With --into a source directory becomes dst/<source name> instead of merging its contents into dst
+	if matches.get_flag("into") && src.is_dir() {
+		match std::fs::canonicalize(&src).ok().and_then(|real| real.file_name().map(PathBuf::from)) {
+			Some(name) => dst.push(name),
+			None => {
+				eprintln!("Error: can't use --into with a source that has no name ({})", src.display());
+				std::process::exit(1);
+			}
+		}
+	}
+
 	//OPTION VARIABLES
 	let verbose = matches.get_flag("verbose");
 	let quiet = !verbose;
 	let only_files = matches.get_flag("only_files");
 	let only_dirs = matches.get_flag("only_dirs");
 	let non_recursive = matches.get_flag("no_recursive");
-	let single_threaded = matches.get_flag("single_thread");
+	//--ordered needs everything created one at a time, a pool of threads finishes files in any order
+	let ordered = matches.get_flag("ordered");
+	let single_threaded = matches.get_flag("single_thread") || ordered;
 	let dry_run = matches.get_flag("dry_run");
 
 	//The excluded file extensions
 	let excludes: Vec<String> = matches
 		.get_many::<String>("exclude")
 		.map(|vals| vals.map(String::from).collect())
-		.unwrap_or_else(Vec::new);
+		.unwrap_or_default();
+
+	//Reference time for --newer-than-file, read once up front
+	let newer_than = matches.get_one::<String>("newer_than_file").map(|path| {
+		match std::fs::metadata(path).and_then(|m| m.modified()) {
+			Ok(time) => time,
+			Err(e) => {
+				eprintln!("Error: can't read the --newer-than-file reference {}: {}", path, e);
+				std::process::exit(1);
+			}
+		}
+	});
+
+	//Names pruned from the walk entirely
+	let exclude_names: Vec<&'static str> = if matches.get_flag("exclude_vcs") { VCS_NAMES.to_vec() } else { Vec::new() };
+
+	//Appending a whole tree onto another makes no sense, only allow it for a single file
+	let append = matches.get_flag("append");
+	if append && src.is_dir() {
+		eprintln!("Error: --append only works when the source is a single file");
+		std::process::exit(1);
+	}
+
+	//Temp dir for atomic copies has to be on the same filesystem or the rename won't be atomic (or won't work at all)
+	let mut temp_dir = matches.get_one::<String>("temp_dir").map(PathBuf::from);
+	if let Some(dir) = &temp_dir {
+		if !dir.is_dir() {
+			eprintln!("Error: --temp-dir {} is not a directory", dir.display());
+			std::process::exit(1);
+		}
+		if same_filesystem(dir, &dst) == Some(false) {
+			eprintln!("Warning: --temp-dir is on a different filesystem than the destination, using the destination directory instead");
+			temp_dir = None;
+		}
+	}
+
+	//Hard links can't cross filesystems, a --link-dest elsewhere would just copy everything
+	let link_dest = matches.get_one::<String>("link_dest").map(PathBuf::from);
+	if let Some(previous) = &link_dest {
+		if !previous.is_dir() || !src.is_dir() {
+			eprintln!("Error: --link-dest needs a source directory and {} to be a directory (the previous backup)", previous.display());
+			std::process::exit(1);
+		}
+		if same_filesystem(previous, &dst) == Some(false) {
+			eprintln!("Warning: --link-dest is on a different filesystem than the destination, files can't be linked from it and will be copied");
+		}
+	}
+
+	//Paths to copy from --files-from, separated by NUL with --null
+	let null_separated = matches.get_flag("null");
+	let files_from = matches.get_one::<String>("files_from").map(|list| {
+		match FileList::read(Path::new(list), null_separated) {
+			Ok(list) => list,
+			Err(e) => {
+				eprintln!("Error reading --files-from {}: {}", list, e);
+				std::process::exit(1);
+			}
+		}
+	});
+
+	//--since-git is a --files-from list that git makes, files it saw deleted are only kept with --since-git-delete
+	let mut git_deleted = Vec::new();
+	let files_from = match matches.get_one::<String>("since_git") {
+		Some(_) if !src.is_dir() => {
+			eprintln!("Error: --since-git needs a source directory");
+			std::process::exit(1);
+		}
+		Some(commit) => match changed_since(&src, commit) {
+			Ok(changes) => {
+				if matches.get_flag("since_git_delete") {
+					git_deleted = changes.deleted;
+				}
+				Some(FileList::from_paths(&changes.changed))
+			}
+			Err(e) => {
+				eprintln!("Error: --since-git {}: {}", commit, e);
+				std::process::exit(1);
+			}
+		},
+		None => files_from,
+	};
+
+	//--manifest is a --files-from list whose sizes and hashes get checked once the copy is done
+	let manifest = matches.get_one::<String>("manifest").map(|path| {
+		if !src.is_dir() {
+			eprintln!("Error: --manifest needs a source directory");
+			std::process::exit(1);
+		}
+		match IncludeManifest::read(Path::new(path)) {
+			Ok(manifest) => manifest,
+			Err(e) => {
+				eprintln!("Error reading --manifest {}: {}", path, e);
+				std::process::exit(1);
+			}
+		}
+	});
+	let files_from = match &manifest {
+		Some(manifest) => Some(FileList::from_paths(&manifest.paths())),
+		None => files_from,
+	};
+
+	//Failures log, created up front so scripts can count on it existing
+	let error_log = matches.get_one::<String>("error_log").map(|path| {
+		match ErrorLog::create(Path::new(path)) {
+			Ok(log) => log,
+			Err(e) => {
+				eprintln!("Error creating --error-log {}: {}", path, e);
+				std::process::exit(1);
+			}
+		}
+	});
+
+	//Progress snapshots for detached runs, created up front like the error log
+	let progress_file = matches.get_one::<String>("progress_file").map(|path| {
+		match ProgressFile::create(Path::new(path)) {
+			Ok(file) => file,
+			Err(e) => {
+				eprintln!("Error creating --progress-file {}: {}", path, e);
+				std::process::exit(1);
+			}
+		}
+	});
+
+	//Checkpoint of finished files, loaded first with --resume
+	let checkpoint = matches.get_one::<String>("checkpoint").map(|path| {
+		match Checkpoint::open(Path::new(path), matches.get_flag("resume"), dry_run) {
+			Ok(checkpoint) => checkpoint,
+			Err(e) => {
+				eprintln!("Error opening --checkpoint {}: {}", path, e);
+				std::process::exit(1);
+			}
+		}
+	});
+
+	//Globs for failures we don't care about
+	let ignore_errors: Vec<glob::Pattern> = matches
+		.get_many::<String>("ignore_error")
+		.map(|vals| vals.map(|pattern| match glob::Pattern::new(pattern) {
+			Ok(p) => p,
+			Err(e) => {
+				eprintln!("Error: invalid --ignore-error pattern {}: {}", pattern, e);
+				std::process::exit(1);
+			}
+		}).collect())
+		.unwrap_or_default();
+
+	//A zero sized chunk would never copy anything
+	let buffer_size = matches.get_one::<u64>("buffer_size").map(|&size| {
+		if size == 0 {
+			eprintln!("Error: --buffer-size has to be more than 0");
+			std::process::exit(1);
+		}
+		size as usize
+	});
+
+	//--preserve turns on a group of the individual preserve options, mode is always kept anyway
+	let preserve = matches.get_one::<PreserveList>("preserve").copied().unwrap_or_default();
+
+	//Creation times can only be set on Windows and macOS, tell the user once and carry on everywhere else
+	//--preserve timestamps keeps them too where it can, without the warning
+	let preserve_crtime = (matches.get_flag("preserve_crtime") || preserve.timestamps) && cfg!(any(windows, target_os = "macos"));
+	if matches.get_flag("preserve_crtime") && !preserve_crtime {
+		eprintln!("Warning: --preserve-crtime isn't supported on this platform, creation times won't be kept");
+	}
+
+	//Owners and hard links (device and inode) are a Unix thing, extended attributes are only handled on Linux and macOS
+	let preserve_owner = preserve.ownership && cfg!(unix);
+	if preserve.ownership && !preserve.all && !preserve_owner {
+		eprintln!("Warning: --preserve ownership isn't supported on this platform, owners won't be kept");
+	}
+	let preserve_links = preserve.links && cfg!(unix);
+	if preserve.links && !preserve.all && !preserve_links {
+		eprintln!("Warning: --preserve links isn't supported on this platform, hard linked files will be copied separately");
+	}
+	let preserve_xattrs = preserve.xattr && cfg!(any(target_os = "linux", target_os = "macos"));
+	if preserve.xattr && !preserve.all && !preserve_xattrs {
+		eprintln!("Warning: --preserve xattr isn't supported on this platform, extended attributes won't be kept");
+	}
+
+	//Resource forks are a macOS thing, same deal
+	let preserve_resource_fork = matches.get_flag("preserve_resource_fork") && cfg!(target_os = "macos");
+	if matches.get_flag("preserve_resource_fork") && !preserve_resource_fork {
+		eprintln!("Warning: --preserve-resource-fork only works on macOS, resource forks won't be copied");
+	}
+
+	//Device nodes only exist on Unix
+	let devices = matches.get_flag("devices") && cfg!(unix);
+	if matches.get_flag("devices") && !devices {
+		eprintln!("Warning: --devices only works on Unix, device files won't be recreated");
+	}
+
+	//If the destination is inside the source the walk will find it, remember it so it gets skipped
+	let nested_dest = match (std::fs::canonicalize(&src), canonicalize_partial(&dst)) {
+		(Ok(real_src), Ok(real_dst)) if real_dst.starts_with(&real_src) => Some(real_dst),
+		_ => None,
+	};
+
+	//FAT/exFAT can't hold Unix permissions and rejects some names, say so once up front instead of failing per file
+	let dest_fat = if cfg!(unix) { fat_filesystem(&dst) } else { None };
+	if let Some(fs_name) = dest_fat {
+		eprintln!("Warning: the destination is on a {} filesystem, permissions won't be copied and names with characters like : * ? \" < > | will fail", fs_name);
+	}
 
 	//Give warning if using verbose and either and or both of the only files or only dirs flags as verbose overrides them
 	if verbose && (only_files || only_dirs) {
@@ -45,11 +324,135 @@ fn main() {
 		source: src.clone(),
 		show_files: !only_dirs && !quiet,
 		show_dirs: !only_files && !quiet,
+		verbose,
 		recursive: !non_recursive,
 		dry_run,
+		tree: matches.get_flag("tree"),
+		strict: matches.get_flag("strict"),
 		excludes,
+		exclude_names,
+		strict_excludes: matches.get_flag("strict_excludes"),
+		name_max: max_name_length(&dst).unwrap_or(DEFAULT_NAME_MAX),
+		skip_too_long: matches.get_flag("skip_too_long"),
+		newer_than,
+		recent_cutoff: matches.get_one::<u64>("skip_recent").map(|secs| SystemTime::now().checked_sub(Duration::from_secs(*secs)).unwrap_or(SystemTime::UNIX_EPOCH)),
+		exclude_empty: matches.get_flag("exclude_empty"),
+		exclude_symlinks: matches.get_flag("exclude_symlinks"),
+		copy_contents: matches.get_flag("copy_contents"),
+		only_executable: matches.get_flag("only_executable"),
+		atomic: matches.get_flag("atomic"),
+		fsync: matches.get_flag("fsync"),
+		temp_dir,
+		timeout: matches.get_one::<u64>("timeout").map(|secs| Duration::from_secs(*secs)),
+		append,
+		overwrite_readonly: matches.get_flag("overwrite_readonly"),
+		no_clobber_newer: matches.get_flag("no_clobber_newer"),
+		clobber_empty_only: matches.get_flag("clobber_empty_only"),
+		rename_conflicts: matches.get_flag("rename_conflicts"),
+		repair: matches.get_flag("repair"),
+		link_dest,
+		files_from,
+		manifest,
+		git_deleted,
+		structure_only: matches.get_flag("structure_only"),
+		si: matches.get_flag("si"),
+		base: matches.get_one::<String>("base").map(PathBuf::from),
+		error_log,
+		checkpoint,
+		ignore_errors,
+		quiet_errors: matches.get_flag("quiet_errors"),
+		abort_on_walk_error: matches.get_flag("abort_on_walk_error"),
+		preserve_crtime,
+		preserve_times: preserve.timestamps,
+		preserve_owner,
+		preserve_xattrs,
+		lowercase_dest: matches.get_flag("lowercase_dest"),
+		renames: matches.get_many::<RenameRule>("rename").map(|rules| rules.cloned().collect()).unwrap_or_default(),
+		strip_components: matches.get_one::<usize>("strip_components").copied().unwrap_or(0),
+		no_hidden: matches.get_flag("no_hidden"),
+		progress_refresh: matches.get_one::<u8>("progress_refresh").copied(),
+		progress_interval: match (matches.get_one::<Duration>("progress_interval"), matches.get_one::<u8>("progress_refresh")) {
+			(Some(interval), _) => Some(*interval),
+			_ if matches.get_one::<String>("progress").map(String::as_str) != Some("periodic") => None,
+			(None, Some(hz)) => Some(Duration::from_secs(1) / *hz as u32),
+			(None, None) => Some(PERIODIC_INTERVAL),
+		},
+		eta_smoothing: matches.get_one::<f64>("eta_smoothing").copied(),
+		progress: match matches.get_one::<String>("progress").map(String::as_str) {
+			Some("porcelain") => ProgressMode::Porcelain,
+			Some("periodic") => ProgressMode::Periodic,
+			_ => ProgressMode::Bar,
+		},
+		progress_file,
+		verify: matches.get_flag("verify"),
+		verify_sample: matches.get_one::<f64>("verify_sample").copied(),
+		verify_size: matches.get_flag("verify_size") || matches.get_flag("verify"),
+		hash: matches.get_one::<HashAlgorithm>("hash").copied().unwrap_or(HashAlgorithm::Blake3),
+		seed: matches.get_one::<u64>("seed").copied().unwrap_or(0),
+		preserve_flags: matches.get_flag("preserve_flags") || (preserve.all && cfg!(any(target_os = "linux", target_os = "macos"))),
+		preserve_resource_fork,
+		devices,
+		dereference: matches.get_flag("dereference"),
+		preserve_links,
+		chmod: matches.get_one::<ModeSpec>("chmod").cloned(),
+		chmod_dir: matches.get_one::<ModeSpec>("chmod_dir").cloned(),
+		keep_dest_dir_perms: matches.get_flag("keep_dest_dir_perms"),
+		keep_dest_dir_times: matches.get_flag("keep_dest_dir_times"),
+		permissions: dest_fat.is_none(),
+		data_only: matches.get_flag("data_only"),
+		throughput: matches.get_flag("throughput"),
+		absolute_paths: matches.get_flag("absolute_paths"),
+		summary: match matches.get_one::<SummaryFormat>("format") {
+			Some(format) => *format,
+			None if matches.get_flag("oneline_summary") => SummaryFormat::Oneline,
+			None => SummaryFormat::Text,
+		},
+		delete: matches.get_flag("delete"),
+		trash: matches.get_flag("trash"),
+		max_files: matches.get_one::<u64>("max_files").copied().filter(|_| !matches.get_flag("force")),
+		max_bytes: matches.get_one::<u64>("max_bytes").copied(),
+		buffer_size,
+		order: match matches.get_one::<String>("order").map(String::as_str) {
+			Some("breadth-first") => WalkOrder::BreadthFirst,
+			_ => WalkOrder::DepthFirst,
+		},
+		ordered,
+		parallel: match matches.get_one::<String>("parallel").map(String::as_str) {
+			Some("dirs") => ParallelStrategy::Dirs,
+			_ => ParallelStrategy::Files,
+		},
+		collision: match matches.get_one::<String>("collision").map(String::as_str) {
+			Some("rename") => CollisionPolicy::Rename,
+			_ => CollisionPolicy::Fail,
+		},
+		nested_dest,
 	};
 
+	//--data-only wins over every preserve option, including ones from a config file, nothing but contents is copied
+	if options.data_only {
+		options.permissions = false;
+		options.chmod = None;
+		options.chmod_dir = None;
+		options.preserve_times = false;
+		options.preserve_crtime = false;
+		options.preserve_owner = false;
+		options.preserve_xattrs = false;
+		options.preserve_flags = false;
+		options.preserve_resource_fork = false;
+	}
+
+	//--newest becomes a --files-from list of the picked files, made after the options so every filter applies to the pick
+	let mut newest_picked = None;
+	if let Some(count) = matches.get_one::<usize>("newest") {
+		if !src.is_dir() {
+			eprintln!("Error: --newest needs a source directory");
+			std::process::exit(1);
+		}
+		let (newest, total) = newest_files(&src, &options, *count);
+		newest_picked = Some((newest.len(), total));
+		options.files_from = Some(FileList::from_paths(&newest));
+	}
+
 	if options.dry_run && quiet {
 		if !only_dirs && !only_files {
 			options.show_files = true;
@@ -60,29 +463,156 @@ fn main() {
 		}
 	}
 
+	//Benchmark mode copies into temp directories and cleans up after itself
+	if matches.get_flag("bench") {
+		run_bench(&src, &options);
+		return;
+	}
+
+	//Duplicate report only reads the source, there's no destination
+	if matches.get_flag("dedupe_report") {
+		run_dedupe_report(&src, &options);
+		return;
+	}
+
+	//--list only prints the copy set, nothing is written and no banners get in the way of a pipe
+	if matches.get_flag("list") {
+		let listed = match &globbed {
+			Some(sources) => sources.iter().try_for_each(|source| {
+				options.source = source.clone();
+				list_copy_set(source, Path::new(source.file_name().unwrap_or_default()), null_separated, &options)
+			}),
+			None => list_copy_set(&src, Path::new(""), null_separated, &options),
+		};
+		//Piping into head closes the pipe early, that's not an error
+		if let Err(e) = listed
+			&& e.kind() != std::io::ErrorKind::BrokenPipe {
+			eprintln!("Error: {}", e);
+			std::process::exit(1);
+		}
+		return;
+	}
+
+	//rcpy file - works like cat with rcpy's filters, so nothing but the file's bytes may reach stdout
+	if to_stdout {
+		if let Err(e) = copy_to_stdout(&src, &options) {
+			eprintln!("Error copying {} to stdout: {}", src.display(), e);
+			std::process::exit(1);
+		}
+		return;
+	}
+
+	if options.progress_interval.is_some() && options.progress == ProgressMode::Bar && options.progress_file.is_none() {
+		eprintln!("Warning: --progress-interval only changes --progress porcelain/periodic and --progress-file, the bar redraws as usual");
+	}
+
+	//Has to happen before rayon starts its threads so every worker inherits it
+	if let Some(class) = matches.get_one::<String>("ionice")
+		&& let Err(e) = lower_io_priority(class == "idle") {
+		eprintln!("Warning: couldn't lower the IO priority, copying at normal priority ({})", e);
+	}
+
+	//--cpu-affinity sizes rayon's pool to the listed cores and pins each worker to one of them, -s copies on
+	//this thread so it gets pinned to the whole list instead
+	if let Some(cores) = matches.get_one::<Vec<usize>>("cpu_affinity") {
+		match allowed_cores() {
+			Err(e) => eprintln!("Warning: can't pin threads here, --cpu-affinity is ignored ({})", e),
+			Ok(allowed) => {
+				if let Some(core) = cores.iter().find(|core| !allowed.contains(core)) {
+					let allowed: Vec<String> = allowed.iter().map(usize::to_string).collect();
+					eprintln!("Error: --cpu-affinity core {} isn't available to rcpy (available: {})", core, allowed.join(","));
+					std::process::exit(1);
+				}
+				if single_threaded {
+					if let Err(e) = pin_thread(cores) {
+						eprintln!("Warning: couldn't pin the copy thread ({})", e);
+					}
+				} else {
+					let workers = cores.clone();
+					let pool = rayon::ThreadPoolBuilder::new()
+						.num_threads(cores.len())
+						.start_handler(move |index| {
+							if let Err(e) = pin_thread(&[workers[index % workers.len()]]) {
+								eprintln!("Warning: couldn't pin copy thread {} ({})", index, e);
+							}
+						})
+						.build_global();
+					if let Err(e) = pool {
+						eprintln!("Warning: couldn't set up the pinned threads, --cpu-affinity is ignored ({})", e);
+					}
+				}
+			}
+		}
+	}
+
 	//Start timer then start copying!
 	let start_time = Instant::now();
 
-	//Print heading
-	println!("\n--------------RUSTY COPY--------------\n");
+	//Print heading, --oneline-summary/--format leave out all the decoration
+	let banner = options.summary == SummaryFormat::Text;
+	if banner {
+		println!("\n--------------RUSTY COPY--------------\n");
+	}
 	
-	if copied_single(&src, &dst, &start_time, options.dry_run) {
+	//Archives are extracted into the destination rather than copied as a file
+	if src.is_file() && (matches.get_flag("from_archive") || is_archive(&src)) {
+		if options.manifest.is_some() {
+			eprintln!("Error: --manifest can't check files extracted from an archive, extract it first");
+			std::process::exit(1);
+		}
+		if banner {
+			println!("Extracting archive {}\n", src.display());
+		}
+		finish_run(extract_archive(&src, &dst, &options), start_time, &options);
+		return;
+	}
+
+	if let Some(sources) = &globbed {
+		if banner {
+			println!("Copying {} matches of {}\n", sources.len(), source);
+		}
+		let result = copy_matches(sources, &dst, &mut options, single_threaded);
+		finish_run(result, start_time, &options);
+		return;
+	}
+
+	if copied_single(&src, &dst, &start_time, &options) {
 		return; //Then we only copied a single file good to exit
 	}
 	
 	//Check if we are using recursion or not and tell the user
-	if options.recursive {
-		println!("Recursive Mode (default)\n");
-	} else {
-		println!("Non-Recursive Mode\n");
-	}
+	if banner {
+		if options.recursive {
+			println!("Recursive Mode (default)\n");
+		} else {
+			println!("Non-Recursive Mode\n");
+		}
 
-	if options.dry_run {
-		println!("Dry-run mode enabled — no files will be written.\n");
+		if options.dry_run {
+			println!("Dry-run mode enabled — no files will be written.\n");
+		}
+
+		if let Some((picked, total)) = newest_picked {
+			println!("Selected the {} most recently modified of {} file(s) (--newest)\n", picked, total);
+		}
 	}
 	
+	//Compare against what the source looked like last time before copying anything
+	let manifest = matches.get_one::<String>("diff_manifest").map(|path| {
+		let current = scan_source(&src, &options);
+		print_changes(&read_manifest(Path::new(path)), &current);
+		(PathBuf::from(path), current)
+	});
+
 	run_copy(single_threaded, &src, &dst, &options, start_time);
 
+	//Only reached when the copy worked, a failed run leaves the last good manifest in place
+	if let Some((path, current)) = manifest
+		&& !options.dry_run
+		&& let Err(e) = write_manifest(&path, &current) {
+		eprintln!("Warning: couldn't write manifest {}: {}", path.display(), e);
+	}
+
 }
 
 
@@ -0,0 +1,153 @@
+/*****************************************
+    manifest.rs
+-----------------
+Description: Handles --diff-manifest, a
+record of what the source looked like on
+the last run so the next one can print
+what's new, changed and removed
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::copy::{is_dir_entry, walk_source};
+use crate::utils::{skip_reason, CopyOptions};
+
+//Format: this header line, then one `size<TAB>mtime<TAB>path` line per file, mtime in seconds since the epoch
+//The path goes last so a tab in a name can't break the line apart, sorted so two manifests diff cleanly by hand
+//Backslashes, newlines and carriage returns in the path are escaped (\\, \n, \r) so every file stays on one line
+const MANIFEST_HEADER: &str = "rcpy-manifest 2";
+//Version 1 didn't escape anything, still read so upgrading doesn't report every file as new
+const MANIFEST_HEADER_V1: &str = "rcpy-manifest 1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileState {
+    size: u64,
+    mtime: u64,
+}
+
+pub type Manifest = BTreeMap<PathBuf, FileState>;
+
+//Function to record the files in the source (relative paths), walked and filtered the same way the copy is
+pub fn scan_source(src: &Path, options: &CopyOptions) -> Manifest {
+    walk_source(src, options, None)
+        .filter_map(Result::ok)
+        .filter(|entry| !is_dir_entry(entry) && skip_reason(entry.path(), options).is_none())
+        .filter_map(|entry| {
+            let rel_path = entry.path().strip_prefix(src).ok()?.to_path_buf();
+            let metadata = fs::metadata(entry.path()).ok()?;
+            let mtime = metadata.modified().ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_secs());
+            Some((rel_path, FileState { size: metadata.len(), mtime }))
+        })
+        .collect()
+}
+
+//A missing manifest, or one from a format we don't know, means everything counts as new
+pub fn read_manifest(path: &Path) -> Manifest {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("Warning: couldn't read manifest {} ({}), treating everything as new", path.display(), e);
+            }
+            return Manifest::new();
+        }
+    };
+
+    let mut lines = contents.lines();
+    let escaped = match lines.next() {
+        Some(MANIFEST_HEADER) => true,
+        Some(MANIFEST_HEADER_V1) => false,
+        _ => {
+            eprintln!("Warning: {} isn't a manifest this version understands, treating everything as new", path.display());
+            return Manifest::new();
+        }
+    };
+    lines.filter_map(|line| {
+        let mut fields = line.splitn(3, '\t');
+        let size = fields.next()?.parse().ok()?;
+        let mtime = fields.next()?.parse().ok()?;
+        let name = fields.next()?;
+        let rel_path = if escaped { PathBuf::from(unescape(name)) } else { PathBuf::from(name) };
+        Some((rel_path, FileState { size, mtime }))
+    })
+    .collect()
+}
+
+pub fn write_manifest(path: &Path, manifest: &Manifest) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{}", MANIFEST_HEADER)?;
+    for (rel_path, state) in manifest {
+        writeln!(out, "{}\t{}\t{}", state.size, state.mtime, escape(&rel_path.to_string_lossy()))?;
+    }
+    out.flush()
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+//Function to print what changed since the manifest was written, + new, ~ changed, - removed
+pub fn print_changes(previous: &Manifest, current: &Manifest) {
+    let mut new = 0;
+    let mut changed = 0;
+    let mut removed = 0;
+
+    println!("Changes since the last run:");
+    for (rel_path, state) in current {
+        match previous.get(rel_path) {
+            None => {
+                new += 1;
+                println!("  + {}", rel_path.display());
+            }
+            Some(old) if old != state => {
+                changed += 1;
+                println!("  ~ {}", rel_path.display());
+            }
+            _ => {}
+        }
+    }
+    for rel_path in previous.keys().filter(|rel_path| !current.contains_key(*rel_path)) {
+        removed += 1;
+        println!("  - {}", rel_path.display());
+    }
+    println!("{} new, {} changed, {} removed.\n", new, changed, removed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn awkward_names_survive_a_round_trip() {
+        for name in ["plain.txt", "two\nlines.txt", "back\\slash", "cr\r.txt", "tab\there", "trailing\\", "\\n literally"] {
+            assert_eq!(unescape(&escape(name)), name);
+            assert!(!escape(name).contains(['\n', '\r']), "{:?}", escape(name));
+        }
+    }
+}
@@ -0,0 +1,602 @@
+/*****************************************
+    platform.rs
+-----------------
+Description: Handles the OS specific bits
+that std doesn't cover, like file flags
+(chattr / chflags), extended attributes,
+owners, macOS resource forks,
+Windows long paths, IO priority, device
+nodes and the destination's
+filesystem type. Everything here is
+best effort and warns instead of failing
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::utils::print_error;
+
+//Function to carry file flags (immutable, append-only, nodump...) from the source to the copy
+//Has to run after the contents and permissions are written, an immutable file can't be touched after
+//NOTE: setting immutable/append-only needs root (CAP_LINUX_IMMUTABLE) on Linux and usually on BSD too
+pub fn copy_file_flags(src: &Path, dest: &Path) {
+    static UNSUPPORTED_WARNED: AtomicBool = AtomicBool::new(false);
+
+    match set_flags(dest, read_flags(src)) {
+        Ok(()) => {}
+        Err(e) if is_unsupported(&e) => {
+            if !UNSUPPORTED_WARNED.swap(true, Ordering::Relaxed) {
+                print_error(format_args!("Warning: file flags aren't supported here, --preserve-flags is being ignored ({})", e));
+            }
+        }
+        Err(e) => print_error(format_args!("Failed to set file flags on {}: {}", dest.display(), e)),
+    }
+}
+
+pub fn is_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported
+        || matches!(err.raw_os_error(), Some(code) if is_unsupported_errno(code))
+}
+
+#[cfg(unix)]
+fn is_unsupported_errno(code: i32) -> bool {
+    code == libc::ENOTTY || code == libc::EOPNOTSUPP || code == libc::ENOSYS
+}
+
+#[cfg(not(unix))]
+fn is_unsupported_errno(_code: i32) -> bool {
+    false
+}
+
+//Linux keeps these behind the FS_IOC_GETFLAGS/FS_IOC_SETFLAGS ioctls (what lsattr/chattr use)
+#[cfg(target_os = "linux")]
+fn read_flags(path: &Path) -> io::Result<u32> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(path)?;
+    let mut flags: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags as u32)
+}
+
+#[cfg(target_os = "linux")]
+fn set_flags(path: &Path, flags: io::Result<u32>) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    //Only the flags chattr is allowed to change (FS_FL_USER_MODIFIABLE), the rest are managed by the filesystem
+    const USER_MODIFIABLE: u32 = 0x0003_80FF;
+    let flags = flags? & USER_MODIFIABLE;
+    if flags == 0 {
+        return Ok(());
+    }
+
+    let file = std::fs::File::open(path)?;
+    let mut current: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut current) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut wanted = (current as u32 | flags) as libc::c_int;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &mut wanted) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+//macOS exposes them as st_flags and chflags(2)
+#[cfg(target_os = "macos")]
+fn read_flags(path: &Path) -> io::Result<u32> {
+    use std::os::macos::fs::MetadataExt;
+    Ok(std::fs::symlink_metadata(path)?.st_flags())
+}
+
+#[cfg(target_os = "macos")]
+fn set_flags(path: &Path, flags: io::Result<u32>) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let flags = flags?;
+    if flags == 0 {
+        return Ok(());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::chflags(c_path.as_ptr(), flags as _) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+//Nothing to do anywhere else, report it so the user is told once
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn read_flags(_path: &Path) -> io::Result<u32> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn set_flags(_path: &Path, flags: io::Result<u32>) -> io::Result<()> {
+    flags.map(|_| ())
+}
+
+//Function to carry a macOS file's resource fork and Finder info over, old apps break without them
+//Both are stored as extended attributes, a file without one of them just skips it
+#[cfg(target_os = "macos")]
+pub fn copy_resource_fork(src: &Path, dest: &Path) {
+    const FORK_ATTRS: [&str; 2] = ["com.apple.ResourceFork", "com.apple.FinderInfo"];
+
+    for name in FORK_ATTRS {
+        let copied = read_xattr(src, name.as_bytes()).and_then(|value| match value {
+            Some(value) => write_xattr(dest, name.as_bytes(), &value),
+            None => Ok(()),
+        });
+        if let Err(e) = copied {
+            print_error(format_args!("Failed to copy {} to {}: {}", name, dest.display(), e));
+        }
+    }
+}
+
+//Function to copy every extended attribute (user.*, security labels, macOS quarantine...) for --preserve xattr
+//Attributes the destination or our privileges won't take are reported per attribute, the copy itself still counts
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn copy_xattrs(src: &Path, dest: &Path) {
+    static UNSUPPORTED_WARNED: AtomicBool = AtomicBool::new(false);
+
+    let names = match list_xattrs(src) {
+        Ok(names) => names,
+        Err(e) => {
+            print_error(format_args!("Failed to list extended attributes of {}: {}", src.display(), e));
+            return;
+        }
+    };
+    for name in names {
+        let copied = read_xattr(src, &name).and_then(|value| match value {
+            Some(value) => write_xattr(dest, &name, &value),
+            None => Ok(()), //Removed between listing and reading
+        });
+        match copied {
+            Ok(()) => {}
+            Err(e) if is_unsupported(&e) => {
+                if !UNSUPPORTED_WARNED.swap(true, Ordering::Relaxed) {
+                    print_error(format_args!("Warning: the destination doesn't support extended attributes, they won't be copied ({})", e));
+                }
+                return;
+            }
+            Err(e) => print_error(format_args!("Failed to copy {} to {}: {}", String::from_utf8_lossy(&name), dest.display(), e)),
+        }
+    }
+}
+
+//Only Linux and macOS are handled, main warns and turns the option off everywhere else
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn copy_xattrs(_src: &Path, _dest: &Path) {}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn xattr_c_strings(path: &Path, name: &[u8]) -> io::Result<(std::ffi::CString, std::ffi::CString)> {
+    let c_name = std::ffi::CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok((c_path(path)?, c_name))
+}
+
+//listxattr/getxattr/setxattr only differ by macOS' extra position and options arguments
+#[cfg(target_os = "linux")]
+unsafe fn sys_listxattr(path: *const libc::c_char, buf: *mut libc::c_char, size: usize) -> isize {
+    unsafe { libc::listxattr(path, buf, size) }
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn sys_listxattr(path: *const libc::c_char, buf: *mut libc::c_char, size: usize) -> isize {
+    unsafe { libc::listxattr(path, buf, size, 0) }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn sys_getxattr(path: *const libc::c_char, name: *const libc::c_char, buf: *mut libc::c_void, size: usize) -> isize {
+    unsafe { libc::getxattr(path, name, buf, size) }
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn sys_getxattr(path: *const libc::c_char, name: *const libc::c_char, buf: *mut libc::c_void, size: usize) -> isize {
+    unsafe { libc::getxattr(path, name, buf, size, 0, 0) }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn sys_setxattr(path: *const libc::c_char, name: *const libc::c_char, value: *const libc::c_void, size: usize) -> libc::c_int {
+    unsafe { libc::setxattr(path, name, value, size, 0) }
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn sys_setxattr(path: *const libc::c_char, name: *const libc::c_char, value: *const libc::c_void, size: usize) -> libc::c_int {
+    unsafe { libc::setxattr(path, name, value, size, 0, 0) }
+}
+
+//Names come back as one buffer of NUL terminated strings
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn list_xattrs(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let c_path = c_path(path)?;
+    let size = unsafe { sys_listxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        let err = io::Error::last_os_error();
+        //A filesystem without xattrs simply has none to copy
+        return if is_unsupported(&err) { Ok(Vec::new()) } else { Err(err) };
+    }
+    let mut names = vec![0u8; size as usize];
+    let read = unsafe { sys_listxattr(c_path.as_ptr(), names.as_mut_ptr() as *mut libc::c_char, names.len()) };
+    if read < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(read as usize);
+    Ok(names.split(|b| *b == 0).filter(|name| !name.is_empty()).map(<[u8]>::to_vec).collect())
+}
+
+//None when the file doesn't have the attribute
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_xattr(path: &Path, name: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    #[cfg(target_os = "linux")]
+    const NO_ATTR: i32 = libc::ENODATA;
+    #[cfg(target_os = "macos")]
+    const NO_ATTR: i32 = libc::ENOATTR;
+
+    let (c_path, c_name) = xattr_c_strings(path, name)?;
+
+    //Ask for the size first, then read it all in one go
+    let size = unsafe { sys_getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        let err = io::Error::last_os_error();
+        return if err.raw_os_error() == Some(NO_ATTR) { Ok(None) } else { Err(err) };
+    }
+    let mut value = vec![0u8; size as usize];
+    let read = unsafe { sys_getxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_mut_ptr() as *mut libc::c_void, value.len()) };
+    if read < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    value.truncate(read as usize);
+    Ok(Some(value))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn write_xattr(path: &Path, name: &[u8], value: &[u8]) -> io::Result<()> {
+    let (c_path, c_name) = xattr_c_strings(path, name)?;
+    if unsafe { sys_setxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+//Function to give the copy the source's owner and group for --preserve ownership
+//Only root can give files away, for anyone else this warns once and the copy keeps our own user
+#[cfg(unix)]
+pub fn copy_ownership(src: &Path, dest: &Path) {
+    use std::os::unix::fs::MetadataExt;
+    static DENIED_WARNED: AtomicBool = AtomicBool::new(false);
+
+    let Ok(metadata) = std::fs::metadata(src) else { return };
+    match std::os::unix::fs::chown(dest, Some(metadata.uid()), Some(metadata.gid())) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            if !DENIED_WARNED.swap(true, Ordering::Relaxed) {
+                print_error(format_args!("Warning: not allowed to change owners (needs root), copies keep the current user ({})", e));
+            }
+        }
+        Err(e) => print_error(format_args!("Failed to set the owner of {}: {}", dest.display(), e)),
+    }
+}
+
+//Windows ownership is ACL based, main warns and turns the option off
+#[cfg(not(unix))]
+pub fn copy_ownership(_src: &Path, _dest: &Path) {}
+
+//Resource forks only exist on macOS, main warns and turns the option off everywhere else
+#[cfg(not(target_os = "macos"))]
+pub fn copy_resource_fork(_src: &Path, _dest: &Path) {}
+
+//Function to make a path safe to hand to the filesystem on Windows, where anything past 260 characters
+//fails unless it has the \\?\ extended-length prefix. Only use the result for filesystem calls, never for output
+//Elsewhere paths are returned as they are
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> std::path::PathBuf {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
+
+    //The prefix turns off Windows' own path cleanup, so it needs an absolute path with no . or .. left in it
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let mut prefixed = OsString::new();
+    let mut components = absolute.components();
+    match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Verbatim(_) | Prefix::VerbatimUNC(..) | Prefix::VerbatimDisk(_) | Prefix::DeviceNS(_) => return absolute,
+            Prefix::UNC(server, share) => {
+                prefixed.push(r"\\?\UNC\");
+                prefixed.push(server);
+                prefixed.push(r"\");
+                prefixed.push(share);
+            }
+            Prefix::Disk(_) => {
+                prefixed.push(r"\\?\");
+                prefixed.push(prefix.as_os_str());
+            }
+        },
+        _ => return absolute,
+    }
+    let mut prefixed = std::path::PathBuf::from(prefixed);
+    for component in components {
+        prefixed.push(component.as_os_str());
+    }
+    prefixed
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> std::path::PathBuf {
+    path.to_path_buf()
+}
+
+//Function to drop our IO priority for --ionice, idle only gets the disk when nothing else wants it
+//and low is the bottom of best-effort. Called before any copy threads exist so they all inherit it
+#[cfg(target_os = "linux")]
+pub fn lower_io_priority(idle: bool) -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    const IOPRIO_CLASS_BE: libc::c_long = 2;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+
+    let ioprio = if idle {
+        IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT
+    } else {
+        (IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT) | 7
+    };
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0 as libc::c_long, ioprio) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+//Other Unixes have no IO classes, the closest thing is lowering the CPU nice value which most schedulers
+//also use for IO
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn lower_io_priority(idle: bool) -> io::Result<()> {
+    let nice = if idle { 19 } else { 10 };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lower_io_priority(_idle: bool) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+//Function to list the cores this process may run on (its affinity mask), --cpu-affinity can only pick from these
+#[cfg(target_os = "linux")]
+pub fn allowed_cores() -> io::Result<Vec<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..libc::CPU_SETSIZE as usize).filter(|&core| unsafe { libc::CPU_ISSET(core, &set) }).collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cores() -> io::Result<Vec<usize>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+//Function to pin the calling thread to a set of cores, the ids have to come from allowed_cores
+#[cfg(target_os = "linux")]
+pub fn pin_thread(cores: &[usize]) -> io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &core in cores {
+        unsafe { libc::CPU_SET(core, &mut set) };
+    }
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_thread(_cores: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+//Function to tell if a walked entry is a block/character device, checked from the walk's file type
+//so the device is never opened
+#[cfg(unix)]
+pub fn is_device(file_type: &std::fs::FileType) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    file_type.is_block_device() || file_type.is_char_device()
+}
+
+#[cfg(not(unix))]
+pub fn is_device(_file_type: &std::fs::FileType) -> bool {
+    false
+}
+
+//Function to recreate a device with the same major/minor numbers instead of reading it (reading /dev/zero
+//would never end). Only the metadata is looked at. mknod needs root for devices
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] //mode_t/dev_t widths differ between platforms
+pub fn recreate_device(src: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let metadata = std::fs::symlink_metadata(src)?;
+    let kind = if metadata.file_type().is_block_device() { libc::S_IFBLK } else { libc::S_IFCHR };
+
+    //A node left over from an earlier run would make mknod fail with EEXIST
+    match std::fs::remove_file(dest) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let c_path = CString::new(dest.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mode = kind as libc::mode_t | (metadata.mode() & 0o7777) as libc::mode_t;
+    if unsafe { libc::mknod(c_path.as_ptr(), mode, metadata.rdev() as libc::dev_t) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+//Never called since is_device is always false there, main warns the option does nothing
+#[cfg(not(unix))]
+pub fn recreate_device(_src: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+//Function to get the preferred IO size (st_blksize) for writing to a path, checked on the closest existing
+//ancestor like fat_filesystem. None where there's no such thing or it can't be read
+#[cfg(unix)]
+pub fn preferred_block_size(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    let existing = path.ancestors().find(|p| !p.as_os_str().is_empty() && p.exists()).unwrap_or(Path::new("."));
+    std::fs::metadata(existing).ok().map(|m| m.blksize()).filter(|size| *size > 0)
+}
+
+#[cfg(not(unix))]
+pub fn preferred_block_size(_path: &Path) -> Option<u64> {
+    None
+}
+
+//What a name may be taken to allow when the filesystem won't say, it's the limit nearly everywhere
+pub const DEFAULT_NAME_MAX: u64 = 255;
+
+//Function to get the longest file or directory name the filesystem a path lives on allows, checked on the closest
+//existing ancestor like fat_filesystem. Best effort, None when it can't be asked
+#[cfg(unix)]
+pub fn max_name_length(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|p| !p.as_os_str().is_empty() && p.exists()).unwrap_or(Path::new("."));
+    let c_path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let limit = unsafe { libc::pathconf(c_path.as_ptr(), libc::_PC_NAME_MAX) };
+    u64::try_from(limit).ok().filter(|limit| *limit > 0)
+}
+
+#[cfg(not(unix))]
+pub fn max_name_length(_path: &Path) -> Option<u64> {
+    None
+}
+
+//Function to measure a name the way the filesystem limit counts it, bytes on Unix and UTF-16 units on Windows
+#[cfg(unix)]
+pub fn name_length(name: &std::ffi::OsStr) -> u64 {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().len() as u64
+}
+
+#[cfg(windows)]
+pub fn name_length(name: &std::ffi::OsStr) -> u64 {
+    use std::os::windows::ffi::OsStrExt;
+    name.encode_wide().count() as u64
+}
+
+//Function to tell if a path lives on a FAT/exFAT volume, those can't store Unix permissions and are picky about names
+//Returns the filesystem's name for the warning, checks the closest existing ancestor when the path doesn't exist yet
+pub fn fat_filesystem(path: &Path) -> Option<&'static str> {
+    let existing = path.ancestors().find(|p| !p.as_os_str().is_empty() && p.exists()).unwrap_or(Path::new("."));
+    fat_filesystem_name(existing)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn statfs(path: &Path) -> Option<libc::statfs> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats)
+}
+
+//Linux reports the filesystem by its magic number (f_type's width depends on the architecture, hence the cast)
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_cast)]
+fn fat_filesystem_name(path: &Path) -> Option<&'static str> {
+    const MSDOS_SUPER_MAGIC: i64 = 0x4d44;
+    const EXFAT_SUPER_MAGIC: i64 = 0x2011_BAB0;
+
+    match statfs(path)?.f_type as i64 {
+        MSDOS_SUPER_MAGIC => Some("FAT"),
+        EXFAT_SUPER_MAGIC => Some("exFAT"),
+        _ => None,
+    }
+}
+
+//macOS reports it by name
+#[cfg(target_os = "macos")]
+fn fat_filesystem_name(path: &Path) -> Option<&'static str> {
+    let stats = statfs(path)?;
+    let name: Vec<u8> = stats.f_fstypename.iter().take_while(|c| **c != 0).map(|c| *c as u8).collect();
+    match name.as_slice() {
+        b"msdos" => Some("FAT"),
+        b"exfat" => Some("exFAT"),
+        _ => None,
+    }
+}
+
+//Windows has no Unix permissions to lose, and anything else we can't check
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn fat_filesystem_name(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_gets_the_extended_length_prefix() {
+        let root = std::env::temp_dir().join(format!("rcpy-long-path-{}", std::process::id()));
+        let deep = root
+            .join("a".repeat(100))
+            .join(".")
+            .join("b".repeat(100))
+            .join("..")
+            .join("c".repeat(100))
+            .join("d".repeat(100));
+        assert!(deep.as_os_str().len() > 260);
+
+        let prefixed = long_path(&deep);
+        let text = prefixed.to_string_lossy();
+        assert!(text.starts_with(r"\\?\"), "{}", text);
+        assert!(!text.contains(r"\.\") && !text.contains(r"\..\"), "{}", text);
+        assert!(!text.contains(&"b".repeat(100)), "{}", text);
+
+        std::fs::create_dir_all(&prefixed).unwrap();
+        std::fs::write(prefixed.join("file.txt"), "deep").unwrap();
+        assert_eq!(std::fs::read_to_string(prefixed.join("file.txt")).unwrap(), "deep");
+        std::fs::remove_dir_all(long_path(&root)).unwrap();
+
+        //Already prefixed paths are left alone
+        assert_eq!(long_path(&prefixed), prefixed);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_handles_unc_shares() {
+        let prefixed = long_path(Path::new(r"\\server\share\dir\file.txt"));
+        assert_eq!(prefixed, Path::new(r"\\?\UNC\server\share\dir\file.txt"));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn long_path_is_a_no_op_elsewhere() {
+        let deep = Path::new("./rel").join("x".repeat(300));
+        assert_eq!(long_path(&deep), deep);
+    }
+}
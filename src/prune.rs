@@ -0,0 +1,128 @@
+/*****************************************
+    prune.rs
+-----------------
+Description: Handles --delete (mirroring),
+anything in the destination that isn't in
+the source gets removed, or moved to the
+trash with --trash. --since-git-delete
+removes files git saw deleted the same way
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use walkdir::WalkDir;
+
+use crate::copy::report_failure;
+use crate::utils::{display_path, is_excluded_name, is_excluded_path, phase_progress_bar, print_error, print_line, set_active_bar, CopyCounters, CopyOptions};
+
+//Function to remove whatever is in dst but not in src, runs after the copy so it never races it
+//Excluded files are left alone, same as rsync, and nothing is deleted if the copy had errors
+//since a half-read source could make good files look extraneous
+pub fn prune_destination(src: &Path, dst: &Path, options: &CopyOptions, counters: &CopyCounters) {
+    if counters.errors.load(Ordering::Relaxed) > 0 {
+        print_error(format_args!("Warning: there were errors copying, skipping --delete so nothing is removed by mistake"));
+        return;
+    }
+
+    let extraneous = find_extraneous(src, dst, options);
+    remove_all(dst, &extraneous, options, counters);
+}
+
+//Function to remove what --since-git found deleted (or renamed away) since the commit, anything the
+//destination doesn't have (anymore) is nothing to do
+pub fn remove_git_deleted(dst: &Path, options: &CopyOptions, counters: &CopyCounters) {
+    let deleted: Vec<PathBuf> = options.git_deleted.iter()
+        .filter(|rel_path| fs::symlink_metadata(dst.join(rel_path)).is_ok())
+        .cloned()
+        .collect();
+    remove_all(dst, &deleted, options, counters);
+}
+
+//Deletes (or trashes) paths relative to the destination with a bar, a dry run only prints them
+fn remove_all(dst: &Path, rel_paths: &[PathBuf], options: &CopyOptions, counters: &CopyCounters) {
+    if rel_paths.is_empty() {
+        return;
+    }
+
+    let pb = phase_progress_bar("Pruning destination...", rel_paths.len() as u64, options);
+    for rel_path in rel_paths {
+        let dest_path = dst.join(rel_path);
+        if options.dry_run {
+            print_line(format_args!("[DRY RUN] delete {}", display_path(&dest_path, options).display()));
+            counters.deleted.fetch_add(1, Ordering::Relaxed);
+        } else {
+            match remove_path(&dest_path, options.trash) {
+                Ok(true) => { counters.trashed.fetch_add(1, Ordering::Relaxed); }
+                Ok(false) => { counters.deleted.fetch_add(1, Ordering::Relaxed); }
+                Err(err) => report_failure(options, counters, rel_path, "Failed to delete", &err),
+            }
+            if options.show_files {
+                print_line(format_args!("[DELETE] {}", display_path(&dest_path, options).display()));
+            }
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    set_active_bar(None);
+}
+
+//Walks the destination collecting paths with nothing at the same place in the source
+//A directory that's extraneous is removed whole, so its contents aren't listed separately
+fn find_extraneous(src: &Path, dst: &Path, options: &CopyOptions) -> Vec<PathBuf> {
+    let mut extraneous = Vec::new();
+    let walker = if options.recursive { WalkDir::new(dst) } else { WalkDir::new(dst).max_depth(1) };
+    let mut entries = walker.min_depth(1).into_iter();
+
+    while let Some(entry) = entries.next() {
+        let Ok(entry) = entry else { continue };
+        let rel_path = entry.path().strip_prefix(dst).unwrap().to_path_buf();
+        if is_excluded_path(&rel_path, &options.excludes) || is_excluded_name(entry.file_name(), &options.exclude_names) {
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+            continue;
+        }
+        if fs::symlink_metadata(src.join(&rel_path)).is_err() {
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+            extraneous.push(rel_path);
+        }
+    }
+    extraneous
+}
+
+//Returns whether the path went to the trash (true) or was deleted for good (false)
+fn remove_path(path: &Path, trash: bool) -> io::Result<bool> {
+    if trash {
+        if let Some(trashed) = move_to_trash(path) {
+            return trashed.map(|()| true);
+        }
+        static UNSUPPORTED_WARNED: AtomicBool = AtomicBool::new(false);
+        if !UNSUPPORTED_WARNED.swap(true, Ordering::Relaxed) {
+            print_error(format_args!("Warning: there's no trash on this platform, --trash is deleting files permanently"));
+        }
+    }
+
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(false)
+}
+
+//None when the platform has no trash to move things to
+#[cfg(any(windows, target_os = "macos", target_os = "linux"))]
+fn move_to_trash(path: &Path) -> Option<io::Result<()>> {
+    Some(trash::delete(path).map_err(io::Error::other))
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+fn move_to_trash(_path: &Path) -> Option<io::Result<()>> {
+    None
+}
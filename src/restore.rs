@@ -0,0 +1,155 @@
+/*****************************************
+    restore.rs
+-----------------
+Description: Handles --manifest, a list of
+the files to copy (for restoring part of a
+backup) that can also say what size and
+hash each one should have. The copies are
+checked against it after the copy and any
+listed file the source lacks is an error
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use rayon::prelude::*;
+
+use crate::copy::report_failure;
+use crate::platform::long_path;
+use crate::utils::{path_from_bytes, phase_progress_bar, set_active_bar, CopyCounters, CopyOptions};
+use crate::verify::{hash_file, HashAlgorithm};
+
+//One file the manifest asks for, the size and hash are only checked when given
+#[derive(Debug)]
+pub struct ManifestEntry {
+    path: PathBuf,
+    size: Option<u64>,
+    hash: Option<Vec<u8>>,
+}
+
+//Format: one file per line, relative to the source. A line is PATH, SIZE<TAB>PATH or SIZE<TAB>HASH<TAB>PATH
+//with - for a size or hash that isn't known, the hash is hex in the --hash algorithm. The path goes last so
+//a name with a tab in it still works behind a size and hash (-<TAB>-<TAB>PATH). Blank lines and # comments are skipped
+#[derive(Debug, Default)]
+pub struct IncludeManifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl IncludeManifest {
+    pub fn read(path: &Path) -> Result<IncludeManifest, String> {
+        let mut raw = Vec::new();
+        File::open(path).and_then(|mut file| file.read_to_end(&mut raw)).map_err(|e| e.to_string())?;
+
+        let mut manifest = IncludeManifest::default();
+        for (number, line) in raw.split(|b| *b == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            let entry = parse_entry(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            manifest.entries.push(entry);
+        }
+        Ok(manifest)
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.entries.iter().map(|entry| entry.path.clone()).collect()
+    }
+
+    //Whether any entry gives something to check beyond the file being there
+    pub fn has_checks(&self) -> bool {
+        self.entries.iter().any(|entry| entry.size.is_some() || entry.hash.is_some())
+    }
+}
+
+fn parse_entry(line: &[u8]) -> Result<ManifestEntry, String> {
+    let fields: Vec<&[u8]> = line.splitn(3, |b| *b == b'\t').collect();
+    let (size, hash, path) = match fields[..] {
+        [path] => (None, None, path),
+        [size, path] => (Some(size), None, path),
+        [size, hash, path] => (Some(size), Some(hash), path),
+        _ => unreachable!(),
+    };
+    if path.is_empty() {
+        return Err("no path".to_string());
+    }
+
+    let known = |field: Option<&[u8]>| field.filter(|field| *field != b"-").map(|field| String::from_utf8_lossy(field).into_owned());
+    let size = known(size).map(|size| size.parse::<u64>().map_err(|_| format!("{} is not a size in bytes", size))).transpose()?;
+    let hash = known(hash).map(|hash| parse_hex(&hash).ok_or_else(|| format!("{} is not a hex hash", hash))).transpose()?;
+    Ok(ManifestEntry { path: path_from_bytes(path), size, hash })
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+//Checks every manifest entry once the copy is done. An entry the source doesn't have is an error, one whose
+//copy has the wrong size or hash is a verification failure. A dry run wrote nothing, so there the source
+//itself is checked against the manifest
+pub fn check_manifest(src: &Path, dst: &Path, options: &CopyOptions, counters: &CopyCounters, parallel: bool) {
+    let Some(manifest) = &options.manifest else {
+        return;
+    };
+
+    let pb = phase_progress_bar("Checking manifest...", manifest.entries.len() as u64, options);
+    let check = |entry: &ManifestEntry| {
+        check_entry(entry, src, dst, options, counters);
+        pb.inc(1);
+    };
+    if parallel {
+        manifest.entries.par_iter().for_each(check);
+    } else {
+        manifest.entries.iter().for_each(check);
+    }
+    pb.finish_and_clear();
+    set_active_bar(None);
+}
+
+fn check_entry(entry: &ManifestEntry, src: &Path, dst: &Path, options: &CopyOptions, counters: &CopyCounters) {
+    let source = long_path(&src.join(&entry.path));
+    if std::fs::symlink_metadata(&source).is_err() {
+        let err = io::Error::new(io::ErrorKind::NotFound, "listed in the manifest but not in the source");
+        report_failure(options, counters, &entry.path, "Missing", &err);
+        return;
+    }
+    if entry.size.is_none() && entry.hash.is_none() {
+        return;
+    }
+
+    let copy = if options.dry_run { source } else { long_path(&dst.join(&entry.path)) };
+    counters.verified.fetch_add(1, Ordering::Relaxed);
+    match mismatch(entry, &copy, options.hash) {
+        Ok(None) => {}
+        Ok(Some(problem)) => {
+            counters.verify_mismatches.fetch_add(1, Ordering::Relaxed);
+            report_failure(options, counters, &entry.path, "Verification failed for", &io::Error::new(io::ErrorKind::InvalidData, problem));
+        }
+        Err(err) => report_failure(options, counters, &entry.path, "Couldn't verify", &err),
+    }
+}
+
+//What's wrong with the file compared to its entry, None when it matches. The size is checked first so
+//a file that's obviously wrong isn't hashed
+fn mismatch(entry: &ManifestEntry, path: &Path, algorithm: HashAlgorithm) -> io::Result<Option<String>> {
+    let metadata = std::fs::metadata(path)?;
+    if !metadata.is_file() {
+        return Ok(Some("the manifest gives a size or hash but this isn't a file".to_string()));
+    }
+    if let Some(size) = entry.size
+        && metadata.len() != size {
+        return Ok(Some(format!("{} bytes, the manifest says {}", metadata.len(), size)));
+    }
+    if let Some(hash) = &entry.hash
+        && hash_file(path, algorithm)? != *hash {
+        return Ok(Some(format!("{} hash doesn't match the manifest", algorithm.name())));
+    }
+    Ok(None)
+}
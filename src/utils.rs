@@ -7,15 +7,540 @@ Author: Dylan Morgan
 Date 4/11/2025
 *****************************************/
 
-use std::{path::PathBuf, time::Instant};
+use std::{collections::{BTreeSet, HashMap, HashSet}, ffi::OsString, fs::File, io::{self, Read, Write}, path::{Component, Path, PathBuf}, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc, Mutex}, time::{Duration, Instant, SystemTime}};
 use clap::ArgMatches;
+use indicatif::{style::ProgressTracker, HumanDuration, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
 use walkdir::DirEntry;
+use crate::config::{config_args, config_flags, negation};
+use crate::restore::IncludeManifest;
+use crate::verify::HashAlgorithm;
 use clap::{Arg, Command};
 
-#[derive(Debug)]
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CopyStats {
     pub files: u64,
     pub dirs: u64,
+    pub symlinks: u64,
+    pub timed_out: u64,
+    pub skipped: u64,
+    pub bytes: u64,
+    pub errors: u64,
+    pub ignored_errors: u64,
+    pub collisions: u64,
+    pub hidden_skipped: u64,
+    pub symlink_loops: u64,
+    pub unreadable: u64,
+    pub verified: u64,
+    pub verify_mismatches: u64,
+    pub deleted: u64,
+    pub trashed: u64,
+    pub resumed: u64,
+    pub devices: u64,
+    pub deduplicated: u64,
+    pub linked_previous: u64, //Unchanged files hard linked from the --link-dest backup
+    pub size_mismatches: u64,
+    pub forced_overwrites: u64,
+    pub kept_newer: u64, //Destination files --no-clobber-newer left alone
+    pub kept_nonempty: u64, //Destination files --clobber-empty-only left alone
+    pub renamed_conflicts: u64, //Files --rename-conflicts wrote under a numbered name
+    pub repaired: u64, //Destination files --repair found at the wrong size and copied again
+    pub repair_intact: u64, //Destination files --repair found at the right size and left alone
+    pub copy_millis: u64, //How long copying the files took, for the throughput
+    pub peak_rate: u64, //Bytes per second, only sampled with --throughput
+    pub skipped_empty: u64,
+    pub skipped_symlinks: u64,
+    pub skipped_not_executable: u64,
+    pub skipped_recent: u64,
+    pub skipped_too_long: u64, //Entries --skip-too-long left out, their name is longer than the destination allows
+    pub permission_failures: u64,
+    pub deferred: u64, //Files left for a later run by --max-bytes
+    pub deferred_bytes: u64,
+    pub failed_dirs: Vec<(PathBuf, u64)>, //Directories that couldn't be created and how many files were skipped under each
+    pub exclude_hits: Vec<(String, u64)>, //Files each --exclude pattern skipped, patterns that skipped nothing aren't listed
+}
+
+impl CopyStats {
+    //Function to squash the stats into one greppable line for --oneline-summary, e.g.
+    //rcpy: 1234 files, 56 dirs, 7.8 GiB, 3 errors, 12.3s
+    //Every field is named here on purpose so a new counter won't compile until it's added
+    pub fn oneline(&self, duration: Duration, options: &CopyOptions) -> String {
+        let CopyStats {
+            files, dirs, symlinks, timed_out, skipped, bytes, errors, ignored_errors,
+            collisions, hidden_skipped, symlink_loops, unreadable, verified, verify_mismatches, deleted, trashed, resumed, devices, deduplicated, linked_previous, size_mismatches, forced_overwrites, kept_newer, kept_nonempty, renamed_conflicts, repaired, repair_intact, copy_millis: _, peak_rate,
+            skipped_empty, skipped_symlinks, skipped_not_executable, skipped_recent, skipped_too_long, permission_failures, deferred, deferred_bytes, failed_dirs, exclude_hits: _,
+        } = self;
+
+        let mut parts = vec![
+            format!("{} files", files),
+            format!("{} dirs", dirs),
+            format_bytes(*bytes, options.si),
+            format!("{} errors", errors),
+        ];
+        let optional = [
+            (*symlinks, "symlinks"),
+            (*skipped, "skipped"),
+            (*timed_out, "timed out"),
+            (*ignored_errors, "ignored errors"),
+            (*collisions, "collisions"),
+            (*hidden_skipped, "hidden skipped"),
+            (*symlink_loops, "symlink loops"),
+            (*unreadable, "unreadable"),
+            (*verified, "verified"),
+            (*verify_mismatches, "mismatches"),
+            (*deleted, "deleted"),
+            (*trashed, "trashed"),
+            (*resumed, "already done"),
+            (*devices, "devices"),
+            (*deduplicated, "hard linked"),
+            (*linked_previous, "linked from --link-dest"),
+            (*size_mismatches, "wrong size"),
+            (*forced_overwrites, "read-only overwritten"),
+            (*kept_newer, "newer kept"),
+            (*kept_nonempty, "non-empty kept"),
+            (*renamed_conflicts, "conflicts renamed"),
+            (*repaired, "repaired"),
+            (*repair_intact, "intact"),
+            (*skipped_empty, "empty skipped"),
+            (*skipped_symlinks, "symlinks skipped"),
+            (*skipped_not_executable, "not executable skipped"),
+            (*skipped_recent, "too recent"),
+            (*skipped_too_long, "name too long"),
+            (*permission_failures, "permissions not set"),
+            (*deferred, "left over --max-bytes"),
+            (failed_dirs.iter().map(|(_, skipped)| skipped).sum(), "skipped under failed dirs"),
+        ];
+        parts.extend(optional.iter().filter(|(count, _)| *count > 0).map(|(count, label)| format!("{} {}", count, label)));
+        if *deferred_bytes > 0 {
+            parts.push(format!("{} left", format_bytes(*deferred_bytes, options.si)));
+        }
+        if options.throughput {
+            parts.push(format!("{}/s avg", format_bytes(self.average_rate(), options.si)));
+            parts.push(format!("{}/s peak", format_bytes(*peak_rate, options.si)));
+        }
+        parts.push(format!("{:.1}s", duration.as_secs_f64()));
+        format!("rcpy: {}{}", if options.dry_run { "(dry run) " } else { "" }, parts.join(", "))
+    }
+
+    //Function to print the summary for --format oneline/json, false when the full text summary should be printed instead
+    pub fn print_compact(&self, duration: Duration, options: &CopyOptions) -> bool {
+        match options.summary {
+            SummaryFormat::Text => return false,
+            SummaryFormat::Oneline => println!("{}", self.oneline(duration, options)),
+            #[cfg(feature = "serde")]
+            SummaryFormat::Json => {
+                let json = serde_json::json!({
+                    "dry_run": options.dry_run,
+                    "duration_secs": duration.as_secs_f64(),
+                    "stats": self,
+                });
+                println!("{}", json);
+            }
+        }
+        true
+    }
+
+    //Bytes per second over the file copying phase
+    pub fn average_rate(&self) -> u64 {
+        if self.copy_millis == 0 {
+            return 0;
+        }
+        self.bytes.saturating_mul(1000) / self.copy_millis
+    }
+
+    //Function to fold another run's stats into these, used when a glob source is copied one match at a time
+    pub fn add(&mut self, other: CopyStats) {
+        let CopyStats {
+            files, dirs, symlinks, timed_out, skipped, bytes, errors, ignored_errors,
+            collisions, hidden_skipped, symlink_loops, unreadable, verified, verify_mismatches, deleted, trashed, resumed, devices, deduplicated, linked_previous, size_mismatches, forced_overwrites, kept_newer, kept_nonempty, renamed_conflicts, repaired, repair_intact, copy_millis, peak_rate,
+            skipped_empty, skipped_symlinks, skipped_not_executable, skipped_recent, skipped_too_long, permission_failures, deferred, deferred_bytes, failed_dirs, exclude_hits,
+        } = other;
+
+        self.files += files;
+        self.dirs += dirs;
+        self.symlinks += symlinks;
+        self.timed_out += timed_out;
+        self.skipped += skipped;
+        self.bytes += bytes;
+        self.errors += errors;
+        self.ignored_errors += ignored_errors;
+        self.collisions += collisions;
+        self.hidden_skipped += hidden_skipped;
+        self.symlink_loops += symlink_loops;
+        self.unreadable += unreadable;
+        self.verified += verified;
+        self.verify_mismatches += verify_mismatches;
+        self.deleted += deleted;
+        self.trashed += trashed;
+        self.resumed += resumed;
+        self.devices += devices;
+        self.deduplicated += deduplicated;
+        self.linked_previous += linked_previous;
+        self.size_mismatches += size_mismatches;
+        self.forced_overwrites += forced_overwrites;
+        self.kept_newer += kept_newer;
+        self.kept_nonempty += kept_nonempty;
+        self.renamed_conflicts += renamed_conflicts;
+        self.repaired += repaired;
+        self.repair_intact += repair_intact;
+        self.copy_millis += copy_millis;
+        self.peak_rate = self.peak_rate.max(peak_rate);
+        self.skipped_empty += skipped_empty;
+        self.skipped_symlinks += skipped_symlinks;
+        self.skipped_not_executable += skipped_not_executable;
+        self.skipped_recent += skipped_recent;
+        self.skipped_too_long += skipped_too_long;
+        self.permission_failures += permission_failures;
+        self.deferred += deferred;
+        self.deferred_bytes += deferred_bytes;
+        self.failed_dirs.extend(failed_dirs);
+        for (pattern, hits) in exclude_hits {
+            match self.exclude_hits.iter_mut().find(|(existing, _)| *existing == pattern) {
+                Some((_, total)) => *total += hits,
+                None => self.exclude_hits.push((pattern, hits)),
+            }
+        }
+    }
+
+    //The --exclude patterns that didn't skip a single file, for --strict-excludes
+    pub fn unused_excludes<'a>(&self, options: &'a CopyOptions) -> Vec<&'a String> {
+        options.excludes.iter().filter(|pattern| !self.exclude_hits.iter().any(|(hit, count)| hit == *pattern && *count > 0)).collect()
+    }
+}
+
+//Mode from --chmod/--chmod-dir, either octal (644) or symbolic (u+rwx,go-w) applied on top of the source mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeSpec {
+    Octal(u32),
+    Symbolic(Vec<ModeClause>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeClause {
+    who: u32,   //Mask of the bits this clause touches (u/g/o)
+    op: char,   //+, - or =
+    perms: String,
+}
+
+impl ModeSpec {
+    pub fn parse(value: &str) -> Result<ModeSpec, String> {
+        if !value.is_empty() && value.chars().all(|c| c.is_digit(8)) {
+            return u32::from_str_radix(value, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .map(ModeSpec::Octal)
+                .ok_or_else(|| format!("{} is not a valid octal mode", value));
+        }
+
+        let mut clauses = Vec::new();
+        for clause in value.split(',') {
+            let op_at = clause.find(['+', '-', '=']).ok_or_else(|| format!("{} is missing +, - or =", clause))?;
+            let (who_part, rest) = clause.split_at(op_at);
+            let op = rest.chars().next().unwrap();
+            let perms = &rest[1..];
+
+            let mut who = 0;
+            for c in who_part.chars() {
+                who |= match c {
+                    'u' => 0o4700,
+                    'g' => 0o2070,
+                    'o' => 0o1007,
+                    'a' => 0o7777,
+                    _ => return Err(format!("unknown user class '{}' in {}", c, clause)),
+                };
+            }
+            if who == 0 {
+                who = 0o7777;
+            }
+            if let Some(bad) = perms.chars().find(|c| !"rwxXst".contains(*c)) {
+                return Err(format!("unknown permission '{}' in {}", bad, clause));
+            }
+            clauses.push(ModeClause { who, op, perms: perms.to_string() });
+        }
+        Ok(ModeSpec::Symbolic(clauses))
+    }
+
+    //Function to work out the final mode from the source's mode
+    pub fn apply(&self, base: u32, is_dir: bool) -> u32 {
+        let clauses = match self {
+            ModeSpec::Octal(mode) => return *mode,
+            ModeSpec::Symbolic(clauses) => clauses,
+        };
+
+        let mut mode = base & 0o7777;
+        for clause in clauses {
+            let mut bits = 0;
+            for c in clause.perms.chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => 0,
+                };
+            }
+            bits &= clause.who;
+            match clause.op {
+                '+' => mode |= bits,
+                '-' => mode &= !bits,
+                _ => mode = (mode & !clause.who) | bits,
+            }
+        }
+        mode
+    }
+}
+
+//Order entries are walked (and so created/copied) in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkOrder {
+    DepthFirst,
+    BreadthFirst,
+}
+
+//How multi-threaded copies split up the work, see --parallel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParallelStrategy {
+    Files, //Every file goes into one shared pool
+    Dirs, //One task per top-level entry, each copying its subtree in order
+}
+
+//How the summary at the end is printed, everything but Text also leaves out the banners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryFormat {
+    Text,
+    Oneline,
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+impl SummaryFormat {
+    pub const ALL: &[SummaryFormat] = &[
+        SummaryFormat::Text,
+        SummaryFormat::Oneline,
+        #[cfg(feature = "serde")]
+        SummaryFormat::Json,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SummaryFormat::Text => "text",
+            SummaryFormat::Oneline => "oneline",
+            #[cfg(feature = "serde")]
+            SummaryFormat::Json => "json",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<SummaryFormat, String> {
+        SummaryFormat::ALL.iter()
+            .copied()
+            .find(|format| format.name().eq_ignore_ascii_case(value))
+            .ok_or_else(|| {
+                let names: Vec<_> = SummaryFormat::ALL.iter().map(|format| format.name()).collect();
+                format!("unknown format {} (available: {})", value, names.join(", "))
+            })
+    }
+}
+
+//Errors from checking the copy before it starts, left to the caller to decide what to do with
+#[derive(Debug)]
+pub enum CopyError {
+    SamePath(PathBuf),
+    BadPattern(String, glob::PatternError),
+    NoMatches(String),
+}
+
+impl std::fmt::Display for CopyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CopyError::SamePath(path) => write!(f, "Source and destination paths are the same! ({})", path.display()),
+            CopyError::BadPattern(pattern, e) => write!(f, "invalid source pattern {}: {}", pattern, e),
+            CopyError::NoMatches(pattern) => write!(f, "nothing matches the source pattern {}", pattern),
+        }
+    }
+}
+
+impl std::error::Error for CopyError {}
+
+//Function to make sure source and destination aren't the same place, both are canonicalized
+//so ./a and a (or a path through a symlink) count as the same
+pub fn validate_paths(src: &Path, dst: &Path) -> Result<(), CopyError> {
+	let same = match (std::fs::canonicalize(src), canonicalize_partial(dst)) {
+		(Ok(real_src), Ok(real_dst)) => real_src == real_dst,
+		_ => src == dst,
+	};
+	if same {
+		return Err(CopyError::SamePath(src.to_path_buf()));
+	}
+	Ok(())
+}
+
+//Function to tell if two paths are the same file, through symlinks and .. (canonicalized) or hard links
+//(same device and inode on Unix). Paths that don't exist yet can't be the same file
+pub fn same_file(a: &Path, b: &Path) -> bool {
+	match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+		(Ok(real_a), Ok(real_b)) => real_a == real_b || same_inode(&real_a, &real_b),
+		_ => false,
+	}
+}
+
+#[cfg(unix)]
+fn same_inode(a: &Path, b: &Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	match (std::fs::metadata(a), std::fs::metadata(b)) {
+		(Ok(meta_a), Ok(meta_b)) => meta_a.dev() == meta_b.dev() && meta_a.ino() == meta_b.ino(),
+		_ => false,
+	}
+}
+
+//std has no stable file index on Windows, there the canonical path has to do
+#[cfg(not(unix))]
+fn same_inode(_a: &Path, _b: &Path) -> bool {
+	false
+}
+
+//Function to expand a quoted glob source (rcpy 'logs/*.txt' dst), None when the source isn't a pattern
+//A path that exists as typed is always taken literally, even with * or [ in its name, and like a shell
+//a * doesn't match dotfiles. An empty match is an error rather than a copy of the literal pattern
+pub fn expand_source(source: &str) -> Result<Option<Vec<PathBuf>>, CopyError> {
+	if !source.contains(['*', '?', '[']) || Path::new(source).exists() {
+		return Ok(None);
+	}
+	let match_options = glob::MatchOptions { require_literal_leading_dot: true, ..Default::default() };
+	let matches: Vec<PathBuf> = glob::glob_with(source, match_options)
+		.map_err(|e| CopyError::BadPattern(source.to_string(), e))?
+		.filter_map(Result::ok)
+		.collect();
+	if matches.is_empty() {
+		return Err(CopyError::NoMatches(source.to_string()));
+	}
+	Ok(Some(matches))
+}
+
+//Counters (and other bookkeeping) shared between copy workers while a copy is running
+#[derive(Debug, Default)]
+pub struct CopyCounters {
+    pub files: AtomicU64,
+    pub dirs: AtomicU64,
+    pub symlinks: AtomicU64,
+    pub timed_out: AtomicU64,
+    pub skipped: AtomicU64,
+    pub bytes: AtomicU64,
+    pub errors: AtomicU64,
+    pub ignored_errors: AtomicU64,
+    pub collisions: AtomicU64,
+    pub hidden_skipped: AtomicU64,
+    pub symlink_loops: AtomicU64,
+    pub unreadable: AtomicU64,
+    pub verified: AtomicU64,
+    pub verify_mismatches: AtomicU64,
+    pub deleted: AtomicU64,
+    pub trashed: AtomicU64,
+    pub resumed: AtomicU64,
+    pub devices: AtomicU64,
+    pub deduplicated: AtomicU64,
+    pub linked_previous: AtomicU64,
+    pub size_mismatches: AtomicU64,
+    pub forced_overwrites: AtomicU64,
+    pub kept_newer: AtomicU64,
+    pub kept_nonempty: AtomicU64,
+    pub renamed_conflicts: AtomicU64,
+    pub repaired: AtomicU64,
+    pub repair_intact: AtomicU64,
+    pub copy_millis: AtomicU64,
+    pub peak_rate: AtomicU64,
+    pub skipped_empty: AtomicU64,
+    pub skipped_symlinks: AtomicU64,
+    pub skipped_not_executable: AtomicU64,
+    pub skipped_recent: AtomicU64,
+    pub skipped_too_long: AtomicU64,
+    pub permission_failures: AtomicU64,
+    pub deferred: AtomicU64,
+    pub deferred_bytes: AtomicU64,
+    pub budget_used: AtomicU64, //Bytes promised to files so far for --max-bytes
+    pub budget_full: AtomicBool, //Set once a file didn't fit, no new copies start after that
+    pub failed_dirs: Mutex<HashMap<PathBuf, u64>>,
+    pub exclude_hits: Mutex<HashMap<String, u64>>, //--exclude pattern -> files it skipped
+    pub claimed: Mutex<HashMap<PathBuf, PathBuf>>, //Transformed destination -> source that claimed it
+    pub dereferenced: Mutex<HashMap<PathBuf, PathBuf>>, //Resolved source -> its first copy, for --dereference
+    pub hard_linked: Mutex<HashMap<(u64, u64), PathBuf>>, //Source device and inode -> its first copy, for --preserve links
+    pub to_verify: Mutex<Vec<(PathBuf, PathBuf, PathBuf)>>, //Source, copy and relative path of each file picked for verification
+    pub planned: Mutex<Vec<(PathBuf, Option<u64>)>>, //Destination relative path and file size (None for directories), for --tree
+    pub to_sync: Mutex<HashSet<PathBuf>>, //Directories files were copied into, fsynced at the end with --fsync
+    pub dir_times: Mutex<Vec<(PathBuf, std::fs::FileTimes)>>, //Destination directories and the times they get at the end
+    pub dir_permissions: Mutex<Vec<(PathBuf, PathBuf)>>, //Source and destination of directories whose permissions are copied last
+}
+
+//What --preserve asked for, a cp style list (mode,ownership,timestamps,links,xattr,all) that main folds into
+//the individual preserve options. all also marks that unsupported items are skipped quietly
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreserveList {
+    pub mode: bool,
+    pub ownership: bool,
+    pub timestamps: bool,
+    pub links: bool,
+    pub xattr: bool,
+    pub all: bool,
+}
+
+impl PreserveList {
+    const ITEMS: &[&str] = &["mode", "ownership", "timestamps", "links", "xattr", "all"];
+
+    pub fn parse(value: &str) -> Result<PreserveList, String> {
+        let mut list = PreserveList::default();
+        for item in value.split(',') {
+            match item.trim().to_ascii_lowercase().as_str() {
+                "mode" => list.mode = true,
+                "ownership" => list.ownership = true,
+                "timestamps" => list.timestamps = true,
+                "links" => list.links = true,
+                "xattr" => list.xattr = true,
+                "all" => list = PreserveList { mode: true, ownership: true, timestamps: true, links: true, xattr: true, all: true },
+                other => return Err(format!("unknown item {} (valid: {})", other, PreserveList::ITEMS.join(", "))),
+            }
+        }
+        Ok(list)
+    }
+}
+
+//How progress is shown while copying, the visual bar, PROGRESS/DONE lines on stderr for GUI frontends or a
+//plain percentage line every few seconds for logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    Bar,
+    Porcelain,
+    Periodic,
+}
+
+//How often --progress periodic prints when neither --progress-interval nor --progress-refresh says
+pub const PERIODIC_INTERVAL: Duration = Duration::from_secs(5);
+
+//One --rename rule, PATTERN=>REPLACEMENT applied to each file's name with $1/${name} groups available
+#[derive(Debug, Clone)]
+pub struct RenameRule {
+    pattern: regex::Regex,
+    replacement: String,
+}
+
+impl RenameRule {
+    pub fn parse(value: &str) -> Result<RenameRule, String> {
+        let (pattern, replacement) = value.split_once("=>")
+            .ok_or_else(|| format!("{} is missing => (e.g. '\\.jpeg$=>.jpg')", value))?;
+        let pattern = regex::Regex::new(pattern).map_err(|e| format!("invalid pattern {}: {}", pattern, e))?;
+        Ok(RenameRule { pattern, replacement: replacement.to_string() })
+    }
+
+    pub fn apply(&self, name: &str) -> String {
+        self.pattern.replace_all(name, self.replacement.as_str()).into_owned()
+    }
+}
+
+//What to do when a name transform maps two source files onto the same destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    Fail,
+    Rename,
 }
 
 #[derive(Debug)]
@@ -23,32 +548,924 @@ pub struct CopyOptions {
 	pub source: PathBuf,
     pub show_files: bool,
     pub show_dirs: bool,
+    pub verbose: bool,
     pub recursive: bool,
 	pub dry_run: bool,
+    pub tree: bool,
+    pub strict: bool, //With dry_run, probe each destination directory for writability
     pub excludes: Vec<String>,
+    pub exclude_empty: bool,
+    pub exclude_symlinks: bool,
+    pub copy_contents: bool,
+    pub only_executable: bool,
+    pub newer_than: Option<SystemTime>, //Modification time of the --newer-than-file reference
+    pub recent_cutoff: Option<SystemTime>, //Files modified after this are skipped by --skip-recent
+    pub exclude_names: Vec<&'static str>, //Whole file/directory names, seeded by --exclude-vcs
+    pub strict_excludes: bool,
+    pub name_max: u64, //Longest name in bytes the destination filesystem takes, longer ones are warned about (or skipped)
+    pub skip_too_long: bool,
+    pub atomic: bool,
+    pub fsync: bool,
+    pub temp_dir: Option<PathBuf>,
+    pub timeout: Option<Duration>,
+    pub append: bool,
+    pub overwrite_readonly: bool,
+    pub no_clobber_newer: bool,
+    pub clobber_empty_only: bool,
+    pub rename_conflicts: bool,
+    pub repair: bool, //Only copy files missing from the destination or at a different size than the source
+    pub link_dest: Option<PathBuf>, //Previous backup unchanged files are hard linked from
+    pub files_from: Option<FileList>,
+    pub manifest: Option<IncludeManifest>,
+    pub git_deleted: Vec<PathBuf>, //Paths --since-git-delete removes from the destination, relative to it
+    pub structure_only: bool,
+    pub si: bool,
+    pub base: Option<PathBuf>,
+    pub error_log: Option<ErrorLog>,
+    pub checkpoint: Option<Checkpoint>,
+    pub ignore_errors: Vec<glob::Pattern>,
+    pub quiet_errors: bool,
+    pub abort_on_walk_error: bool,
+    pub preserve_crtime: bool,
+    pub preserve_times: bool, //Modification and access times
+    pub preserve_owner: bool,
+    pub preserve_xattrs: bool,
+    pub lowercase_dest: bool,
+    pub renames: Vec<RenameRule>, //--rename rules, applied in order to file names
+    pub strip_components: usize, //Leading source-relative components dropped from every destination path
+    pub no_hidden: bool,
+    pub progress_refresh: Option<u8>,
+    pub eta_smoothing: Option<f64>,
+    pub progress: ProgressMode,
+    pub progress_file: Option<ProgressFile>,
+    pub progress_interval: Option<Duration>, //Report progress on this timer instead of as files finish
+    pub verify: bool,
+    pub verify_sample: Option<f64>, //Percent of files to verify
+    pub hash: HashAlgorithm,
+    pub verify_size: bool, //Also on with --verify, a size check right after each copy
+    pub seed: u64,
+    pub order: WalkOrder,
+    pub ordered: bool, //Sorted walk on one thread, for a destination created in the same order every run
+    pub parallel: ParallelStrategy,
+    pub preserve_flags: bool,
+    pub preserve_resource_fork: bool,
+    pub devices: bool,
+    pub dereference: bool,
+    pub preserve_links: bool, //Files hard linked together in the source are hard linked in the copy too
+    pub chmod: Option<ModeSpec>,
+    pub chmod_dir: Option<ModeSpec>,
+    pub keep_dest_dir_perms: bool,
+    pub keep_dest_dir_times: bool,
+    pub data_only: bool, //Only contents, permissions and every other bit of metadata are left at the defaults
+    pub throughput: bool, //Average and peak copy rate in the summary
+    pub absolute_paths: bool,
+    pub summary: SummaryFormat,
+    pub delete: bool,
+    pub trash: bool,
+    pub max_files: Option<u64>, //None when unlimited or overridden with --force
+    pub max_bytes: Option<u64>,
+    pub buffer_size: Option<usize>, //Streaming copy chunk size, None picks one from the destination's block size
+    pub permissions: bool, //False when the destination can't store Unix permissions (FAT/exFAT)
+    pub collision: CollisionPolicy,
+    pub nested_dest: Option<PathBuf>, //Canonical destination, only set when it lives inside the source
 }
 
-//Function to help determine if an entry is excluded based on the extension it has
-pub fn is_excluded(entry: &DirEntry, excludes: &[String]) -> bool {
-	if let Some(ext) = entry.path().extension().and_then(|e| e.to_str()) {
-		excludes.iter().any(|ex| ex.trim_start_matches('.').eq_ignore_ascii_case(ext))
-	} else {
-		false
+//Failures written to --error-log, one tab separated line each: path, error kind, message
+//The path is relative to the source so `cut -f1` of the log can be fed back through --files-from
+#[derive(Debug)]
+pub struct ErrorLog {
+    file: Mutex<File>,
+}
+
+impl ErrorLog {
+    //Creates (or truncates) the log right away so it exists even when nothing fails
+    pub fn create(path: &Path) -> io::Result<ErrorLog> {
+        Ok(ErrorLog { file: Mutex::new(File::create(path)?) })
+    }
+
+    pub fn record(&self, path: &Path, kind: io::ErrorKind, message: &str) {
+        let message = message.replace(['\t', '\n', '\r'], " ");
+        let line = format!("{}\t{:?}\t{}\n", path.display(), kind, message);
+        //Written straight through (no buffering) so nothing is lost if we exit early
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+//Snapshots for --progress-file, appended as lines so `tail -f` follows a detached run, e.g.
+//[00:12:34] 45.2% 1.2 GiB/2.6 GiB, 1234 files, 0 errors, ETA 00:10:00
+#[derive(Debug)]
+pub struct ProgressFile {
+    file: Mutex<File>,
+}
+
+impl ProgressFile {
+    pub fn create(path: &Path) -> io::Result<ProgressFile> {
+        Ok(ProgressFile { file: Mutex::new(File::create(path)?) })
+    }
+
+    fn snapshot(&self, snapshot: &ProgressSnapshot, eta: Duration, options: &CopyOptions) {
+        let percent = if snapshot.total_bytes == 0 { 100.0 } else { snapshot.bytes as f64 * 100.0 / snapshot.total_bytes as f64 };
+        self.write_line(snapshot.elapsed, &format!(
+            "{:.1}% {}/{}, {} files, {} errors, ETA {}",
+            percent,
+            format_bytes(snapshot.bytes, options.si),
+            format_bytes(snapshot.total_bytes, options.si),
+            snapshot.files,
+            snapshot.errors,
+            format_hms(eta),
+        ));
+    }
+
+    //The run's summary, the last line in the file
+    pub fn finish(&self, stats: &CopyStats, duration: Duration, options: &CopyOptions) {
+        self.write_line(duration, &stats.oneline(duration, options));
+    }
+
+    fn write_line(&self, elapsed: Duration, line: &str) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "[{}] {}", format_hms(elapsed), line);
+        }
+    }
+}
+
+fn format_hms(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+//Record of the files a run has finished, so --resume can skip them without looking at the destination
+//Format: the header line below, then each finished path (relative to the source) ending in a NUL byte
+//NUL can't be part of a path so any name works, and a record cut off by a crash simply has no NUL yet
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    done: HashSet<PathBuf>,
+    file: Option<Mutex<File>>, //None in a dry run, the checkpoint is only read then
+}
+
+const CHECKPOINT_HEADER: &[u8] = b"rcpy-checkpoint 1\n";
+
+impl Checkpoint {
+    //Without resume the checkpoint starts over, with it the finished paths are loaded and new ones appended
+    //A file that isn't a checkpoint is warned about and replaced rather than failing the run
+    pub fn open(path: &Path, resume: bool, dry_run: bool) -> io::Result<Checkpoint> {
+        let mut done = HashSet::new();
+        let mut valid_len = None;
+        if resume {
+            match std::fs::read(path) {
+                Ok(raw) => match raw.strip_prefix(CHECKPOINT_HEADER) {
+                    Some(records) => {
+                        //Anything after the last NUL is a record that never finished being written
+                        let complete = records.iter().rposition(|b| *b == 0).map_or(0, |end| end + 1);
+                        done.extend(records[..complete].split(|b| *b == 0).filter(|r| !r.is_empty()).map(path_from_bytes));
+                        valid_len = Some((CHECKPOINT_HEADER.len() + complete) as u64);
+                    }
+                    None => eprintln!("Warning: {} isn't an rcpy checkpoint, starting over", path.display()),
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let file = if dry_run {
+            None
+        } else if let Some(len) = valid_len {
+            //Cut off a partial record so the next one doesn't get glued onto it
+            let file = std::fs::OpenOptions::new().append(true).open(path)?;
+            file.set_len(len)?;
+            Some(Mutex::new(file))
+        } else {
+            let mut file = File::create(path)?;
+            file.write_all(CHECKPOINT_HEADER)?;
+            Some(Mutex::new(file))
+        };
+        Ok(Checkpoint { path: path.to_path_buf(), done, file })
+    }
+
+    pub fn is_done(&self, rel_path: &Path) -> bool {
+        self.done.contains(rel_path)
+    }
+
+    pub fn record(&self, rel_path: &Path) {
+        let mut record = path_to_bytes(rel_path);
+        record.push(0);
+        //One write per record, straight through, so a crash loses at most the file in flight
+        if let Some(file) = &self.file
+            && let Ok(mut file) = file.lock() {
+            let _ = file.write_all(&record);
+        }
+    }
+
+    //Nothing left to resume after a clean run
+    pub fn finish(&self) {
+        if self.file.is_some()
+            && let Err(e) = std::fs::remove_file(&self.path) {
+            eprintln!("Warning: couldn't remove checkpoint {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+//Paths read from --files-from, relative to the source
+//A listed directory brings everything under it along, and every parent of a listed path is kept so it can be created
+#[derive(Debug, Default)]
+pub struct FileList {
+    listed: HashSet<PathBuf>,
+    parents: HashSet<PathBuf>,
+}
+
+impl FileList {
+    //Reads a list from a file (or stdin for "-"), entries separated by newlines or NUL bytes with --null
+    pub fn read(path: &Path, null_separated: bool) -> io::Result<FileList> {
+        let mut raw = Vec::new();
+        if path == Path::new("-") {
+            io::stdin().read_to_end(&mut raw)?;
+        } else {
+            File::open(path)?.read_to_end(&mut raw)?;
+        }
+
+        let separator = if null_separated { b'\0' } else { b'\n' };
+        let mut list = FileList::default();
+        for entry in raw.split(|b| *b == separator) {
+            //Tolerate CRLF lists unless we are in NUL mode where \r could be part of a name
+            let entry = if !null_separated { entry.strip_suffix(b"\r").unwrap_or(entry) } else { entry };
+            if entry.is_empty() {
+                continue;
+            }
+            list.insert(&path_from_bytes(entry));
+        }
+        Ok(list)
+    }
+
+    //A list made from paths we already have, like the files --since-git finds
+    pub fn from_paths(paths: &[PathBuf]) -> FileList {
+        let mut list = FileList::default();
+        for path in paths {
+            list.insert(path);
+        }
+        list
+    }
+
+    fn insert(&mut self, path: &Path) {
+        //Drop ./ prefixes and trailing separators so entries line up with what the walker produces
+        let cleaned: PathBuf = path.components().filter(|c| !matches!(c, Component::CurDir)).collect();
+        for parent in cleaned.ancestors().skip(1) {
+            self.parents.insert(parent.to_path_buf());
+        }
+        self.listed.insert(cleaned);
+    }
+
+    //Function to check if a walked entry (relative to the source) should be copied
+    pub fn wants(&self, rel_path: &Path, is_dir: bool) -> bool {
+        if rel_path.ancestors().any(|p| self.listed.contains(p)) {
+            return true;
+        }
+        is_dir && self.parents.contains(rel_path)
+    }
+}
+
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(unix)]
+pub fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+//Version control metadata skipped by --exclude-vcs, the directories and their ignore/config files
+pub const VCS_NAMES: [&str; 11] = [
+	".git", ".svn", ".hg", ".bzr", "CVS",
+	".gitignore", ".gitattributes", ".gitmodules", ".hgignore", ".bzrignore", ".cvsignore",
+];
+
+//Function to check a file or directory name against the excluded names (whole name, case sensitive)
+pub fn is_excluded_name(name: &std::ffi::OsStr, exclude_names: &[&str]) -> bool {
+	exclude_names.iter().any(|excluded| name == *excluded)
+}
+
+//Why should_copy turned a file down, the attribute filters are counted separately in the summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+	Excluded,
+	NotNewer,
+	Empty,
+	Symlink,
+	NotExecutable,
+	Recent,
+}
+
+//Function with every per-file filter in one place, false means the file is left out of the copy
+pub fn should_copy(path: &Path, options: &CopyOptions) -> bool {
+	skip_reason(path, options).is_none()
+}
+
+//Metadata is only read when a filter needs it
+pub fn skip_reason(path: &Path, options: &CopyOptions) -> Option<SkipReason> {
+	if is_excluded_path(path, &options.excludes) {
+		return Some(SkipReason::Excluded);
+	}
+	if options.exclude_symlinks && path.is_symlink() {
+		return Some(SkipReason::Symlink);
+	}
+	if options.newer_than.is_none() && options.recent_cutoff.is_none() && !options.exclude_empty && !options.only_executable {
+		return None;
+	}
+
+	//Links are followed, it's the target that gets copied
+	let Ok(metadata) = std::fs::metadata(path) else {
+		return None; //Let the copy itself report it
+	};
+	if let Some(reference) = options.newer_than {
+		//Same as find -newer, anything we can't read the time of isn't newer
+		if !metadata.modified().is_ok_and(|modified| modified > reference) {
+			return Some(SkipReason::NotNewer);
+		}
+	}
+	//A file written to within --skip-recent (or dated in the future) may still be half written
+	if let Some(cutoff) = options.recent_cutoff
+		&& metadata.modified().is_ok_and(|modified| modified > cutoff) {
+		return Some(SkipReason::Recent);
+	}
+	if options.exclude_empty && metadata.len() == 0 {
+		return Some(SkipReason::Empty);
+	}
+	if options.only_executable && !is_executable(&metadata) {
+		return Some(SkipReason::NotExecutable);
+	}
+	None
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+	use std::os::unix::fs::PermissionsExt;
+	metadata.permissions().mode() & 0o111 != 0
+}
+
+//There's no execute bit on Windows, so --only-executable lets everything through
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+	true
+}
+
+//Function to help determine if a path is excluded based on the extension it has
+pub fn is_excluded_path(path: &Path, excludes: &[String]) -> bool {
+	matching_exclude(path, excludes).is_some()
+}
+
+//Function to find which --exclude pattern a path falls under, so each one's hits can be counted
+pub fn matching_exclude<'a>(path: &Path, excludes: &'a [String]) -> Option<&'a String> {
+	let ext = path.extension().and_then(|e| e.to_str())?;
+	excludes.iter().find(|ex| ex.trim_start_matches('.').eq_ignore_ascii_case(ext))
+}
+
+//Function to count a skipped file against the --exclude pattern that skipped it
+pub fn count_exclude_hit(path: &Path, options: &CopyOptions, counters: &CopyCounters) {
+	if let Some(pattern) = matching_exclude(path, &options.excludes) {
+		*counters.exclude_hits.lock().unwrap().entry(pattern.clone()).or_insert(0) += 1;
+	}
+}
+
+//Function to drop trailing (and doubled) separators, dir/ and dir// become dir so every walked entry strips the
+//source prefix the same way. Inner . components go too, .. is kept and a bare / or C:\ stays as it is
+pub fn trim_trailing_separators(path: &Path) -> PathBuf {
+	path.components().collect()
+}
+
+//Function to tidy a path up for display, collapses `.` components, doubled separators and `dir/..` pairs
+//This is purely lexical (no symlink resolution) so only use it for output, never for the actual copy
+pub fn normalize_path(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				match normalized.components().next_back() {
+					Some(Component::Normal(_)) => { normalized.pop(); }
+					Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+					_ => normalized.push(".."),
+				}
+			}
+			other => normalized.push(other.as_os_str()),
+		}
+	}
+	if normalized.as_os_str().is_empty() {
+		normalized.push(".");
+	}
+	normalized
+}
+
+//Function to pick how a path is shown in verbose output, as typed (relative stays relative) unless --absolute-paths
+pub fn display_path(path: &Path, options: &CopyOptions) -> PathBuf {
+	if options.absolute_paths && let Ok(absolute) = std::path::absolute(path) {
+		return normalize_path(&absolute);
+	}
+	normalize_path(path)
+}
+
+//Function to turn a byte count into something readable, 1536 -> "1.5 KiB"
+//With si set powers of 1000 are used instead (KB/MB/GB)
+pub fn format_bytes(bytes: u64, si: bool) -> String {
+	const IEC_UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+	const SI_UNITS: [&str; 6] = ["KB", "MB", "GB", "TB", "PB", "EB"];
+
+	let (base, units) = if si { (1000.0, SI_UNITS) } else { (1024.0, IEC_UNITS) };
+	if (bytes as f64) < base {
+		return format!("{} B", bytes);
+	}
+
+	//Compared after rounding to the one decimal shown, so just under a boundary prints 1.0 MiB and not 1024.0 KiB
+	let mut value = bytes as f64 / base;
+	let mut unit = 0;
+	while (value * 10.0).round() / 10.0 >= base && unit < units.len() - 1 {
+		value /= base;
+		unit += 1;
+	}
+	format!("{:.1} {}", value, units[unit])
+}
+
+//Function to read a size like 500M, 1.5GiB or 2GB, bare numbers are bytes. K/M/G/T alone and KiB/MiB... are
+//powers of 1024, KB/MB... powers of 1000 (same units format_bytes prints)
+pub fn parse_size(value: &str) -> Result<u64, String> {
+	let value = value.trim();
+	let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+	let (number, unit) = value.split_at(split);
+	let number: f64 = number.parse().map_err(|_| format!("{} is not a size (e.g. 500M, 2GiB)", value))?;
+	let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+		"" | "B" => 1,
+		"K" | "KIB" => 1 << 10,
+		"M" | "MIB" => 1 << 20,
+		"G" | "GIB" => 1 << 30,
+		"T" | "TIB" => 1 << 40,
+		"KB" => 1_000,
+		"MB" => 1_000_000,
+		"GB" => 1_000_000_000,
+		"TB" => 1_000_000_000_000,
+		_ => return Err(format!("unknown size unit {} (use K, M, G, T, KiB... or KB...)", unit.trim())),
+	};
+	Ok((number * multiplier as f64) as u64)
+}
+
+//Highest core count a --cpu-affinity list can name, CPU_SETSIZE on Linux (a cpu_set_t has no room past it)
+const MAX_CORES: usize = 1024;
+
+//Function to read a --cpu-affinity list like 0,2,4-7 into core ids, sorted with duplicates dropped. Ids are
+//checked against MAX_CORES before a range is expanded, whether the cores exist here is checked in main
+fn parse_cpu_list(value: &str) -> Result<Vec<usize>, String> {
+	let mut cores = BTreeSet::new();
+	for part in value.split(',').map(str::trim) {
+		let parse = |id: &str| {
+			let core = id.trim().parse::<usize>().map_err(|_| format!("{} is not a core id (e.g. 0,2,4-7)", id.trim()))?;
+			if core >= MAX_CORES {
+				return Err(format!("core {} is out of range, ids go up to {}", core, MAX_CORES - 1));
+			}
+			Ok(core)
+		};
+		let range = match part.split_once('-') {
+			Some((first, last)) => parse(first)?..=parse(last)?,
+			None => {
+				let id = parse(part)?;
+				id..=id
+			}
+		};
+		if range.is_empty() {
+			return Err(format!("{} is an empty range, write the lower core first", part));
+		}
+		cores.extend(range);
+	}
+	Ok(cores.into_iter().collect())
+}
+
+//Function to canonicalize a path that may not exist yet, the closest existing ancestor is resolved
+//and the missing components are appended to it
+pub fn canonicalize_partial(path: &Path) -> io::Result<PathBuf> {
+	let absolute = std::path::absolute(path)?;
+	let mut missing = Vec::new();
+	let mut existing = absolute.as_path();
+	while !existing.exists() {
+		missing.push(existing.file_name().unwrap_or_default().to_owned());
+		existing = match existing.parent() {
+			Some(parent) => parent,
+			None => break,
+		};
+	}
+	let mut resolved = std::fs::canonicalize(existing)?;
+	for component in missing.iter().rev() {
+		resolved.push(component);
+	}
+	Ok(resolved)
+}
+
+//Function to check if two paths live on the same filesystem, used to make sure a rename between them is atomic
+//The second path does not need to exist yet, its closest existing ancestor is used instead
+//Returns None when the platform can't tell us
+pub fn same_filesystem(a: &Path, b: &Path) -> Option<bool> {
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::MetadataExt;
+
+		let a_dev = std::fs::metadata(a).ok()?.dev();
+		let existing = b.ancestors().find(|p| p.exists())?;
+		let b_dev = std::fs::metadata(existing).ok()?.dev();
+		Some(a_dev == b_dev)
+	}
+	#[cfg(not(unix))]
+	{
+		let _ = (a, b);
+		None
+	}
+}
+
+//Function to check for dotfiles / dot directories
+pub fn is_hidden(entry: &DirEntry) -> bool {
+	entry.file_name().to_str().is_some_and(|name| name.starts_with('.') && name != "." && name != "..")
+}
+
+//Bar currently on screen. Worker output goes through print_line/print_error so every line comes out whole
+//and above the bar instead of tearing through it, the lock also keeps lines from different threads apart
+static ACTIVE_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+pub fn set_active_bar(pb: Option<&ProgressBar>) {
+	*ACTIVE_BAR.lock().unwrap() = pb.cloned();
+}
+
+pub fn print_line(line: std::fmt::Arguments) {
+	match &*ACTIVE_BAR.lock().unwrap() {
+		Some(pb) => pb.suspend(|| println!("{}", line)),
+		None => println!("{}", line),
+	}
+}
+
+pub fn print_error(line: std::fmt::Arguments) {
+	match &*ACTIVE_BAR.lock().unwrap() {
+		Some(pb) => pb.suspend(|| eprintln!("{}", line)),
+		None => eprintln!("{}", line),
+	}
+}
+
+//Sets how often a bar redraws, fewer redraws are much kinder to slow terminals and SSH sessions
+//With --progress porcelain or periodic bars are never drawn, progress is printed as lines instead
+pub fn set_progress_refresh(pb: &ProgressBar, options: &CopyOptions) {
+	if options.progress != ProgressMode::Bar {
+		pb.set_draw_target(ProgressDrawTarget::hidden());
+	} else if let Some(hz) = options.progress_refresh {
+		pb.set_draw_target(ProgressDrawTarget::stderr_with_hz(hz));
+	}
+}
+
+//Function to build a bar's style from its template, every bar goes through here so they all honour --eta-smoothing
+//The template is also fitted to the terminal's width here
+pub fn progress_style(template: &str, options: &CopyOptions) -> ProgressStyle {
+	let template = fit_template(template, bar_layout(terminal_width()));
+	let style = ProgressStyle::default_bar().template(&template).unwrap();
+	match options.eta_smoothing {
+		Some(factor) if factor > 0.0 => style.with_key("eta", SmoothedEta { factor, rate: None, sampled: None }),
+		_ => style,
+	}
+}
+
+//Below this many columns the bar templates don't fit on one line, the 40 column bar shrinks and the elapsed time goes
+const NARROW_TERMINAL: u16 = 80;
+const MIN_BAR_WIDTH: u16 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BarLayout {
+	Full,
+	Compact(u16), //Bar width
+	Text, //Width unknown, a percentage can't come out garbled
+}
+
+fn bar_layout(width: Option<u16>) -> BarLayout {
+	match width {
+		None => BarLayout::Text,
+		Some(width) if width >= NARROW_TERMINAL => BarLayout::Full,
+		Some(width) => BarLayout::Compact((width / 4).max(MIN_BAR_WIDTH)),
+	}
+}
+
+//COLUMNS wins when it's set so a CI job (or a test) can say what it has, otherwise the terminal is asked
+fn terminal_width() -> Option<u16> {
+	std::env::var("COLUMNS").ok()
+		.and_then(|columns| columns.trim().parse().ok())
+		.filter(|columns| *columns > 0)
+		.or_else(|| console::Term::stderr().size_checked().map(|(_, columns)| columns))
+}
+
+//Function to fit a bar template written for a normal terminal ({bar:40.colors}) to the layout
+fn fit_template(template: &str, layout: BarLayout) -> String {
+	let bar = regex::Regex::new(r"\{bar:40\.([^}]*)\}").unwrap();
+	match layout {
+		BarLayout::Full => template.to_string(),
+		BarLayout::Compact(width) => bar.replace(template, format!("{{bar:{}.$1}}", width)).replace(" [{elapsed_precise}]", ""),
+		BarLayout::Text => bar.replace(template, "{percent:>3}%").replace(" [{elapsed_precise}]", ""),
+	}
+}
+
+//{eta} for --eta-smoothing. indicatif's own rate is averaged again once a second, each time keeping FACTOR of
+//the old average, so a burst or a stall only moves the ETA a little. Until there's a rate it's indicatif's ETA
+#[derive(Clone)]
+struct SmoothedEta {
+	factor: f64,
+	rate: Option<f64>,
+	sampled: Option<Instant>,
+}
+
+impl ProgressTracker for SmoothedEta {
+	fn clone_box(&self) -> Box<dyn ProgressTracker> {
+		Box::new(self.clone())
+	}
+
+	fn tick(&mut self, state: &ProgressState, now: Instant) {
+		if self.sampled.is_some_and(|at| now.duration_since(at) < Duration::from_secs(1)) {
+			return;
+		}
+		self.sampled = Some(now);
+		let current = state.per_sec();
+		if current.is_finite() {
+			self.rate = Some(match self.rate {
+				Some(rate) => self.factor * rate + (1.0 - self.factor) * current,
+				None => current,
+			});
+		}
+	}
+
+	fn reset(&mut self, _state: &ProgressState, _now: Instant) {
+		self.rate = None;
+		self.sampled = None;
+	}
+
+	fn write(&self, state: &ProgressState, w: &mut dyn std::fmt::Write) {
+		let remaining = state.len().unwrap_or(0).saturating_sub(state.pos());
+		let eta = match self.rate {
+			Some(rate) if rate > 0.0 => Duration::from_secs_f64(remaining as f64 / rate),
+			_ => state.eta(),
+		};
+		let _ = write!(w, "{:#}", HumanDuration(eta));
+	}
+}
+
+//Function to parse --progress-interval, any number of seconds above 0 (fractions allowed)
+fn parse_interval(value: &str) -> Result<Duration, String> {
+	match value.parse::<f64>() {
+		Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+		_ => Err(format!("{} isn't an interval, use a number of seconds above 0", value)),
 	}
 }
 
+//Function to parse --eta-smoothing, how much of the old rate to keep has to be at least 0 and below 1
+fn parse_smoothing(value: &str) -> Result<f64, String> {
+	match value.parse::<f64>() {
+		Ok(factor) if (0.0..1.0).contains(&factor) => Ok(factor),
+		_ => Err(format!("{} isn't a smoothing factor, use a number from 0 up to (not including) 1", value)),
+	}
+}
+
+//Aggregate progress at one moment, what PROGRESS lines and --progress-file snapshots are made from. The
+//numbers are the bar's own position/length and the copy counters, so they match what the bar would show
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressSnapshot {
+	pub bytes: u64,
+	pub total_bytes: u64,
+	pub files: u64,
+	pub errors: u64,
+	pub elapsed: Duration,
+}
+
+impl ProgressSnapshot {
+	pub fn take(pb: &ProgressBar, counters: &CopyCounters) -> ProgressSnapshot {
+		ProgressSnapshot {
+			bytes: pb.position(),
+			total_bytes: pb.length().unwrap_or(0),
+			files: counters.files.load(Ordering::Relaxed) + counters.symlinks.load(Ordering::Relaxed),
+			errors: counters.errors.load(Ordering::Relaxed),
+			elapsed: pb.elapsed(),
+		}
+	}
+}
+
+//Function to print a PROGRESS line for --progress porcelain and a snapshot to --progress-file as files finish,
+//at most --progress-refresh times a second (2 by default). With --progress-interval the timer reports instead
+pub fn report_progress(pb: &ProgressBar, counters: &CopyCounters, options: &CopyOptions) {
+	static LAST_REPORT: Mutex<Option<Instant>> = Mutex::new(None);
+
+	if (options.progress != ProgressMode::Porcelain && options.progress_file.is_none()) || options.progress_interval.is_some() {
+		return;
+	}
+	let interval = Duration::from_secs(1) / options.progress_refresh.unwrap_or(2) as u32;
+	//Whoever holds the lock is about to report anyway
+	let Ok(mut last) = LAST_REPORT.try_lock() else { return };
+	if last.is_some_and(|at| at.elapsed() < interval) {
+		return;
+	}
+	*last = Some(Instant::now());
+	emit_progress(pb, counters, options);
+}
+
+//Function for --progress-interval, reports on a timer until told to stop (the sender dropped) rather than as
+//files finish, so a frontend gets evenly spaced updates instead of a flood of small files and silence on big ones
+pub fn report_every(interval: Duration, pb: &ProgressBar, counters: &CopyCounters, options: &CopyOptions, stopped: mpsc::Receiver<()>) {
+	while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+		emit_progress(pb, counters, options);
+	}
+}
+
+fn emit_progress(pb: &ProgressBar, counters: &CopyCounters, options: &CopyOptions) {
+	let snapshot = ProgressSnapshot::take(pb, counters);
+	match options.progress {
+		ProgressMode::Porcelain => eprintln!("PROGRESS {}", progress_fields(&snapshot)),
+		ProgressMode::Periodic => eprintln!("{}", periodic_line(&snapshot, &format!("{} left", HumanDuration(pb.eta())), options)),
+		ProgressMode::Bar => {}
+	}
+	if let Some(file) = &options.progress_file {
+		file.snapshot(&snapshot, pb.eta(), options);
+	}
+}
+
+//25% (1.2 GiB/4.8 GiB, 3 minutes left)
+fn periodic_line(snapshot: &ProgressSnapshot, status: &str, options: &CopyOptions) -> String {
+	let percent = (snapshot.bytes * 100).checked_div(snapshot.total_bytes).unwrap_or(100);
+	format!("{}% ({}/{}, {})", percent, format_bytes(snapshot.bytes, options.si), format_bytes(snapshot.total_bytes, options.si), status)
+}
+
+//Function to show the file being copied after the bar, the newest one to start when copying in parallel
+//Redrawing the message for every small file would cost more than the copy, so it changes at most 10 times a second
+pub fn show_current_file(pb: &ProgressBar, path: &Path) {
+	static LAST_SHOWN: Mutex<Option<Instant>> = Mutex::new(None);
+
+	if pb.is_hidden() {
+		return;
+	}
+	let Ok(mut last) = LAST_SHOWN.try_lock() else { return };
+	if last.is_some_and(|at| at.elapsed() < Duration::from_millis(100)) {
+		return;
+	}
+	*last = Some(Instant::now());
+	pb.set_message(path.display().to_string());
+}
+
+//Final DONE line for --progress porcelain (or the last line for periodic), always printed so whoever is
+//reading knows the copy phase is over
+pub fn report_done(pb: &ProgressBar, counters: &CopyCounters, options: &CopyOptions) {
+	let snapshot = ProgressSnapshot::take(pb, counters);
+	match options.progress {
+		ProgressMode::Porcelain => eprintln!("DONE {}", progress_fields(&snapshot)),
+		ProgressMode::Periodic => eprintln!("{}", periodic_line(&snapshot, "done", options)),
+		ProgressMode::Bar => {}
+	}
+}
+
+fn progress_fields(snapshot: &ProgressSnapshot) -> String {
+	format!("bytes={} total={} files={} errors={}", snapshot.bytes, snapshot.total_bytes, snapshot.files, snapshot.errors)
+}
+
+//Bar for a phase that runs after the copy (verifying...), counted in items since the total is known up front
+pub fn phase_progress_bar(message: &'static str, len: u64, options: &CopyOptions) -> ProgressBar {
+	let pb = ProgressBar::new(len);
+	set_progress_refresh(&pb, options);
+	pb.set_style(progress_style("{msg} {bar:40.green/blue} {pos}/{len} ETA {eta} [{elapsed_precise}]", options));
+	pb.set_message(message);
+	set_active_bar(Some(&pb));
+	pb
+}
+
 //Function to display the stats of a multi-file copy
-pub fn display_complete(stats: CopyStats, start_time: Instant, dry_run: bool) {
+pub fn display_complete(stats: CopyStats, start_time: Instant, options: &CopyOptions) {
 
 	let duration = start_time.elapsed();
-	if !dry_run {
+	if stats.print_compact(duration, options) {
+		//--format oneline/json, already printed
+	} else if options.structure_only {
+		let heading = if options.dry_run { "DRY RUN COMPLETE (STRUCTURE ONLY)" } else { "STRUCTURE ONLY COMPLETE" };
+		println!("\n\n--------{}--------\n", heading);
+		println!("\n{} directory(ies) created, {} file(s) skipped (structure only).", stats.dirs, stats.skipped);
+		println!("Duration: {:.2?}", duration);
+		println!("\n-----------------------------------------\n");
+	} else if !options.dry_run {
 		println!("\n\n--------------COPY COMPLETE--------------\n");
 		println!("\n{} file(s), {} directory(ies) copied.", stats.files, stats.dirs);
+		println!("{} copied.", format_bytes(stats.bytes, options.si));
+		if options.throughput {
+			println!("Throughput: {}/s average, {}/s peak.", format_bytes(stats.average_rate(), options.si), format_bytes(stats.peak_rate, options.si));
+		}
+		if stats.symlinks > 0 {
+			println!("{} symlink(s) resolved and copied as files.", stats.symlinks);
+		}
+		if stats.devices > 0 {
+			println!("{} device node(s) recreated.", stats.devices);
+		}
+		if stats.deduplicated > 0 {
+			println!("{} file(s) pointing at an already copied target, or hard links of one, were hard linked to it.", stats.deduplicated);
+		}
+		if options.link_dest.is_some() {
+			println!("{} unchanged file(s) hard linked from the previous backup, {} copied.",
+				stats.linked_previous, (stats.files + stats.symlinks).saturating_sub(stats.linked_previous));
+		}
+		if stats.timed_out > 0 {
+			println!("{} file(s) timed out and were skipped.", stats.timed_out);
+		}
+		if stats.errors > 0 {
+			println!("{} error(s).", stats.errors);
+		}
+		if stats.size_mismatches > 0 {
+			println!("{} of them copied to the wrong size (truncated?).", stats.size_mismatches);
+		}
+		if stats.forced_overwrites > 0 {
+			println!("{} read-only destination file(s) replaced (--overwrite-readonly).", stats.forced_overwrites);
+		}
+		if stats.kept_newer > 0 {
+			println!("Warning: {} destination file(s) are newer than the source and were left alone by --no-clobber-newer{}.",
+				stats.kept_newer, if options.verbose { "" } else { " (--verbose lists them)" });
+		}
+		if stats.kept_nonempty > 0 {
+			println!("{} destination file(s) weren't empty and were left alone by --clobber-empty-only{}.",
+				stats.kept_nonempty, if options.verbose { "" } else { " (--verbose lists them)" });
+		}
+		if stats.permission_failures > 0 {
+			println!("Warning: permissions couldn't be set on {} file(s)/directory(ies), the destination may not support them{}.",
+				stats.permission_failures, if options.verbose { "" } else { " (--verbose lists them)" });
+		}
+		for (dir, skipped) in &stats.failed_dirs {
+			if *skipped > 0 {
+				println!("Skipped {} file(s) under {} (directory couldn't be created).", skipped, dir.display());
+			}
+		}
+		if stats.ignored_errors > 0 {
+			println!("{} error(s) ignored (--ignore-error).", stats.ignored_errors);
+		}
+		if stats.collisions > 0 {
+			println!("{} destination name collision(s).", stats.collisions);
+		}
+		if options.repair {
+			println!("{} file(s) had the wrong size and were copied again, {} already matched the source's size and were left alone.",
+				stats.repaired, stats.repair_intact);
+		}
+		if stats.renamed_conflicts > 0 {
+			println!("{} file(s) conflicted with a different file in the destination and were written under a numbered name.", stats.renamed_conflicts);
+		}
+		if stats.hidden_skipped > 0 {
+			println!("{} hidden file(s)/directory(ies) skipped.", stats.hidden_skipped);
+		}
+		if stats.symlink_loops > 0 {
+			println!("{} symlink loop(s) skipped.", stats.symlink_loops);
+		}
+		if stats.unreadable > 0 {
+			println!("{} unreadable entr(y/ies) skipped along with anything inside them.", stats.unreadable);
+		}
+		display_attribute_skips(&stats, "skipped");
+		if stats.resumed > 0 {
+			println!("{} file(s) already copied by an earlier run (checkpoint), skipped.", stats.resumed);
+		}
+		if stats.deferred > 0 {
+			println!("Stopped at --max-bytes: {} file(s) ({}) left for the next run.", stats.deferred, format_bytes(stats.deferred_bytes, options.si));
+		}
+		if options.verify || options.verify_sample.is_some() || options.manifest.as_ref().is_some_and(IncludeManifest::has_checks) {
+			display_verify_summary(&stats, options);
+		}
+		if options.delete {
+			println!("{} extraneous file(s)/directory(ies) deleted, {} moved to the trash.", stats.deleted, stats.trashed);
+		}
 		println!("Duration: {:.2?}", duration);
 		println!("\n-----------------------------------------\n");
 	} else {
 		println!("\n\n------------DRY RUN COMPLETE------------\n");
 		println!("\n{} file(s), {} directory(ies) would have been copied.", stats.files, stats.dirs);
+		println!("{} would have been copied.", format_bytes(stats.bytes, options.si));
+		if stats.symlinks > 0 {
+			println!("{} symlink(s) would have been resolved and copied as files.", stats.symlinks);
+		}
+		if stats.devices > 0 {
+			println!("{} device node(s) would have been recreated.", stats.devices);
+		}
+		if stats.hidden_skipped > 0 {
+			println!("{} hidden file(s)/directory(ies) would have been skipped.", stats.hidden_skipped);
+		}
+		display_attribute_skips(&stats, "would have been skipped");
+		if stats.errors > 0 {
+			println!("{} problem(s) found, the real copy would fail there.", stats.errors);
+		}
+		if stats.resumed > 0 {
+			println!("{} file(s) already copied by an earlier run (checkpoint) would have been skipped.", stats.resumed);
+		}
+		if stats.deferred > 0 {
+			println!("--max-bytes would stop the copy with {} file(s) ({}) left for the next run.", stats.deferred, format_bytes(stats.deferred_bytes, options.si));
+		}
+		if options.delete {
+			println!("{} extraneous file(s)/directory(ies) would have been {}.", stats.deleted, if options.trash { "moved to the trash" } else { "deleted" });
+		}
 		println!("Duration: {:.2?}", duration);
 		println!("\n-----------------------------------------\n");
 	}
@@ -56,21 +1473,108 @@ pub fn display_complete(stats: CopyStats, start_time: Instant, dry_run: bool) {
 
 }
 
+//Function to print what --exclude-empty, --exclude-symlinks, --only-executable, --skip-recent and --skip-too-long left out
+fn display_attribute_skips(stats: &CopyStats, skipped: &str) {
+	if stats.skipped_empty > 0 {
+		println!("{} empty file(s) {}.", stats.skipped_empty, skipped);
+	}
+	if stats.skipped_symlinks > 0 {
+		println!("{} symlink(s) {}.", stats.skipped_symlinks, skipped);
+	}
+	if stats.skipped_not_executable > 0 {
+		println!("{} non-executable file(s) {}.", stats.skipped_not_executable, skipped);
+	}
+	if stats.skipped_recent > 0 {
+		println!("{} file(s) too recent (modified within --skip-recent) {}.", stats.skipped_recent, skipped);
+	}
+	if stats.skipped_too_long > 0 {
+		println!("{} file(s)/directory(ies) with names too long for the destination {}.", stats.skipped_too_long, skipped);
+	}
+}
+
+//Function to print how verification went, for a sample also note how much of the copy that covered
+fn display_verify_summary(stats: &CopyStats, options: &CopyOptions) {
+	match options.verify_sample {
+		Some(percent) => {
+			println!("Verified a {}% sample (seed {}) with {}: {} of {} file(s) checked, {} mismatch(es).",
+				percent, options.seed, options.hash.name(), stats.verified, stats.files + stats.symlinks, stats.verify_mismatches);
+			if stats.verify_mismatches == 0 && stats.verified > 0 {
+				println!("No mismatches in the sample, the unchecked files are likely fine but weren't verified.");
+			}
+		}
+		None => println!("Verified {} file(s) with {}, {} mismatch(es).", stats.verified, options.hash.name(), stats.verify_mismatches),
+	}
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+	match value.trim_end_matches('%').parse::<f64>() {
+		Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+		_ => Err(format!("{} isn't a percentage between 0 and 100", value)),
+	}
+}
+
+//Function to parse the command line, with --config/.rcpyrc settings slotted in front of it so the command line wins
+//The first parse only finds the config file, a repeated option then takes its last value instead of erroring
 pub fn get_arg_matches() -> ArgMatches {
-    Command::new("rcpy")
+	let args: Vec<OsString> = std::env::args_os().collect();
+	let matches = build_command().get_matches_from(&args);
+	match config_args(&matches) {
+		Ok(None) => matches,
+		Ok(Some((path, config))) => {
+			let mut merged = vec![args[0].clone()];
+			merged.extend(config);
+			merged.extend(args[1..].iter().cloned());
+			//Checked again as a whole, the command line alone was fine so a conflict involves the file
+			build_command().args_override_self(true).try_get_matches_from(merged).unwrap_or_else(|e| {
+				let _ = e.print();
+				eprintln!("\nSome of these options come from {}, turn a flag it sets off with --no-<flag> (--hidden for no-hidden) or skip the file with --no-config", path.display());
+				std::process::exit(e.exit_code());
+			})
+		}
+		Err(e) => {
+			eprintln!("Error: {}", e);
+			std::process::exit(1);
+		}
+	}
+}
+
+fn build_command() -> Command {
+    let command = Command::new("rcpy")
 		.about("A recursive copy tool written in Rust with progress bars, dry-run mode, file exclusion, and multi-threaded support.")
 		.override_usage("rcpy <source> <destination> [OPTIONS]")
 		.arg(Arg::new("source")
 			.required(true)
-			.help("Source directory"))
+			.help("Source directory or file, or a quoted glob ('logs/*.txt') to copy every match into the destination"))
 		.arg(Arg::new("destination")
-			.required(true)
+			.required_unless_present_any(["bench", "dedupe_report"])
 			.help("Destination directory"))
+		.arg(Arg::new("no_follow_source")
+			.long("no-follow-source")
+			.action(clap::ArgAction::SetTrue)
+			.help("Refuse to copy when the source is a symlink to a directory instead of copying its target"))
+		.arg(Arg::new("no_follow_dest")
+			.long("no-follow-dest")
+			.action(clap::ArgAction::SetTrue)
+			.help("Fail if the destination is a symlink instead of writing into what it points to"))
+		.arg(Arg::new("dereference")
+			.long("dereference")
+			.short('L')
+			.action(clap::ArgAction::SetTrue)
+			.help("Copy what symlinks point to, copying a target reached through several links once and hard linking the rest"))
+		.arg(Arg::new("copy_contents")
+			.long("copy-contents")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with("exclude_symlinks")
+			.help("Follow symlinked directories inside the source and copy their contents, links back up the tree are skipped"))
 		.arg(Arg::new("single_thread")
 			.short('s')
 			.long("single-thread")
 			.action(clap::ArgAction::SetTrue)
 			.help("Copy using only one thread, will be slower!"))
+		.arg(Arg::new("ordered")
+			.long("ordered")
+			.action(clap::ArgAction::SetTrue)
+			.help("Create directories and files one at a time in sorted order, so every run fills the destination in the same order (implies --single-thread)"))
 		.arg(Arg::new("only_files")
 			.long("only-files")
 			.action(clap::ArgAction::SetTrue)
@@ -91,16 +1595,570 @@ pub fn get_arg_matches() -> ArgMatches {
 			.short('d')
 			.action(clap::ArgAction::SetTrue)
 			.help("Simulate copy without writing any files. NOTE(acts as though verbose is set)"))
+		.arg(Arg::new("tree")
+			.long("tree")
+			.action(clap::ArgAction::SetTrue)
+			.requires("dry_run")
+			.help("With --dry-run, draw what would be created as a tree at the end instead of a line per operation"))
+		.arg(Arg::new("strict")
+			.long("strict")
+			.action(clap::ArgAction::SetTrue)
+			.requires("dry_run")
+			.help("With --dry-run, check every destination directory can really be written to"))
 		.arg(Arg::new("exclude")
 			.long("exclude")
 			.action(clap::ArgAction::Append)
 			.value_name("EXT")
 			.help("Exclude files by extension (e.g. --exclude .psd --exclude tmp)"))
+		.arg(Arg::new("strict_excludes")
+			.long("strict-excludes")
+			.action(clap::ArgAction::SetTrue)
+			.requires("exclude")
+			.help("Fail the run if an --exclude didn't match a single file, to catch typos like .jpeg for .jpg"))
+		.arg(Arg::new("exclude_empty")
+			.long("exclude-empty")
+			.action(clap::ArgAction::SetTrue)
+			.help("Skip empty (zero byte) files"))
+		.arg(Arg::new("exclude_symlinks")
+			.long("exclude-symlinks")
+			.action(clap::ArgAction::SetTrue)
+			.help("Skip symlinks instead of copying what they point to"))
+		.arg(Arg::new("only_executable")
+			.long("only-executable")
+			.action(clap::ArgAction::SetTrue)
+			.help("Only copy files with an execute bit set (Unix, does nothing on Windows)"))
+		.arg(Arg::new("newer_than_file")
+			.long("newer-than-file")
+			.value_name("PATH")
+			.help("Only copy files modified more recently than PATH (like find -newer)"))
+		.arg(Arg::new("skip_recent")
+			.long("skip-recent")
+			.value_name("SECONDS")
+			.value_parser(clap::value_parser!(u64))
+			.help("Skip files modified in the last SECONDS seconds, they may still be being written"))
+		.arg(Arg::new("skip_too_long")
+			.long("skip-too-long")
+			.action(clap::ArgAction::SetTrue)
+			.help("Skip files and directories whose names are longer than the destination filesystem allows instead of failing on them"))
+		.arg(Arg::new("exclude_vcs")
+			.long("exclude-vcs")
+			.action(clap::ArgAction::SetTrue)
+			.help("Skip version control metadata (.git, .svn, .hg, .bzr, CVS and their ignore files)"))
 		.arg(Arg::new("no_recursive")
 			.long("no-recursive")
 			.action(clap::ArgAction::SetTrue)
 			.help("Copy only the top-level directory contents (non-recursive)"))
-		.get_matches()
+		.arg(Arg::new("from_archive")
+			.long("from-archive")
+			.action(clap::ArgAction::SetTrue)
+			.help("Treat the source as a tar archive and extract it (automatic for .tar, .tar.gz and .tgz)"))
+		.arg(Arg::new("atomic")
+			.long("atomic")
+			.action(clap::ArgAction::SetTrue)
+			.help("Write each file to a temp file first and rename it into place"))
+		.arg(Arg::new("fsync")
+			.long("fsync")
+			.action(clap::ArgAction::SetTrue)
+			.help("Flush every copied file, and the directories they went into, to disk before finishing (survives a power cut, much slower)"))
+		.arg(Arg::new("temp_dir")
+			.long("temp-dir")
+			.value_name("DIR")
+			.requires("atomic")
+			.help("Directory for --atomic temp files, must be on the same filesystem as the destination"))
+		.arg(Arg::new("timeout")
+			.long("timeout")
+			.value_name("SECONDS")
+			.value_parser(clap::value_parser!(u64).range(1..))
+			.help("Give up on any single file that takes longer than this to copy"))
+		.arg(Arg::new("append")
+			.long("append")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with("atomic")
+			.help("Append the source file to the destination instead of overwriting it (single files only, not idempotent!)"))
+		.arg(Arg::new("overwrite_readonly")
+			.long("overwrite-readonly")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with("append")
+			.help("Replace destination files that are read-only instead of failing on them, like cp -f"))
+		.arg(Arg::new("no_clobber_newer")
+			.long("no-clobber-newer")
+			.action(clap::ArgAction::SetTrue)
+			.help("Never overwrite a destination file that was modified more recently than the source, whatever else is set"))
+		.arg(Arg::new("link_dest")
+			.long("link-dest")
+			.value_name("PREVIOUS")
+			.conflicts_with("append")
+			.help("Hard link files that are unchanged since the backup in PREVIOUS from there instead of copying them, like rsync"))
+		.arg(Arg::new("clobber_empty_only")
+			.long("clobber-empty-only")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with_all(["append", "rename_conflicts"])
+			.help("Only overwrite destination files that are empty (placeholders), non-empty ones are left alone"))
+		.arg(Arg::new("rename_conflicts")
+			.long("rename-conflicts")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with_all(["append", "delete"])
+			.help("When merging, write a file whose destination already holds something different as 'name (2).ext' so both are kept"))
+		.arg(Arg::new("repair")
+			.long("repair")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with_all(["append", "rename_conflicts"])
+			.help("Fix a destination left by a crashed copy: only copy files that are missing or whose size differs from the source's"))
+		.arg(Arg::new("files_from")
+			.long("files-from")
+			.value_name("FILE")
+			.help("Only copy the paths listed in FILE (relative to the source, use - for stdin)"))
+		.arg(Arg::new("manifest")
+			.long("manifest")
+			.value_name("FILE")
+			.conflicts_with_all(["files_from", "since_git", "lowercase_dest", "strip_components", "rename"])
+			.help("Copy exactly the files listed in FILE (lines of PATH, SIZE<TAB>PATH or SIZE<TAB>HASH<TAB>PATH) and check the copies against it"))
+		.arg(Arg::new("newest")
+			.long("newest")
+			.value_name("N")
+			.value_parser(clap::value_parser!(usize))
+			.conflicts_with_all(["manifest", "delete"])
+			.help("Only copy the N most recently modified files (after every other filter), plus the directories they're in"))
+		.arg(Arg::new("since_git")
+			.long("since-git")
+			.value_name("COMMIT")
+			.conflicts_with("files_from")
+			.help("Only copy files git reports as changed in the source since COMMIT (the source has to be in a git working tree)"))
+		.arg(Arg::new("since_git_delete")
+			.long("since-git-delete")
+			.action(clap::ArgAction::SetTrue)
+			.requires("since_git")
+			.help("With --since-git, also remove files deleted (or renamed away) since COMMIT from the destination"))
+		.arg(Arg::new("list")
+			.long("list")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with_all(["bench", "dry_run"])
+			.help("Only print the path (relative to the source) of every file that would be copied, one per line (NUL separated with -0)"))
+		.arg(Arg::new("null")
+			.short('0')
+			.long("null")
+			.action(clap::ArgAction::SetTrue)
+			.help("Path lists are separated by NUL bytes instead of newlines (like find -print0)"))
+		.arg(Arg::new("into")
+			.long("into")
+			.action(clap::ArgAction::SetTrue)
+			.help("Copy a source directory as a subdirectory of the destination (dst/<source name>) instead of merging its contents"))
+		.arg(Arg::new("base")
+			.long("base")
+			.value_name("DIR")
+			.help("When copying a single file, keep its path relative to DIR under the destination"))
+		.arg(Arg::new("si")
+			.long("si")
+			.action(clap::ArgAction::SetTrue)
+			.help("Show sizes in powers of 1000 (KB, MB, GB) instead of 1024 (KiB, MiB, GiB)"))
+		.arg(Arg::new("checkpoint")
+			.long("checkpoint")
+			.value_name("FILE")
+			.help("Record each finished file in FILE so an interrupted copy can be picked up with --resume (removed after a clean run)"))
+		.arg(Arg::new("resume")
+			.long("resume")
+			.action(clap::ArgAction::SetTrue)
+			.requires("checkpoint")
+			.help("Skip the files the --checkpoint says were already copied"))
+		.arg(Arg::new("error_log")
+			.long("error-log")
+			.value_name("FILE")
+			.help("Write every failure to FILE, one line each (path, error kind, message)"))
+		.arg(Arg::new("ionice")
+			.long("ionice")
+			.value_name("CLASS")
+			.num_args(0..=1)
+			.default_missing_value("idle")
+			.value_parser(["idle", "low"])
+			.help("Run at low IO priority so other programs stay responsive: idle (default) or low"))
+		.arg(Arg::new("cpu_affinity")
+			.long("cpu-affinity")
+			.value_name("LIST")
+			.value_parser(parse_cpu_list)
+			.conflicts_with("bench")
+			.help("Pin the copy threads to these CPU cores (e.g. 0,2,4-7), one worker per core listed (Linux only)"))
+		.arg(Arg::new("bench")
+			.long("bench")
+			.action(clap::ArgAction::SetTrue)
+			.help("Benchmark copying the source into a temp directory with different thread counts (no destination needed)"))
+		.arg(Arg::new("dedupe_report")
+			.long("dedupe-report")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with_all(["bench", "list", "dry_run"])
+			.help("Only report groups of identical files in the source and the space they waste, nothing is copied (no destination needed)"))
+		.arg(Arg::new("ignore_error")
+			.long("ignore-error")
+			.action(clap::ArgAction::Append)
+			.value_name("PATTERN")
+			.help("Quietly skip failures on paths matching this glob, they don't fail the run (e.g. --ignore-error '**/*.lock')"))
+		.arg(Arg::new("quiet_errors")
+			.long("quiet-errors")
+			.action(clap::ArgAction::SetTrue)
+			.help("Don't print each failure, the summary still counts them and the run still fails"))
+		.arg(Arg::new("abort_on_walk_error")
+			.long("abort-on-walk-error")
+			.action(clap::ArgAction::SetTrue)
+			.help("Stop the whole copy at the first directory that can't be read instead of skipping it"))
+		.arg(Arg::new("preserve")
+			.long("preserve")
+			.value_name("LIST")
+			.value_parser(PreserveList::parse)
+			.help("Attributes to keep, comma separated like cp: mode,ownership,timestamps,links,xattr or all (everything this platform supports)"))
+		.arg(Arg::new("preserve_crtime")
+			.long("preserve-crtime")
+			.action(clap::ArgAction::SetTrue)
+			.help("Keep each file's creation time (Windows and macOS only)"))
+		.arg(Arg::new("lowercase_dest")
+			.long("lowercase-dest")
+			.action(clap::ArgAction::SetTrue)
+			.help("Lowercase every destination path component (for moving to case-sensitive filesystems)"))
+		.arg(Arg::new("strip_components")
+			.long("strip-components")
+			.value_name("N")
+			.value_parser(clap::value_parser!(usize))
+			.help("Drop the first N components of every path below the source, like tar (files with N or fewer are skipped)"))
+		.arg(Arg::new("rename")
+			.long("rename")
+			.value_name("PATTERN=>REPLACEMENT")
+			.action(clap::ArgAction::Append)
+			.value_parser(RenameRule::parse)
+			.conflicts_with("delete")
+			.help("Regex substitution on every copied file's name, repeatable and applied in order (e.g. --rename '\\.jpeg$=>.jpg')"))
+		.arg(Arg::new("collision")
+			.long("collision")
+			.value_name("POLICY")
+			.value_parser(["fail", "rename"])
+			.default_value("fail")
+			.help("When renamed files collide: fail the later file, or rename it to 'name (2).ext'"))
+		.arg(Arg::new("no_hidden")
+			.long("no-hidden")
+			.action(clap::ArgAction::SetTrue)
+			.help("Skip hidden (dot) files and directories, hidden directories aren't descended into"))
+		.arg(Arg::new("progress")
+			.long("progress")
+			.value_name("MODE")
+			.value_parser(["bar", "porcelain", "periodic"])
+			.default_value("bar")
+			.help("bar (default), porcelain: `PROGRESS bytes=N total=N files=N errors=N` lines on stderr and a final DONE line, or periodic: a `25% (1.2 GiB/4.8 GiB, 3 minutes left)` line every 5 seconds (--progress-interval/--progress-refresh change it)"))
+		.arg(Arg::new("progress_file")
+			.long("progress-file")
+			.value_name("FILE")
+			.help("Also append a progress line (percent, bytes, errors, ETA) to FILE while copying and the summary at the end, for tail -f on detached runs"))
+		.arg(Arg::new("progress_refresh")
+			.long("progress-refresh")
+			.value_name("HZ")
+			.value_parser(clap::value_parser!(u8).range(1..))
+			.help("How many times per second the progress bar redraws (lower is smoother over SSH)"))
+		.arg(Arg::new("progress_interval")
+			.long("progress-interval")
+			.value_name("SECS")
+			.value_parser(parse_interval)
+			.help("Write --progress porcelain lines and --progress-file snapshots every SECS seconds (e.g. 0.5) instead of as files finish"))
+		.arg(Arg::new("eta_smoothing")
+			.long("eta-smoothing")
+			.alias("progress-eta-smoothing")
+			.value_name("FACTOR")
+			.value_parser(parse_smoothing)
+			.help("Steady the progress bar's ETA, 0 (the default) is indicatif's own estimate and values towards 1 (e.g. 0.9) change more slowly"))
+		.arg(Arg::new("throughput")
+			.long("throughput")
+			.action(clap::ArgAction::SetTrue)
+			.help("Add the average and peak copy speed to the summary"))
+		.arg(Arg::new("verify")
+			.long("verify")
+			.action(clap::ArgAction::SetTrue)
+			.help("Hash every copied file and its source to make sure they match"))
+		.arg(Arg::new("verify_sample")
+			.long("verify-sample")
+			.value_name("PERCENT")
+			.value_parser(parse_percent)
+			.conflicts_with("verify")
+			.help("Verify only a random PERCENT of copied files"))
+		.arg(Arg::new("verify_size")
+			.long("verify-size")
+			.action(clap::ArgAction::SetTrue)
+			.help("Check each copy is the same size as its source, a cheap way to catch truncated copies (on with --verify)"))
+		.arg(Arg::new("hash")
+			.long("hash")
+			.value_name("ALGO")
+			.value_parser(HashAlgorithm::parse)
+			.help("Hash used to verify copies: blake3 (default), sha256, md5 or xxh3 (depending on build features)"))
+		.arg(Arg::new("seed")
+			.long("seed")
+			.value_name("N")
+			.value_parser(clap::value_parser!(u64))
+			.requires("verify_sample")
+			.help("Seed for picking the --verify-sample files, the same seed checks the same files"))
+		.arg(Arg::new("order")
+			.long("order")
+			.value_name("ORDER")
+			.value_parser(["depth-first", "breadth-first"])
+			.default_value("depth-first")
+			.help("Walk order for output and copying, strict with --single-thread and best-effort when multi-threaded"))
+		.arg(Arg::new("parallel")
+			.long("parallel")
+			.value_name("STRATEGY")
+			.value_parser(["files", "dirs"])
+			.default_value("files")
+			.help("How a multi-threaded copy splits the work: every file in one pool, or one thread per top-level subdirectory (for a few big independent subtrees)"))
+		.arg(Arg::new("preserve_flags")
+			.long("preserve-flags")
+			.action(clap::ArgAction::SetTrue)
+			.help("Copy file flags like immutable and append-only (Linux chattr / macOS chflags, usually needs root)"))
+		.arg(Arg::new("preserve_resource_fork")
+			.long("preserve-resource-fork")
+			.action(clap::ArgAction::SetTrue)
+			.help("Keep macOS resource forks and Finder info (com.apple.ResourceFork / com.apple.FinderInfo)"))
+		.arg(Arg::new("devices")
+			.long("devices")
+			.action(clap::ArgAction::SetTrue)
+			.help("Recreate block/character devices with mknod instead of reading them (Unix, needs root)"))
+		.arg(Arg::new("chmod")
+			.long("chmod")
+			.value_name("MODE")
+			.value_parser(ModeSpec::parse)
+			.help("Set this mode on every copied file instead of the source's, octal (644) or symbolic (u+rw,go-w)"))
+		.arg(Arg::new("chmod_dir")
+			.long("chmod-dir")
+			.value_name("MODE")
+			.value_parser(ModeSpec::parse)
+			.help("Like --chmod but for directories"))
+		.arg(Arg::new("structure_only")
+			.long("structure-only")
+			.action(clap::ArgAction::SetTrue)
+			.help("Recreate the directory tree (with permissions) without copying any files"))
+		.arg(Arg::new("data_only")
+			.long("data-only")
+			.visible_alias("no-preserve")
+			.action(clap::ArgAction::SetTrue)
+			.help("Copy file contents only, permissions, times and other metadata are never set (for destinations that refuse them). Overrides --preserve and --chmod"))
+		.arg(Arg::new("keep_dest_dir_perms")
+			.long("keep-dest-dir-perms")
+			.action(clap::ArgAction::SetTrue)
+			.help("Directories that already exist in the destination keep their permissions, only new ones get the source's"))
+		.arg(Arg::new("keep_dest_dir_times")
+			.long("keep-dest-dir-times")
+			.action(clap::ArgAction::SetTrue)
+			.help("Directories that already exist in the destination keep their timestamps, copying files into them doesn't bump them"))
+		.arg(Arg::new("absolute_paths")
+			.long("absolute-paths")
+			.action(clap::ArgAction::SetTrue)
+			.help("Show full paths in verbose and dry-run output instead of paths as they were typed"))
+		.arg(Arg::new("delete")
+			.long("delete")
+			.visible_alias("mirror")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with_all(["files_from", "since_git", "lowercase_dest", "strip_components"])
+			.help("Remove files from the destination that aren't in the source (excluded files are kept)"))
+		.arg(Arg::new("trash")
+			.long("trash")
+			.action(clap::ArgAction::SetTrue)
+			.requires("delete")
+			.help("With --delete, move removed files to the trash/recycle bin instead of deleting them"))
+		.arg(Arg::new("oneline_summary")
+			.long("oneline-summary")
+			.action(clap::ArgAction::SetTrue)
+			.conflicts_with("format")
+			.help("Print the summary as a single line instead of the banner, easy to grep out of logs (same as --format oneline)"))
+		.arg(Arg::new("format")
+			.long("format")
+			.value_name("FORMAT")
+			.value_parser(SummaryFormat::parse)
+			.help("Summary format: text (default), oneline or json (json needs the serde build feature, on by default)"))
+		.arg(Arg::new("diff_manifest")
+			.long("diff-manifest")
+			.value_name("FILE")
+			.help("Print what's new, changed and removed in the source since the last run, then save the new state to FILE"))
+		.arg(Arg::new("max_files")
+			.long("max-files")
+			.value_name("N")
+			.value_parser(clap::value_parser!(u64))
+			.help("Refuse to copy if the source has more than N files"))
+		.arg(Arg::new("max_bytes")
+			.long("max-bytes")
+			.value_name("SIZE")
+			.value_parser(parse_size)
+			.help("Stop starting new file copies once about SIZE (e.g. 500M, 2GiB) has been copied, pair with --checkpoint/--resume to move a tree in chunks"))
+		.arg(Arg::new("buffer_size")
+			.long("buffer-size")
+			.value_name("SIZE")
+			.value_parser(parse_size)
+			.help("Chunk size when rcpy streams a copy itself (--timeout, --append), by default 128K rounded up to the destination's block size"))
+		.arg(Arg::new("force")
+			.long("force")
+			.action(clap::ArgAction::SetTrue)
+			.help("Copy anyway when a safety limit like --max-files is hit"))
+		.arg(Arg::new("config")
+			.long("config")
+			.value_name("FILE")
+			.conflicts_with("no_config")
+			.help("Read option defaults from this TOML file instead of the nearest .rcpyrc, options given here still win"))
+		.arg(Arg::new("no_config")
+			.long("no-config")
+			.action(clap::ArgAction::SetTrue)
+			.help("Don't read a config file, not even a .rcpyrc"));
+	with_negations(command)
+}
+
+//Function to give every flag a config file can turn on a hidden opposite (--no-verify, --hidden for --no-hidden)
+//The file's arguments always come first, so the command line's switch is the later one and wins
+fn with_negations(command: Command) -> Command {
+	config_flags().fold(command, |command, flag| {
+		let negation = negation(flag);
+		command.arg(Arg::new(negation.replace('-', "_"))
+			.long(negation.clone())
+			.action(clap::ArgAction::SetTrue)
+			.overrides_with(flag.replace('-', "_"))
+			.hide(true)
+			.help(format!("Turn off --{} from a config file", flag)))
+	})
 }
 
 
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn normalize_path_collapses_dots_and_doubled_separators() {
+		assert_eq!(normalize_path(Path::new("./src/./foo.txt")), PathBuf::from("src/foo.txt"));
+		assert_eq!(normalize_path(Path::new("src//nested///foo.txt")), PathBuf::from("src/nested/foo.txt"));
+		assert_eq!(normalize_path(Path::new("src/nested/../foo.txt")), PathBuf::from("src/foo.txt"));
+		assert_eq!(normalize_path(Path::new("../up/./x")), PathBuf::from("../up/x"));
+		assert_eq!(normalize_path(Path::new("a/../..")), PathBuf::from(".."));
+		assert_eq!(normalize_path(Path::new("./.")), PathBuf::from("."));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn normalize_path_stops_at_the_root() {
+		assert_eq!(normalize_path(Path::new("/../etc/./hosts")), PathBuf::from("/etc/hosts"));
+		assert_eq!(normalize_path(Path::new("//tmp//x/")), PathBuf::from("/tmp/x"));
+	}
+
+	#[test]
+	fn format_bytes_at_power_of_two_boundaries() {
+		assert_eq!(format_bytes(0, false), "0 B");
+		assert_eq!(format_bytes(1023, false), "1023 B");
+		assert_eq!(format_bytes(1024, false), "1.0 KiB");
+		assert_eq!(format_bytes(1536, false), "1.5 KiB");
+		assert_eq!(format_bytes(1024 * 1024 - 1, false), "1.0 MiB");
+		assert_eq!(format_bytes(1024 * 1024, false), "1.0 MiB");
+		assert_eq!(format_bytes(1 << 30, false), "1.0 GiB");
+		assert_eq!(format_bytes((1 << 30) - 1, false), "1.0 GiB");
+		assert_eq!(format_bytes(1 << 40, false), "1.0 TiB");
+		assert_eq!(format_bytes(u64::MAX, false), "16.0 EiB");
+	}
+
+	#[test]
+	fn format_bytes_si_uses_powers_of_1000() {
+		assert_eq!(format_bytes(999, true), "999 B");
+		assert_eq!(format_bytes(1000, true), "1.0 KB");
+		assert_eq!(format_bytes(1024, true), "1.0 KB");
+		assert_eq!(format_bytes(999_999, true), "1.0 MB");
+		assert_eq!(format_bytes(1_000_000_000, true), "1.0 GB");
+	}
+
+	#[test]
+	fn cpu_list_expands_ranges_and_drops_duplicates() {
+		assert_eq!(parse_cpu_list("4-6,0,5,2").unwrap(), vec![0, 2, 4, 5, 6]);
+		assert_eq!(parse_cpu_list(" 3 ").unwrap(), vec![3]);
+	}
+
+	#[test]
+	fn cpu_list_rejects_bad_ids() {
+		assert!(parse_cpu_list("7-3").is_err());
+		assert!(parse_cpu_list("a").is_err());
+		assert!(parse_cpu_list("").is_err());
+		assert!(parse_cpu_list(&MAX_CORES.to_string()).is_err());
+		//Checked before the range is expanded, this used to run until killed
+		assert!(parse_cpu_list("0-99999999999").is_err());
+	}
+
+	#[test]
+	fn validate_paths_sees_through_dot_and_dotdot() {
+		//Tests run from the crate root, where src exists
+		assert!(matches!(validate_paths(Path::new("./src"), Path::new("src")), Err(CopyError::SamePath(_))));
+		assert!(matches!(validate_paths(Path::new("src"), Path::new("src/../src/")), Err(CopyError::SamePath(_))));
+		assert!(validate_paths(Path::new("src"), Path::new("tests")).is_ok());
+		assert!(validate_paths(Path::new("src"), Path::new("src/not-there-yet")).is_ok());
+	}
+
+	#[test]
+	fn octal_mode_replaces_the_source_mode() {
+		let mode = ModeSpec::parse("640").unwrap();
+		assert_eq!(mode.apply(0o755, false), 0o640);
+		assert!(ModeSpec::parse("8").is_err());
+		assert!(ModeSpec::parse("17777").is_err());
+	}
+
+	#[test]
+	fn symbolic_mode_adjusts_the_source_mode() {
+		assert_eq!(ModeSpec::parse("u+rw,go-w").unwrap().apply(0o466, false), 0o644);
+		assert_eq!(ModeSpec::parse("a=r").unwrap().apply(0o777, false), 0o444);
+		assert_eq!(ModeSpec::parse("+X").unwrap().apply(0o644, true), 0o755);
+		assert_eq!(ModeSpec::parse("+X").unwrap().apply(0o644, false), 0o644);
+		assert!(ModeSpec::parse("q+r").is_err());
+		assert!(ModeSpec::parse("u+z").is_err());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn any_execute_bit_makes_a_file_executable() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let path = std::env::temp_dir().join(format!("rcpy-executable-{}", std::process::id()));
+		std::fs::write(&path, "").unwrap();
+		for (mode, executable) in [(0o644, false), (0o744, true), (0o654, true), (0o645, true), (0o000, false)] {
+			std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+			assert_eq!(is_executable(&std::fs::metadata(&path).unwrap()), executable, "{:o}", mode);
+		}
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn copy_stats_survive_a_json_round_trip() {
+		let stats = CopyStats {
+			files: 12,
+			dirs: 3,
+			bytes: 1 << 40,
+			errors: 2,
+			skipped_empty: 1,
+			failed_dirs: vec![(PathBuf::from("locked/dir"), 7)],
+			exclude_hits: vec![("tmp".to_string(), 4)],
+			..CopyStats::default()
+		};
+		let json = serde_json::to_string(&stats).unwrap();
+		assert_eq!(serde_json::from_str::<CopyStats>(&json).unwrap(), stats);
+
+		let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+		assert_eq!(value["bytes"], 1u64 << 40);
+		assert_eq!(value["failed_dirs"][0][1], 7);
+	}
+
+	#[test]
+	fn trailing_separators_are_trimmed() {
+		assert_eq!(trim_trailing_separators(Path::new("dir/")), PathBuf::from("dir"));
+		assert_eq!(trim_trailing_separators(Path::new("dir//")), PathBuf::from("dir"));
+		assert_eq!(trim_trailing_separators(Path::new("a//b/./")), PathBuf::from("a/b"));
+		assert_eq!(trim_trailing_separators(Path::new("../dir/")), PathBuf::from("../dir"));
+	}
+
+	#[test]
+	fn command_line_definition_is_consistent() {
+		//Catches a config flag whose --no-* switch clashes with an option or overrides one that doesn't exist
+		build_command().debug_assert();
+	}
+
+	#[test]
+	fn fit_template_shrinks_the_bar_on_narrow_or_unknown_terminals() {
+		let template = "{spinner:.green} {bar:40.cyan/blue} {bytes}/{total_bytes} ETA {eta} [{elapsed_precise}]";
+		assert_eq!(fit_template(template, bar_layout(Some(120))), template);
+		assert_eq!(fit_template(template, bar_layout(Some(60))), "{spinner:.green} {bar:15.cyan/blue} {bytes}/{total_bytes} ETA {eta}");
+		//Never narrower than MIN_BAR_WIDTH, however little room there is
+		assert_eq!(fit_template(template, bar_layout(Some(20))), "{spinner:.green} {bar:10.cyan/blue} {bytes}/{total_bytes} ETA {eta}");
+		assert_eq!(fit_template(template, bar_layout(None)), "{spinner:.green} {percent:>3}% {bytes}/{total_bytes} ETA {eta}");
+	}
+}
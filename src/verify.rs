@@ -0,0 +1,167 @@
+/*****************************************
+    verify.rs
+-----------------
+Description: Handles checking that copied
+files match their source by hashing both
+sides, either for every file (--verify) or
+a deterministic sample (--verify-sample)
+with the --hash algorithm
+
+Author: Dylan Morgan
+*****************************************/
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::utils::CopyOptions;
+
+//Function to decide if a file should be checked, every file with --verify, otherwise a
+//stable slice of paths picked by hashing the path with the seed so reruns check the same files. blake3 rather
+//than std's hasher, whose output can change between Rust releases, so a seed picks the same files on every build
+pub fn should_verify(rel_path: &Path, options: &CopyOptions) -> bool {
+    if options.verify {
+        return true;
+    }
+    let Some(percent) = options.verify_sample else {
+        return false;
+    };
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&options.seed.to_le_bytes());
+    hasher.update(rel_path.as_os_str().as_encoded_bytes());
+    let digest = hasher.finalize();
+    //Buckets of a hundredth of a percent so a fractional sample (--verify-sample 0.5) is honoured
+    let bucket = u64::from_le_bytes(digest.as_bytes()[..8].try_into().unwrap()) % 10_000;
+    bucket < (percent * 100.0) as u64
+}
+
+//Function to compare a source and its copy, true when the contents match
+pub fn contents_match(src: &Path, dest: &Path, algorithm: HashAlgorithm) -> io::Result<bool> {
+    //Different sizes can never match, skip the hashing
+    if src.metadata()?.len() != dest.metadata()?.len() {
+        return Ok(false);
+    }
+    Ok(hash_file(src, algorithm)? == hash_file(dest, algorithm)?)
+}
+
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0u8; 128 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+//Algorithms for --hash, the optional ones only exist when their cargo feature is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Blake3,
+    #[cfg(feature = "sha256")]
+    Sha256,
+    #[cfg(feature = "md5")]
+    Md5,
+    #[cfg(feature = "xxhash")]
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: &[HashAlgorithm] = &[
+        HashAlgorithm::Blake3,
+        #[cfg(feature = "sha256")]
+        HashAlgorithm::Sha256,
+        #[cfg(feature = "md5")]
+        HashAlgorithm::Md5,
+        #[cfg(feature = "xxhash")]
+        HashAlgorithm::Xxh3,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            #[cfg(feature = "sha256")]
+            HashAlgorithm::Sha256 => "sha256",
+            #[cfg(feature = "md5")]
+            HashAlgorithm::Md5 => "md5",
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<HashAlgorithm, String> {
+        HashAlgorithm::ALL.iter()
+            .copied()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(value))
+            .ok_or_else(|| {
+                let names: Vec<_> = HashAlgorithm::ALL.iter().map(|algorithm| algorithm.name()).collect();
+                format!("unknown hash {} (available: {})", value, names.join(", "))
+            })
+    }
+
+    pub fn hasher(self) -> Box<dyn FileHasher> {
+        match self {
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            #[cfg(feature = "sha256")]
+            HashAlgorithm::Sha256 => Box::new(<sha2::Sha256 as sha2::Digest>::new()),
+            #[cfg(feature = "md5")]
+            HashAlgorithm::Md5 => Box::new(<md5::Md5 as md5::Digest>::new()),
+            #[cfg(feature = "xxhash")]
+            HashAlgorithm::Xxh3 => Box::new(xxhash_rust::xxh3::Xxh3::new()),
+        }
+    }
+}
+
+//What every --hash algorithm has to provide, adding one is an impl of this plus an enum variant
+pub trait FileHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+impl FileHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.finalize().as_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "sha256")]
+impl FileHasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        sha2::Digest::finalize(*self).to_vec()
+    }
+}
+
+#[cfg(feature = "md5")]
+impl FileHasher for md5::Md5 {
+    fn update(&mut self, data: &[u8]) {
+        md5::Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        md5::Digest::finalize(*self).to_vec()
+    }
+}
+
+#[cfg(feature = "xxhash")]
+impl FileHasher for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.digest128().to_le_bytes().to_vec()
+    }
+}
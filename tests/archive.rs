@@ -0,0 +1,78 @@
+mod common;
+
+use common::Scratch;
+
+//Builds a tar at path out of (name, contents) files and (name, target) symlinks, in the order given
+fn build_tar(path: &std::path::Path, entries: &[(&str, Entry)]) {
+    let mut builder = tar::Builder::new(std::fs::File::create(path).unwrap());
+    for (name, entry) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        match entry {
+            Entry::File(contents) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(contents.len() as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+            }
+            Entry::Link(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, name, target).unwrap();
+            }
+        }
+    }
+    builder.finish().unwrap();
+}
+
+enum Entry {
+    File(&'static str),
+    Link(String),
+}
+
+#[test]
+fn extracts_files_and_directories() {
+    let scratch = Scratch::new("archive-extract");
+    build_tar(&scratch.path("in.tar"), &[("a.txt", Entry::File("a")), ("sub/b.txt", Entry::File("b"))]);
+
+    scratch.rcpy(["in.tar", "out"]).assert_success();
+    assert_eq!(scratch.read("out/a.txt"), "a");
+    assert_eq!(scratch.read("out/sub/b.txt"), "b");
+}
+
+#[cfg(unix)]
+#[test]
+fn entry_through_an_earlier_symlink_stays_inside() {
+    let scratch = Scratch::new("archive-symlink-escape");
+    let victim = scratch.mkdir("victim");
+    build_tar(&scratch.path("evil.tar"), &[
+        ("link", Entry::Link(victim.to_string_lossy().into_owned())),
+        ("link/pwned.txt", Entry::File("pwned")),
+        ("ok.txt", Entry::File("ok")),
+    ]);
+
+    let run = scratch.rcpy(["evil.tar", "out"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("escapes the destination"), "{}", run.stderr);
+    assert!(!scratch.exists("victim/pwned.txt"));
+    //The rest of the archive is still extracted
+    assert_eq!(scratch.read("out/ok.txt"), "ok");
+}
+
+#[test]
+fn parent_dir_entry_is_refused() {
+    let scratch = Scratch::new("archive-dotdot");
+    //tar::Builder won't write .. itself, so the name goes straight into the header
+    let mut header = tar::Header::new_gnu();
+    header.as_gnu_mut().unwrap().name[..13].copy_from_slice(b"../escape.txt");
+    header.set_mode(0o644);
+    header.set_size(1);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    let mut builder = tar::Builder::new(std::fs::File::create(scratch.path("evil.tar")).unwrap());
+    builder.append(&header, &b"x"[..]).unwrap();
+    builder.finish().unwrap();
+
+    scratch.rcpy(["evil.tar", "out"]).assert_failure();
+    assert!(!scratch.exists("escape.txt"));
+}
@@ -0,0 +1,253 @@
+//Helpers shared by the integration tests, every test runs the built rcpy binary against its own scratch tree
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+static NEXT_SCRATCH: AtomicUsize = AtomicUsize::new(0);
+
+//A directory under the system temp dir that's removed again when the test is done
+pub struct Scratch {
+    pub root: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Scratch {
+        let root = std::env::temp_dir()
+            .join("rcpy-tests")
+            .join(format!("{}-{}-{}", name, std::process::id(), NEXT_SCRATCH.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        Scratch { root }
+    }
+
+    pub fn path(&self, rel: &str) -> PathBuf {
+        self.root.join(rel)
+    }
+
+    //Writes a file, creating the directories above it
+    pub fn write(&self, rel: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    pub fn mkdir(&self, rel: &str) -> PathBuf {
+        let path = self.path(rel);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    pub fn read(&self, rel: &str) -> String {
+        fs::read_to_string(self.path(rel)).unwrap()
+    }
+
+    pub fn exists(&self, rel: &str) -> bool {
+        self.path(rel).symlink_metadata().is_ok()
+    }
+
+    //Runs rcpy from the scratch root, so relative paths in args are relative to it and no .rcpyrc above it is picked up
+    pub fn rcpy<I, S>(&self, args: I) -> Run
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Run::from(self.command(args).output().unwrap())
+    }
+
+    //Same as rcpy, but the run is killed and the test fails if it takes longer than secs (a copy that never ends)
+    pub fn rcpy_within<I, S>(&self, secs: u64, args: I) -> Run
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut child = self.command(args).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(secs);
+        while child.try_wait().unwrap().is_none() {
+            if Instant::now() > deadline {
+                child.kill().unwrap();
+                panic!("rcpy was still running after {} seconds", secs);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Run::from(child.wait_with_output().unwrap())
+    }
+
+    //Runs rcpy as a user permissions apply to. As root the scratch tree is handed to nobody and rcpy runs as nobody,
+    //anyone else just runs it as themselves
+    #[cfg(unix)]
+    pub fn rcpy_unprivileged<I, S>(&self, args: I) -> Run
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        use std::os::unix::process::CommandExt;
+
+        if !is_root() {
+            return self.rcpy(args);
+        }
+        chown_tree(&self.root, NOBODY);
+        let mut command = Command::new(shared_binary());
+        command.current_dir(&self.root).args(args).env("COLUMNS", "120").uid(NOBODY).gid(NOBODY);
+        Run::from(command.output().unwrap())
+    }
+
+    //rcpy_unprivileged where there is such a thing, Windows enforces read-only for everyone anyway
+    pub fn rcpy_restricted<I, S>(&self, args: I) -> Run
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        #[cfg(unix)]
+        return self.rcpy_unprivileged(args);
+        #[cfg(not(unix))]
+        return self.rcpy(args);
+    }
+
+    pub fn command<I, S>(&self, args: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_rcpy"));
+        command.current_dir(&self.root).args(args).env("COLUMNS", "120");
+        command
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        //A test that takes permissions away has to give them back before this, or the tree stays behind
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+//What a finished run printed and how it exited
+pub struct Run {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl From<Output> for Run {
+    fn from(output: Output) -> Run {
+        Run {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }
+    }
+}
+
+impl Run {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    //Fails the test with everything the run printed, so a broken run explains itself
+    pub fn assert_success(&self) -> &Run {
+        assert!(self.success(), "rcpy exited with {:?}\nstdout:\n{}\nstderr:\n{}", self.code, self.stdout, self.stderr);
+        self
+    }
+
+    //The stats of a --format json run
+    #[cfg(feature = "serde")]
+    pub fn stats(&self) -> serde_json::Value {
+        let summary: serde_json::Value = serde_json::from_str(&self.stdout).unwrap_or_else(|e| panic!("{}: {}", e, self.stdout));
+        summary["stats"].clone()
+    }
+
+    pub fn assert_failure(&self) -> &Run {
+        assert!(!self.success(), "rcpy should have failed\nstdout:\n{}\nstderr:\n{}", self.stdout, self.stderr);
+        self
+    }
+}
+
+#[cfg(unix)]
+const NOBODY: u32 = 65534;
+
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+//The built binary usually sits under a home directory other users can't get into, so nobody gets its own
+//copy (a hard link when it's on the same filesystem) next to the scratch trees
+#[cfg(unix)]
+fn shared_binary() -> &'static Path {
+    static BINARY: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+    BINARY.get_or_init(|| {
+        let dir = std::env::temp_dir().join("rcpy-tests");
+        fs::create_dir_all(&dir).unwrap();
+        //Left behind by earlier test binaries, cargo runs them one at a time
+        for entry in fs::read_dir(&dir).unwrap().flatten() {
+            if entry.file_name().to_string_lossy().starts_with("rcpy-bin-") {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        let binary = dir.join(format!("rcpy-bin-{}", std::process::id()));
+        if fs::hard_link(env!("CARGO_BIN_EXE_rcpy"), &binary).is_err() {
+            fs::copy(env!("CARGO_BIN_EXE_rcpy"), &binary).unwrap();
+        }
+        binary
+    })
+}
+
+//Symlinks themselves are changed, never what they point to
+#[cfg(unix)]
+fn chown_tree(path: &Path, id: u32) {
+    std::os::unix::fs::lchown(path, Some(id), Some(id)).unwrap();
+    if path.is_dir() && !path.is_symlink() {
+        for entry in fs::read_dir(path).unwrap() {
+            chown_tree(&entry.unwrap().path(), id);
+        }
+    }
+}
+
+//Sets a file's modification time to secs_ago seconds before now
+pub fn set_age(path: &Path, secs_ago: u64) {
+    let file = fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(secs_ago)).unwrap();
+}
+
+pub fn modified(path: &Path) -> SystemTime {
+    fs::metadata(path).unwrap().modified().unwrap()
+}
+
+#[cfg(unix)]
+pub fn mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+}
+
+//Relative paths of everything under dir, sorted, directories end in /
+pub fn tree(dir: &Path) -> Vec<String> {
+    let mut entries = Vec::new();
+    collect(dir, dir, &mut entries);
+    entries.sort();
+    entries
+}
+
+fn collect(root: &Path, dir: &Path, entries: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let rel = path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/");
+        if path.is_dir() && !path.is_symlink() {
+            entries.push(format!("{}/", rel));
+            collect(root, &path, entries);
+        } else {
+            entries.push(rel);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::{tree, Scratch};
+
+fn with_config(name: &str, config: &str) -> Scratch {
+    let scratch = Scratch::new(name);
+    scratch.write(".rcpyrc", config);
+    scratch.write("src/a.txt", "a");
+    scratch.write("src/empty.txt", "");
+    scratch.write("src/.hidden", "h");
+    scratch
+}
+
+#[test]
+fn config_file_settings_apply_and_the_command_line_wins() {
+    let scratch = with_config("config-precedence", "exclude-empty = true\nformat = \"oneline\"\n");
+
+    let run = scratch.rcpy(["src", "from-file"]);
+    run.assert_success();
+    assert!(run.stdout.starts_with("rcpy: "), "{}", run.stdout);
+    assert_eq!(tree(&scratch.path("from-file")), [".hidden", "a.txt"]);
+
+    //A value given on the command line replaces the file's
+    let run = scratch.rcpy(["src", "from-cli", "--format", "text"]);
+    assert!(run.assert_success().stdout.contains("COPY COMPLETE"), "{}", run.stdout);
+
+    scratch.rcpy(["src", "no-config", "--no-config"]).assert_success();
+    assert_eq!(tree(&scratch.path("no-config")), [".hidden", "a.txt", "empty.txt"]);
+}
+
+#[test]
+fn a_flag_from_the_file_can_be_turned_off() {
+    let scratch = with_config("config-negation", "exclude-empty = true\nno-hidden = true\n");
+
+    scratch.rcpy(["src", "filtered"]).assert_success();
+    assert_eq!(tree(&scratch.path("filtered")), ["a.txt"]);
+
+    scratch.rcpy(["src", "everything", "--no-exclude-empty", "--hidden"]).assert_success();
+    assert_eq!(tree(&scratch.path("everything")), [".hidden", "a.txt", "empty.txt"]);
+}
+
+#[test]
+fn a_file_setting_conflicting_with_the_command_line_is_reported() {
+    let scratch = with_config("config-conflict", "only-files = true\n");
+
+    let run = scratch.rcpy(["src", "dst", "--only-dirs"]);
+    assert_eq!(run.code, Some(2), "{}", run.stderr);
+    assert!(run.stderr.contains("cannot be used with"), "{}", run.stderr);
+    assert!(run.stderr.contains(".rcpyrc"), "{}", run.stderr);
+    assert!(!scratch.exists("dst"));
+
+    scratch.rcpy(["src", "dst", "--no-only-files", "--only-dirs"]).assert_success();
+}
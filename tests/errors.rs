@@ -0,0 +1,160 @@
+mod common;
+
+use common::Scratch;
+
+//Makes copying src/<name> fail even as root: the destination already has a non-empty directory by that name
+fn blocked(scratch: &Scratch, name: &str) {
+    scratch.write(&format!("src/{}", name), "contents");
+    scratch.write(&format!("dst/{}/in-the-way", name), "");
+}
+
+#[test]
+fn ignored_failure_does_not_fail_the_run() {
+    let scratch = Scratch::new("ignore-error-matched");
+    blocked(&scratch, "app.lock");
+    scratch.write("src/fine.txt", "fine");
+
+    let run = scratch.rcpy(["src", "dst", "--ignore-error", "*.lock"]);
+    run.assert_success();
+    assert!(run.stdout.contains("1 error(s) ignored"), "{}", run.stdout);
+    assert_eq!(scratch.read("dst/fine.txt"), "fine");
+}
+
+#[test]
+fn failure_the_pattern_does_not_match_still_fails_the_run() {
+    let scratch = Scratch::new("ignore-error-unmatched");
+    blocked(&scratch, "app.lock");
+    blocked(&scratch, "data.db");
+
+    let run = scratch.rcpy(["src", "dst", "--ignore-error", "*.lock"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("data.db"), "{}", run.stderr);
+    assert!(!run.stderr.contains("app.lock"), "{}", run.stderr);
+}
+
+#[test]
+fn files_under_a_directory_that_could_not_be_created_are_reported_once() {
+    for mode in [None, Some("-s")] {
+        let scratch = Scratch::new("failed-dir");
+        for path in ["src/sub/1.txt", "src/sub/2.txt", "src/sub/3.txt", "src/sub/deeper/4.txt", "src/ok.txt"] {
+            scratch.write(path, "x");
+        }
+        //A file where the directory has to go
+        scratch.write("dst/sub", "in the way");
+
+        let run = scratch.rcpy(["src", "dst"].into_iter().chain(mode));
+        run.assert_failure();
+        assert!(run.stdout.contains("Skipped 4 file(s) under sub (directory couldn't be created)"), "{}", run.stdout);
+        assert!(run.stdout.contains("1 error(s)"), "{}", run.stdout);
+        //Only the directory itself is an error line, none of the files under it
+        assert_eq!(run.stderr.lines().filter(|line| !line.is_empty()).count(), 1, "{}", run.stderr);
+        assert_eq!(scratch.read("dst/ok.txt"), "x");
+    }
+}
+
+#[test]
+fn quiet_errors_prints_nothing_per_file_but_still_fails() {
+    let scratch = Scratch::new("quiet-errors");
+    blocked(&scratch, "a.txt");
+    blocked(&scratch, "b.txt");
+    scratch.write("src/fine.txt", "fine");
+
+    let run = scratch.rcpy(["src", "dst", "--quiet-errors"]);
+    run.assert_failure();
+    assert_eq!(run.stderr, "");
+    assert!(run.stdout.contains("2 error(s)"), "{}", run.stdout);
+    assert_eq!(scratch.read("dst/fine.txt"), "fine");
+}
+
+//procfs files stat as 0 bytes but read back with contents, so the copy never matches the size the source reports
+#[cfg(target_os = "linux")]
+#[test]
+fn verify_size_counts_a_copy_of_the_wrong_size() {
+    let scratch = Scratch::new("verify-size");
+    scratch.write("src/fine.txt", "fine");
+    std::os::unix::fs::symlink("/proc/version", scratch.path("src/version")).unwrap();
+
+    let run = scratch.rcpy(["src", "dst", "--dereference", "--verify-size"]);
+    run.assert_failure();
+    assert!(run.stdout.contains("1 of them copied to the wrong size"), "{}", run.stdout);
+    assert!(run.stderr.contains("but the source is 0"), "{}", run.stderr);
+
+    scratch.rcpy(["src", "unchecked", "--dereference"]).assert_success();
+}
+
+#[test]
+fn verify_sample_picks_the_same_files_for_a_seed() {
+    let scratch = Scratch::new("verify-sample");
+    for n in 1..=20 {
+        scratch.write(&format!("src/f{}.txt", n), n.to_string());
+    }
+    let verified = |dst: &str, seed: &str| {
+        scratch.rcpy(["src", dst, "--verify-sample", "50", "--seed", seed, "--format", "json"]).assert_success().stats()["verified"].clone()
+    };
+
+    //The pick is a blake3 hash of the seed and path, so it's pinned across builds and not just across runs
+    assert_eq!(verified("first", "1"), 9);
+    assert_eq!(verified("second", "1"), 9);
+    assert_eq!(verified("other-seed", "7"), 11);
+}
+
+#[test]
+fn verify_sample_honours_fractional_percentages() {
+    let scratch = Scratch::new("verify-sample-fraction");
+    for n in 1..=2000 {
+        scratch.write(&format!("src/f{}.txt", n), n.to_string());
+    }
+    let verified = |percent: &str| {
+        scratch.rcpy(["src", percent, "--verify-sample", percent, "--format", "json"]).assert_success().stats()["verified"].as_u64().unwrap()
+    };
+
+    //About 2, 10 and 20 of the 2000, a sample rounded to whole percents would check about 20 for all three
+    let (tenth, half, one) = (verified("0.1"), verified("0.5"), verified("1"));
+    assert!(tenth < half && half < one, "{} {} {}", tenth, half, one);
+    assert!(half < 16, "{}", half);
+}
+
+fn make_read_only(path: &std::path::Path) {
+    let mut permissions = std::fs::metadata(path).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(path, permissions).unwrap();
+}
+
+#[test]
+fn overwrite_readonly_replaces_a_read_only_destination() {
+    let scratch = Scratch::new("overwrite-readonly");
+    scratch.write("src/a.txt", "new");
+    make_read_only(&scratch.write("dst/a.txt", "old"));
+
+    scratch.rcpy_restricted(["src", "dst"]).assert_failure();
+    assert_eq!(scratch.read("dst/a.txt"), "old");
+
+    let run = scratch.rcpy_restricted(["src", "dst", "--overwrite-readonly"]);
+    run.assert_success();
+    assert_eq!(scratch.read("dst/a.txt"), "new");
+    assert!(run.stdout.contains("1 read-only destination file(s) replaced"), "{}", run.stdout);
+}
+
+#[cfg(all(unix, feature = "serde"))]
+#[test]
+fn unreadable_directory_is_skipped_and_counted() {
+    let scratch = Scratch::new("unreadable-dir");
+    scratch.write("src/ok/a.txt", "a");
+    scratch.write("src/locked/secret.txt", "s");
+    scratch.write("src/z.txt", "z");
+    common::set_mode(&scratch.path("src/locked"), 0o000);
+
+    let run = scratch.rcpy_unprivileged(["src", "dst", "--format", "json"]);
+    let aborted = scratch.rcpy_unprivileged(["src", "aborted", "--abort-on-walk-error"]);
+    common::set_mode(&scratch.path("src/locked"), 0o755);
+
+    run.assert_failure();
+    let stats = run.stats();
+    assert_eq!(stats["unreadable"], 1, "{}", run.stderr);
+    assert_eq!(stats["files"], 2);
+    assert!(run.stderr.contains("locked"), "{}", run.stderr);
+    assert_eq!(scratch.read("dst/ok/a.txt"), "a");
+    assert!(!scratch.exists("dst/locked/secret.txt"));
+
+    aborted.assert_failure();
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use common::{set_age, tree, Scratch};
+
+#[test]
+fn skip_recent_leaves_out_freshly_written_files() {
+    let scratch = Scratch::new("skip-recent");
+    scratch.write("src/fresh.log", "still being written");
+    let old = scratch.write("src/old.log", "done");
+    set_age(&old, 3600);
+
+    let run = scratch.rcpy(["src", "dst", "--skip-recent", "60"]);
+    run.assert_success();
+    assert!(scratch.exists("dst/old.log"));
+    assert!(!scratch.exists("dst/fresh.log"));
+    assert!(run.stdout.contains("1 file(s) too recent"), "{}", run.stdout);
+}
+
+#[test]
+fn skip_recent_longer_than_the_epoch_skips_everything() {
+    let scratch = Scratch::new("skip-recent-huge");
+    let old = scratch.write("src/old.log", "done");
+    set_age(&old, 3600);
+
+    scratch.rcpy(["src", "dst", "--skip-recent", &u64::MAX.to_string()]).assert_success();
+    assert!(!scratch.exists("dst/old.log"));
+}
+
+#[test]
+fn exclude_vcs_skips_version_control_metadata() {
+    let scratch = Scratch::new("exclude-vcs");
+    for path in ["src/a.txt", "src/.git/HEAD", "src/.git/objects/ab/cd", "src/.svn/entries", "src/sub/.hg/store", "src/sub/CVS/Root", "src/.gitignore", "src/sub/b.tmp"] {
+        scratch.write(path, "x");
+    }
+
+    scratch.rcpy(["src", "dst", "--exclude-vcs", "--exclude", "tmp"]).assert_success();
+    assert_eq!(tree(&scratch.path("dst")), ["a.txt", "sub/"]);
+}
+
+//A symlink loop inside .git is only noticed while walking it, so no loop reported means .git was never read
+#[cfg(all(unix, feature = "serde"))]
+#[test]
+fn exclude_vcs_prunes_the_directory_without_walking_it() {
+    let scratch = Scratch::new("exclude-vcs-pruned");
+    scratch.write("src/a.txt", "a");
+    scratch.write("src/.git/objects/ab/cd", "x");
+    std::os::unix::fs::symlink("..", scratch.path("src/.git/objects/loop")).unwrap();
+
+    let walked = scratch.rcpy(["src", "walked", "--copy-contents", "--format", "json"]);
+    assert_eq!(walked.assert_success().stats()["symlink_loops"], 1);
+
+    let pruned = scratch.rcpy(["src", "pruned", "--copy-contents", "--exclude-vcs", "--format", "json"]);
+    assert_eq!(pruned.assert_success().stats()["symlink_loops"], 0);
+    assert_eq!(tree(&scratch.path("pruned")), ["a.txt"]);
+}
+
+#[test]
+fn newer_than_file_copies_only_files_changed_after_the_marker() {
+    let scratch = Scratch::new("newer-than-file");
+    let marker = scratch.write("last-backup", "");
+    set_age(&marker, 600);
+    let before = scratch.write("src/old/before.txt", "before");
+    set_age(&before, 1200);
+    let after = scratch.write("src/new/after.txt", "after");
+    set_age(&after, 60);
+    scratch.write("src/new/now.txt", "now");
+
+    scratch.rcpy(["src", "dst", "--newer-than-file", "last-backup"]).assert_success();
+    assert_eq!(tree(&scratch.path("dst")), ["new/", "new/after.txt", "new/now.txt", "old/"]);
+}
+
+#[test]
+fn newer_than_file_fails_when_the_marker_is_missing() {
+    let scratch = Scratch::new("newer-than-file-missing");
+    scratch.write("src/a.txt", "a");
+
+    let run = scratch.rcpy(["src", "dst", "--newer-than-file", "no-such-marker"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("--newer-than-file reference no-such-marker"), "{}", run.stderr);
+    assert!(!scratch.exists("dst"));
+}
+
+#[test]
+fn single_file_copy_respects_excludes() {
+    let scratch = Scratch::new("single-file-excluded");
+    scratch.write("scratch.tmp", "x");
+    scratch.write("notes.txt", "n");
+
+    let run = scratch.rcpy(["scratch.tmp", "out.tmp", "--exclude", "tmp"]);
+    run.assert_success();
+    assert!(run.stdout.contains("Nothing to copy: scratch.tmp is filtered out."), "{}", run.stdout);
+    assert!(!scratch.exists("out.tmp"));
+
+    scratch.rcpy(["notes.txt", "out.txt", "--exclude", "tmp"]).assert_success();
+    assert_eq!(scratch.read("out.txt"), "n");
+}
+
+#[test]
+fn single_file_copy_respects_attribute_filters() {
+    let scratch = Scratch::new("single-file-filtered");
+    scratch.write("empty.txt", "");
+
+    scratch.rcpy(["empty.txt", "out.txt", "--exclude-empty"]).assert_success();
+    assert!(!scratch.exists("out.txt"));
+}
+
+#[cfg(all(unix, feature = "serde"))]
+#[test]
+fn attribute_filters_each_skip_their_own_kind() {
+    let scratch = Scratch::new("attribute-filters");
+    scratch.write("src/data.txt", "data");
+    scratch.write("src/empty.txt", "");
+    let tool = scratch.write("src/tool.sh", "#!/bin/sh");
+    common::set_mode(&tool, 0o755);
+    std::os::unix::fs::symlink("data.txt", scratch.path("src/link")).unwrap();
+
+    let run = scratch.rcpy(["src", "no-empty", "--exclude-empty", "--format", "json"]);
+    assert_eq!(run.assert_success().stats()["skipped_empty"], 1);
+    assert_eq!(tree(&scratch.path("no-empty")), ["data.txt", "link", "tool.sh"]);
+
+    let run = scratch.rcpy(["src", "no-links", "--exclude-symlinks", "--format", "json"]);
+    assert_eq!(run.assert_success().stats()["skipped_symlinks"], 1);
+    assert_eq!(tree(&scratch.path("no-links")), ["data.txt", "empty.txt", "tool.sh"]);
+
+    let run = scratch.rcpy(["src", "executables", "--only-executable", "--format", "json"]);
+    assert_eq!(run.assert_success().stats()["skipped_not_executable"], 3);
+    assert_eq!(tree(&scratch.path("executables")), ["tool.sh"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn pruned_subtrees_are_not_counted() {
+    let scratch = Scratch::new("pruned-counts");
+    for path in ["src/top.txt", "src/keep/a.txt", "src/.git/HEAD", "src/.git/objects/ab/cd", "src/.cache/deep/x.bin", "src/skip.tmp"] {
+        scratch.write(path, "x");
+    }
+
+    for mode in [None, Some("-s")] {
+        let dst = if mode.is_some() { "single" } else { "parallel" };
+        let run = scratch.rcpy(["src", dst, "--exclude-vcs", "--no-hidden", "--exclude", "tmp", "--format", "json"].into_iter().chain(mode));
+        let stats = run.assert_success().stats();
+        //The destination root and keep/, nothing from .git or .cache
+        assert_eq!(stats["dirs"], 2, "{:?}", mode);
+        assert_eq!(stats["files"], 2, "{:?}", mode);
+        assert_eq!(stats["bytes"], 2, "{:?}", mode);
+        assert_eq!(tree(&scratch.path(dst)), ["keep/", "keep/a.txt", "top.txt"]);
+    }
+}
+
+#[test]
+fn newest_copies_only_the_most_recently_modified_files() {
+    let scratch = Scratch::new("newest");
+    set_age(&scratch.write("src/a-oldest.txt", "1"), 4000);
+    set_age(&scratch.write("src/nested/b-newest.txt", "2"), 100);
+    set_age(&scratch.write("src/c-old.txt", "3"), 3000);
+    set_age(&scratch.write("src/nested/deeper/d-newer.txt", "4"), 200);
+    set_age(&scratch.write("src/skipped.tmp", "5"), 10);
+
+    //The excluded file would be newest of all, --newest picks after the other filters
+    let run = scratch.rcpy(["src", "dst", "--newest", "2", "--exclude", "tmp"]);
+    run.assert_success();
+    assert!(run.stdout.contains("Selected the 2 most recently modified of 4 file(s) (--newest)"), "{}", run.stdout);
+    assert_eq!(tree(&scratch.path("dst")), ["nested/", "nested/b-newest.txt", "nested/deeper/", "nested/deeper/d-newer.txt"]);
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{tree, Scratch};
+use std::path::Path;
+use std::process::Command;
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=rcpy", "-c", "user.email=rcpy@example.com", "-c", "commit.gpgsign=false"])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+//A repository in src with a commit tagged base, then a second commit that edits, adds, renames and deletes
+fn repo_fixture(scratch: &Scratch) {
+    let src = scratch.path("src");
+    scratch.write("src/unchanged.txt", "same");
+    scratch.write("src/edited.txt", "before");
+    scratch.write("src/old-name.txt", "moved contents that git can follow");
+    scratch.write("src/removed.txt", "gone");
+    git(&src, &["init", "-q"]);
+    git(&src, &["add", "."]);
+    git(&src, &["commit", "-q", "-m", "base"]);
+    git(&src, &["tag", "base"]);
+
+    scratch.write("src/edited.txt", "after");
+    scratch.write("src/sub/added.txt", "new");
+    git(&src, &["mv", "old-name.txt", "new-name.txt"]);
+    git(&src, &["rm", "-q", "removed.txt"]);
+    git(&src, &["add", "."]);
+    git(&src, &["commit", "-q", "-m", "changes"]);
+    scratch.write("src/untracked.txt", "not in git");
+}
+
+#[test]
+fn since_git_copies_only_what_changed() {
+    let scratch = Scratch::new("since-git");
+    repo_fixture(&scratch);
+
+    scratch.rcpy(["src", "dst", "--since-git", "base"]).assert_success();
+    let copied: Vec<String> = tree(&scratch.path("dst")).into_iter().filter(|path| !path.ends_with('/')).collect();
+    assert_eq!(copied, ["edited.txt", "new-name.txt", "sub/added.txt"]);
+    assert_eq!(scratch.read("dst/edited.txt"), "after");
+}
+
+#[test]
+fn since_git_delete_removes_deleted_and_renamed_files() {
+    let scratch = Scratch::new("since-git-delete");
+    repo_fixture(&scratch);
+    for stale in ["unchanged.txt", "old-name.txt", "removed.txt"] {
+        scratch.write(&format!("dst/{}", stale), "from the last deploy");
+    }
+
+    scratch.rcpy(["src", "dst", "--since-git", "base", "--since-git-delete"]).assert_success();
+    assert!(!scratch.exists("dst/old-name.txt"));
+    assert!(!scratch.exists("dst/removed.txt"));
+    assert_eq!(scratch.read("dst/unchanged.txt"), "from the last deploy");
+    assert_eq!(scratch.read("dst/new-name.txt"), "moved contents that git can follow");
+}
+
+#[test]
+fn since_git_outside_a_repository_is_an_error() {
+    let scratch = Scratch::new("since-git-no-repo");
+    scratch.write("src/a.txt", "a");
+
+    let run = scratch.command(["src", "dst", "--since-git", "HEAD"]).env("GIT_CEILING_DIRECTORIES", &scratch.root).output().unwrap();
+    assert!(!run.status.success());
+    assert!(String::from_utf8_lossy(&run.stderr).contains("--since-git HEAD"));
+    assert!(!scratch.exists("dst"));
+}
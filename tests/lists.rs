@@ -0,0 +1,46 @@
+mod common;
+
+use common::Scratch;
+
+#[cfg(unix)]
+#[test]
+fn null_separated_files_from_handles_a_newline_in_a_name() {
+    let scratch = Scratch::new("files-from-null");
+    scratch.write("src/we\nird.txt", "weird");
+    scratch.write("src/we", "decoy");
+    scratch.write("src/ird.txt", "decoy");
+    scratch.write("list", "we\nird.txt\0");
+
+    scratch.rcpy(["src", "dst", "--files-from", "list", "--null"]).assert_success();
+    assert_eq!(scratch.read("dst/we\nird.txt"), "weird");
+    assert!(!scratch.exists("dst/we"));
+    assert!(!scratch.exists("dst/ird.txt"));
+}
+
+#[test]
+fn newline_separated_files_from_copies_only_the_listed_paths() {
+    let scratch = Scratch::new("files-from-lines");
+    scratch.write("src/keep.txt", "keep");
+    scratch.write("src/sub/also.txt", "also");
+    scratch.write("src/skip.txt", "skip");
+    scratch.write("list", "keep.txt\r\n./sub/also.txt\n");
+
+    scratch.rcpy(["src", "dst", "--files-from", "list"]).assert_success();
+    assert!(scratch.exists("dst/keep.txt"));
+    assert!(scratch.exists("dst/sub/also.txt"));
+    assert!(!scratch.exists("dst/skip.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn null_separated_list_output_keeps_a_newline_in_a_name() {
+    let scratch = Scratch::new("list-null");
+    scratch.write("src/we\nird.txt", "weird");
+    scratch.write("src/plain.txt", "plain");
+
+    let run = scratch.rcpy(["src", "dst", "--list", "-0"]);
+    run.assert_success();
+    let mut listed: Vec<&str> = run.stdout.split('\0').filter(|name| !name.is_empty()).collect();
+    listed.sort();
+    assert_eq!(listed, ["plain.txt", "we\nird.txt"]);
+}
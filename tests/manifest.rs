@@ -0,0 +1,75 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn diff_manifest_uses_the_copy_filters() {
+    let scratch = Scratch::new("diff-manifest-filters");
+    scratch.write("src/a.txt", "a");
+    scratch.write("src/empty.txt", "");
+    scratch.write("src/skip.tmp", "t");
+
+    scratch.rcpy(["src", "dst", "--diff-manifest", "last.manifest", "--exclude-empty", "--exclude", "tmp"]).assert_success();
+    let manifest = scratch.read("last.manifest");
+    assert!(manifest.contains("\ta.txt\n"), "{}", manifest);
+    assert!(!manifest.contains("empty.txt"), "{}", manifest);
+    assert!(!manifest.contains("skip.tmp"), "{}", manifest);
+}
+
+#[cfg(unix)]
+#[test]
+fn names_with_newlines_stay_one_entry() {
+    let scratch = Scratch::new("diff-manifest-newline");
+    scratch.write("src/two\nlines.txt", "x");
+    scratch.write("src/plain.txt", "y");
+
+    let first = scratch.rcpy(["src", "dst", "--diff-manifest", "last.manifest"]);
+    assert!(first.assert_success().stdout.contains("2 new, 0 changed, 0 removed."), "{}", first.stdout);
+    assert_eq!(scratch.read("last.manifest").lines().count(), 3);
+
+    let second = scratch.rcpy(["src", "dst", "--diff-manifest", "last.manifest"]);
+    assert!(second.assert_success().stdout.contains("0 new, 0 changed, 0 removed."), "{}", second.stdout);
+}
+
+#[test]
+fn manifest_copies_exactly_the_listed_files() {
+    let scratch = Scratch::new("manifest-restore");
+    scratch.write("src/a.txt", "hello\n");
+    scratch.write("src/sub/b.txt", "world\n");
+    scratch.write("src/unlisted.txt", "not listed");
+    let hash = blake3::hash(b"hello\n").to_hex();
+    scratch.write("restore.list", format!("# restore\n6\t{}\ta.txt\n6\tsub/b.txt\n", hash));
+
+    let run = scratch.rcpy(["src", "dst", "--manifest", "restore.list"]);
+    run.assert_success();
+    assert!(run.stdout.contains("Verified 2 file(s)"), "{}", run.stdout);
+    assert_eq!(common::tree(&scratch.path("dst")), ["a.txt", "sub/", "sub/b.txt"]);
+}
+
+#[test]
+fn manifest_entry_missing_from_the_source_is_an_error() {
+    let scratch = Scratch::new("manifest-missing");
+    scratch.write("src/a.txt", "a");
+    scratch.write("restore.list", "a.txt\nmissing.txt\n");
+
+    let run = scratch.rcpy(["src", "dst", "--manifest", "restore.list"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("Missing missing.txt: listed in the manifest but not in the source"), "{}", run.stderr);
+    assert!(!run.stdout.contains("mismatch"), "{}", run.stdout);
+    assert_eq!(scratch.read("dst/a.txt"), "a");
+}
+
+#[test]
+fn manifest_size_or_hash_mismatch_is_a_verification_failure() {
+    let scratch = Scratch::new("manifest-mismatch");
+    scratch.write("src/short.txt", "six b\n");
+    scratch.write("src/changed.txt", "changed\n");
+    let stale = blake3::hash(b"original\n").to_hex();
+    scratch.write("restore.list", format!("5\tshort.txt\n8\t{}\tchanged.txt\n", stale));
+
+    let run = scratch.rcpy(["src", "dst", "--manifest", "restore.list"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("Verification failed for short.txt: 6 bytes, the manifest says 5"), "{}", run.stderr);
+    assert!(run.stderr.contains("Verification failed for changed.txt"), "{}", run.stderr);
+    assert!(run.stdout.contains("2 mismatch(es)"), "{}", run.stdout);
+}
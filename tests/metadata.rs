@@ -0,0 +1,60 @@
+mod common;
+
+use common::Scratch;
+
+#[cfg(target_os = "macos")]
+#[test]
+fn resource_fork_and_finder_info_survive_the_copy() {
+    use std::process::Command;
+
+    let scratch = Scratch::new("resource-fork");
+    let file = scratch.write("src/legacy.app", "data");
+    let finder_info = "54455854 74747874".to_string() + &" 00000000".repeat(6);
+    for (name, hex) in [("com.apple.ResourceFork", "DEADBEEF00FF"), ("com.apple.FinderInfo", finder_info.as_str())] {
+        let status = Command::new("xattr").args(["-wx", name, hex]).arg(&file).status().unwrap();
+        assert!(status.success(), "xattr -wx {} failed", name);
+    }
+
+    scratch.rcpy(["src", "dst", "--preserve-resource-fork"]).assert_success();
+    let read = |name: &str| {
+        let output = Command::new("xattr").args(["-px", name]).arg(scratch.path("dst/legacy.app")).output().unwrap();
+        assert!(output.status.success(), "{} is missing on the copy", name);
+        String::from_utf8(output.stdout).unwrap().split_whitespace().collect::<String>()
+    };
+    assert_eq!(read("com.apple.ResourceFork"), "DEADBEEF00FF");
+    assert_eq!(read("com.apple.FinderInfo"), finder_info.replace(' ', ""));
+}
+
+#[cfg(not(target_os = "macos"))]
+#[test]
+fn resource_fork_option_warns_outside_macos() {
+    let scratch = Scratch::new("resource-fork-elsewhere");
+    scratch.write("src/a.txt", "a");
+
+    let run = scratch.rcpy(["src", "dst", "--preserve-resource-fork"]);
+    run.assert_success();
+    assert!(run.stderr.contains("--preserve-resource-fork only works on macOS"), "{}", run.stderr);
+    assert_eq!(scratch.read("dst/a.txt"), "a");
+}
+
+#[cfg(unix)]
+#[test]
+fn preserve_links_keeps_hard_links_between_source_files() {
+    use std::os::unix::fs::MetadataExt;
+
+    let scratch = Scratch::new("preserve-links");
+    scratch.write("src/a.txt", "shared");
+    scratch.mkdir("src/nested");
+    std::fs::hard_link(scratch.path("src/a.txt"), scratch.path("src/nested/b.txt")).unwrap();
+    scratch.write("src/c.txt", "shared");
+    let inode = |rel: &str| std::fs::metadata(scratch.path(rel)).unwrap().ino();
+
+    let run = scratch.rcpy(["src", "linked", "--preserve", "links", "--format", "json"]);
+    assert_eq!(run.assert_success().stats()["deduplicated"], 1);
+    assert_eq!(inode("linked/a.txt"), inode("linked/nested/b.txt"));
+    //Same contents but never linked in the source, so not linked in the copy either
+    assert_ne!(inode("linked/a.txt"), inode("linked/c.txt"));
+
+    scratch.rcpy(["src", "separate"]).assert_success();
+    assert_ne!(inode("separate/a.txt"), inode("separate/nested/b.txt"));
+}
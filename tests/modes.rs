@@ -0,0 +1,358 @@
+mod common;
+
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+use common::{modified, set_age, tree, Scratch};
+
+#[test]
+fn structure_only_creates_directories_but_no_files() {
+    let scratch = Scratch::new("structure-only");
+    scratch.write("src/a.txt", "a");
+    scratch.write("src/sub/deeper/b.txt", "b");
+    scratch.mkdir("src/empty");
+
+    let run = scratch.rcpy(["src", "dst", "--structure-only"]);
+    run.assert_success();
+    assert_eq!(tree(&scratch.path("dst")), ["empty/", "sub/", "sub/deeper/"]);
+    assert!(run.stdout.contains("(structure only)"), "{}", run.stdout);
+}
+
+#[test]
+fn default_merges_contents_into_an_existing_destination() {
+    let scratch = Scratch::new("merge-default");
+    scratch.write("project/a.txt", "a");
+    scratch.write("backup/existing.txt", "kept");
+
+    scratch.rcpy(["project", "backup"]).assert_success();
+    assert_eq!(tree(&scratch.path("backup")), ["a.txt", "existing.txt"]);
+}
+
+#[test]
+fn into_copies_the_source_as_a_subdirectory_of_an_existing_destination() {
+    let scratch = Scratch::new("merge-into");
+    scratch.write("project/a.txt", "a");
+    scratch.write("backup/existing.txt", "kept");
+
+    scratch.rcpy(["project", "backup", "--into"]).assert_success();
+    assert_eq!(tree(&scratch.path("backup")), ["existing.txt", "project/", "project/a.txt"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn stats_stay_correct_on_a_large_tree() {
+    let scratch = Scratch::new("large-tree");
+    let mut files = 0;
+    for top in 0..20 {
+        for nested in 0..5 {
+            for file in 0..12 {
+                scratch.write(&format!("src/d{}/n{}/f{}.txt", top, nested, file), format!("{}-{}-{}", top, nested, file));
+                files += 1;
+            }
+        }
+    }
+    let expected = tree(&scratch.path("src"));
+
+    for (dst, threads) in [("parallel", None), ("single", Some("-s"))] {
+        let run = scratch.rcpy(["src", dst, "--format", "json"].into_iter().chain(threads));
+        run.assert_success();
+        let stats = run.stats();
+        assert_eq!(stats["files"], files);
+        //Every d and n directory plus the destination root
+        assert_eq!(stats["dirs"], 20 + 20 * 5 + 1);
+        assert_eq!(stats["errors"], 0);
+        assert_eq!(tree(&scratch.path(dst)), expected);
+    }
+}
+
+#[test]
+fn single_threaded_breadth_first_copies_shallow_files_first() {
+    let scratch = Scratch::new("breadth-first");
+    for path in ["src/z.txt", "src/a/deep/deeper/1.txt", "src/a/2.txt", "src/b/c/3.txt", "src/m.txt", "src/b/4.txt", "src/a/deep/5.txt"] {
+        scratch.write(path, "x");
+    }
+
+    let run = scratch.rcpy(["src", "dst", "--order", "breadth-first", "-s", "--verbose"]);
+    run.assert_success();
+    let depths: Vec<usize> = run
+        .stdout
+        .lines()
+        .filter_map(|line| line.strip_prefix("[FILE] "))
+        .map(|line| line.split(" -> ").next().unwrap().matches('/').count())
+        .collect();
+    assert_eq!(depths.len(), 7, "{}", run.stdout);
+    assert!(depths.is_sorted(), "files out of breadth-first order:\n{}", run.stdout);
+}
+
+#[test]
+fn parallel_verbose_lines_are_never_interleaved() {
+    let scratch = Scratch::new("parallel-verbose");
+    for i in 0..400 {
+        scratch.write(&format!("src/d{}/file-{}.txt", i % 8, i), "x".repeat(i));
+    }
+
+    //More workers than this machine may have cores, so the lines really are printed concurrently
+    let output = scratch.command(["src", "dst", "--verbose"]).env("RAYON_NUM_THREADS", "8").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let files: Vec<&str> = stdout.lines().filter(|line| line.contains("file-")).collect();
+    assert_eq!(files.len(), 400, "{}", stdout);
+    for line in files {
+        let (from, to) = line.strip_prefix("[FILE] src/").and_then(|rest| rest.split_once(" -> dst/")).unwrap_or_else(|| panic!("garbled line: {:?}", line));
+        assert_eq!(from, to, "garbled line: {:?}", line);
+        assert!(from.ends_with(".txt") && from.matches("file-").count() == 1, "garbled line: {:?}", line);
+    }
+}
+
+#[test]
+fn fsync_copies_normally() {
+    let scratch = Scratch::new("fsync");
+    scratch.write("src/a.txt", "a");
+    scratch.write("src/sub/b.txt", "b".repeat(200_000));
+    scratch.write("single.txt", "single");
+
+    for (dst, mode) in [("parallel", None), ("single-threaded", Some("-s"))] {
+        scratch.rcpy(["src", dst, "--fsync"].into_iter().chain(mode)).assert_success();
+        assert_eq!(tree(&scratch.path(dst)), ["a.txt", "sub/", "sub/b.txt"]);
+        assert_eq!(scratch.read(&format!("{}/sub/b.txt", dst)), "b".repeat(200_000));
+    }
+    scratch.rcpy(["single.txt", "copied.txt", "--fsync"]).assert_success();
+    assert_eq!(scratch.read("copied.txt"), "single");
+}
+
+#[test]
+fn dedupe_report_groups_identical_files() {
+    let scratch = Scratch::new("dedupe-report");
+    scratch.write("src/a.txt", "same contents");
+    scratch.write("src/sub/b.txt", "same contents");
+    scratch.write("src/c.txt", "other content");
+
+    let run = scratch.rcpy(["src", "--dedupe-report"]);
+    run.assert_success();
+    assert!(run.stdout.contains("2 copies of 13 B (13 B wasted):\n  a.txt\n  sub/b.txt\n"), "{}", run.stdout);
+    assert!(run.stdout.contains("1 group(s) of duplicates, 1 redundant file(s), 13 B reclaimable."), "{}", run.stdout);
+}
+
+//A link named so its own size matches its target's used to be reported as another copy of it
+#[cfg(unix)]
+#[test]
+fn dedupe_report_leaves_symlinks_out() {
+    let scratch = Scratch::new("dedupe-report-symlinks");
+    scratch.write("src/a.txt", "same\n");
+    scratch.write("src/b.txt", "same\n");
+    std::os::unix::fs::symlink("a.txt", scratch.path("src/link")).unwrap();
+
+    let run = scratch.rcpy(["src", "--dedupe-report"]);
+    run.assert_success();
+    assert!(run.stdout.contains("2 copies of 5 B"), "{}", run.stdout);
+    assert!(!run.stdout.contains("link"), "{}", run.stdout);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn zero_byte_files_are_all_recreated() {
+    let scratch = Scratch::new("zero-byte");
+    let empties = ["src/.gitkeep", "src/a/.gitkeep", "src/a/b/marker", "src/c/EMPTY", "src/c/d/e/.keep"];
+    for path in empties {
+        scratch.write(path, "");
+    }
+    scratch.write("src/a/data.txt", "data");
+
+    for (dst, mode) in [("parallel", None), ("single", Some("-s"))] {
+        let run = scratch.rcpy(["src", dst, "--format", "json"].into_iter().chain(mode));
+        let stats = run.assert_success().stats();
+        assert_eq!(stats["files"], 6, "{:?}", mode);
+        assert_eq!(stats["bytes"], 4, "{:?}", mode);
+        for path in empties {
+            let copied = scratch.path(&path.replacen("src", dst, 1));
+            assert_eq!(std::fs::metadata(&copied).map(|m| m.len()).ok(), Some(0), "{} is missing", copied.display());
+        }
+    }
+}
+
+#[test]
+fn no_clobber_newer_leaves_newer_destination_files_alone() {
+    let scratch = Scratch::new("no-clobber-newer");
+    set_age(&scratch.write("src/edited-at-dest.txt", "source"), 7200);
+    scratch.write("dst/edited-at-dest.txt", "edited at the destination");
+    scratch.write("src/stale-at-dest.txt", "fresh");
+    set_age(&scratch.write("dst/stale-at-dest.txt", "stale"), 7200);
+
+    let run = scratch.rcpy(["src", "dst", "--no-clobber-newer"]);
+    run.assert_success();
+    assert!(run.stdout.contains("1 destination file(s) are newer than the source and were left alone"), "{}", run.stdout);
+    assert_eq!(scratch.read("dst/edited-at-dest.txt"), "edited at the destination");
+    assert_eq!(scratch.read("dst/stale-at-dest.txt"), "fresh");
+
+    //Without the safeguard the newer file is overwritten as usual
+    scratch.rcpy(["src", "dst"]).assert_success();
+    assert_eq!(scratch.read("dst/edited-at-dest.txt"), "source");
+}
+
+#[test]
+fn clobber_empty_only_fills_in_placeholders_but_keeps_real_files() {
+    let scratch = Scratch::new("clobber-empty-only");
+    scratch.write("src/placeholder.txt", "recovered contents");
+    scratch.write("dst/placeholder.txt", "");
+    scratch.write("src/kept.txt", "source");
+    scratch.write("dst/kept.txt", "already there");
+    scratch.write("src/new.txt", "new");
+
+    let run = scratch.rcpy(["src", "dst", "--clobber-empty-only"]);
+    run.assert_success();
+    assert!(run.stdout.contains("1 destination file(s) weren't empty and were left alone by --clobber-empty-only"), "{}", run.stdout);
+    assert_eq!(scratch.read("dst/placeholder.txt"), "recovered contents");
+    assert_eq!(scratch.read("dst/kept.txt"), "already there");
+    assert_eq!(scratch.read("dst/new.txt"), "new");
+}
+
+#[test]
+fn rename_conflicts_keeps_both_versions_when_merging_two_sources() {
+    let scratch = Scratch::new("rename-conflicts");
+    scratch.write("laptop/notes.txt", "laptop notes");
+    scratch.write("laptop/same.txt", "identical");
+    scratch.write("laptop/docs/report.md", "laptop report");
+    scratch.write("desktop/notes.txt", "desktop notes");
+    scratch.write("desktop/same.txt", "identical");
+    scratch.write("desktop/docs/report.md", "desktop report");
+    scratch.write("desktop/only-desktop.txt", "d");
+
+    scratch.rcpy(["laptop", "merged", "--rename-conflicts"]).assert_success();
+    let run = scratch.rcpy(["desktop", "merged", "--rename-conflicts", "--format", "json"]);
+    //notes.txt and docs/report.md differ, same.txt is identical and simply overwritten
+    assert_eq!(run.assert_success().stats()["renamed_conflicts"], 2);
+    assert_eq!(tree(&scratch.path("merged")), ["docs/", "docs/report (2).md", "docs/report.md", "notes (2).txt", "notes.txt", "only-desktop.txt", "same.txt"]);
+    assert_eq!(scratch.read("merged/notes.txt"), "laptop notes");
+    assert_eq!(scratch.read("merged/notes (2).txt"), "desktop notes");
+
+    //Merging the same source again finds its files already there, nothing piles up
+    scratch.rcpy(["desktop", "merged", "--rename-conflicts"]).assert_success();
+    assert_eq!(tree(&scratch.path("merged")).len(), 7);
+}
+
+#[test]
+fn progress_interval_reports_on_a_timer_during_a_small_copy() {
+    let scratch = Scratch::new("progress-interval");
+    scratch.write("src/big.bin", vec![7u8; 32 * 1024 * 1024]);
+    scratch.write("src/small.txt", "small");
+
+    let run = scratch.rcpy(["src", "dst", "--progress", "porcelain", "--progress-interval", "0.001"]);
+    run.assert_success();
+    let lines: Vec<&str> = run.stderr.lines().collect();
+    assert!(lines.iter().any(|line| line.starts_with("PROGRESS bytes=")), "{}", run.stderr);
+    //The timer stops with the copy, DONE is the last thing it reports
+    assert_eq!(lines.iter().rfind(|line| line.starts_with("PROGRESS ") || line.starts_with("DONE ")), Some(&"DONE bytes=33554437 total=33554437 files=2 errors=0"));
+}
+
+#[cfg(unix)]
+#[test]
+fn link_dest_shares_unchanged_files_with_the_previous_backup() {
+    use std::os::unix::fs::MetadataExt;
+
+    let scratch = Scratch::new("link-dest");
+    scratch.write("src/same.txt", "unchanged since monday");
+    scratch.write("src/edited.txt", "monday");
+    scratch.rcpy(["src", "monday"]).assert_success();
+    //Same size so only the contents tell them apart
+    scratch.write("src/edited.txt", "friday");
+
+    let run = scratch.rcpy(["src", "tuesday", "--link-dest", "monday", "--format", "json"]);
+    assert_eq!(run.assert_success().stats()["linked_previous"], 1);
+    let inode = |rel: &str| std::fs::metadata(scratch.path(rel)).unwrap().ino();
+    assert_eq!(inode("tuesday/same.txt"), inode("monday/same.txt"));
+    assert_ne!(inode("tuesday/edited.txt"), inode("monday/edited.txt"));
+    assert_eq!(scratch.read("tuesday/edited.txt"), "friday");
+    assert_eq!(scratch.read("monday/edited.txt"), "monday");
+}
+
+#[test]
+fn keep_dest_dir_times_leaves_existing_directories_untouched() {
+    let scratch = Scratch::new("keep-dest-dir-times");
+    scratch.write("src/photos/new.jpg", "jpg");
+    scratch.write("src/fresh/a.txt", "a");
+    scratch.write("dst/photos/old.jpg", "old");
+    let week_ago = SystemTime::now() - Duration::from_secs(7 * 24 * 3600);
+    File::open(scratch.path("dst/photos")).unwrap().set_modified(week_ago).unwrap();
+
+    scratch.rcpy(["src", "dst", "--keep-dest-dir-times"]).assert_success();
+    assert_eq!(scratch.read("dst/photos/new.jpg"), "jpg");
+    assert_eq!(modified(&scratch.path("dst/photos")), week_ago);
+    //A directory the copy created gets its times as usual
+    assert!(modified(&scratch.path("dst/fresh")) > week_ago);
+
+    //Without the flag copying into it bumps the time
+    scratch.write("src/photos/another.jpg", "jpg");
+    scratch.rcpy(["src", "dst"]).assert_success();
+    assert!(modified(&scratch.path("dst/photos")) > week_ago);
+}
+
+#[cfg(unix)]
+#[test]
+fn ordered_runs_create_the_destination_in_the_same_order() {
+    use std::os::unix::fs::MetadataExt;
+
+    let scratch = Scratch::new("ordered");
+    for name in ["zeta.txt", "alpha.txt", "mid/b.txt", "mid/a.txt", "omega/z.txt", "beta.txt"] {
+        scratch.write(&format!("src/{}", name), name);
+    }
+    //What each run reports creating, in the order it happened
+    let created = |dst: &str| {
+        let run = scratch.rcpy(["src", dst, "--ordered", "--verbose"]);
+        run.assert_success();
+        run.stdout.lines()
+            .filter_map(|line| line.strip_prefix("[DIR] ").or_else(|| line.split_once(" -> ").map(|(_, dest)| dest)))
+            .map(|dest| dest.strip_prefix(dst).unwrap().trim_start_matches('/').to_string())
+            .collect::<Vec<_>>()
+    };
+    //The destination's entries in the order the filesystem handed out their inodes
+    let inode_order = |dst: &str| {
+        let root = scratch.path(dst);
+        let mut entries: Vec<(u64, String)> = tree(&root).into_iter()
+            .map(|rel| (std::fs::symlink_metadata(root.join(&rel)).unwrap().ino(), rel))
+            .collect();
+        entries.sort();
+        entries.into_iter().map(|(_, rel)| rel).collect::<Vec<_>>()
+    };
+
+    let expected = ["", "mid", "omega", "alpha.txt", "beta.txt", "mid/a.txt", "mid/b.txt", "omega/z.txt", "zeta.txt"];
+    assert_eq!(created("first"), expected);
+    assert_eq!(created("second"), expected);
+
+    //Inodes freed by tests running alongside can be handed out again in the middle of a run, so a mismatch
+    //only counts when it happens every time
+    let same_inode_order = (0..5).any(|attempt| {
+        let (first, second) = (format!("first-{}", attempt), format!("second-{}", attempt));
+        created(&first);
+        created(&second);
+        inode_order(&first) == inode_order(&second)
+    });
+    assert!(same_inode_order);
+}
+
+#[test]
+fn repair_recopies_truncated_files_and_leaves_the_rest() {
+    let scratch = Scratch::new("repair");
+    scratch.write("src/truncated.bin", "the whole file");
+    scratch.write("src/intact.txt", "complete");
+    scratch.write("src/missing.txt", "never copied");
+    scratch.write("dst/truncated.bin", "the wh");
+    //Right size, so it counts as intact even though only the size was compared
+    scratch.write("dst/intact.txt", "COMPLETE");
+    scratch.write("dst/extra.txt", "only at the destination");
+
+    let run = scratch.rcpy(["src", "dst", "--repair", "--format", "json"]);
+    let stats = run.assert_success().stats();
+    assert_eq!(stats["repaired"], 1);
+    assert_eq!(stats["repair_intact"], 1);
+    assert_eq!(scratch.read("dst/truncated.bin"), "the whole file");
+    assert_eq!(scratch.read("dst/intact.txt"), "COMPLETE");
+    assert_eq!(scratch.read("dst/missing.txt"), "never copied");
+    assert_eq!(scratch.read("dst/extra.txt"), "only at the destination");
+
+    //With --mirror the extra file goes too, and nothing is left to repair
+    let run = scratch.rcpy(["src", "dst", "--repair", "--mirror", "--format", "json"]);
+    let stats = run.assert_success().stats();
+    assert_eq!(stats["repaired"], 0);
+    assert_eq!(stats["deleted"], 1);
+    assert_eq!(tree(&scratch.path("dst")), ["intact.txt", "missing.txt", "truncated.bin"]);
+}
@@ -0,0 +1,284 @@
+mod common;
+
+use common::{tree, Scratch};
+
+#[test]
+fn verbose_output_shows_tidy_paths() {
+    let scratch = Scratch::new("tidy-paths");
+    scratch.write("src/sub/a.txt", "a");
+
+    let run = scratch.rcpy(["./src/.", "./dst//x", "--verbose"]);
+    run.assert_success();
+    assert!(run.stdout.contains("[FILE] src/sub/a.txt -> dst/x/sub/a.txt"), "{}", run.stdout);
+    assert!(!run.stdout.contains("/./") && !run.stdout.contains("//"), "{}", run.stdout);
+}
+
+#[test]
+fn copying_into_its_own_subdirectory_terminates_with_a_warning() {
+    let scratch = Scratch::new("into-itself");
+    scratch.write("src/a.txt", "a");
+    scratch.write("src/sub/b.txt", "b");
+
+    for _ in 0..2 {
+        let run = scratch.rcpy_within(30, ["src", "src/backup"]);
+        run.assert_success();
+        assert!(run.stderr.contains("because it is the destination"), "{}", run.stderr);
+    }
+    assert_eq!(scratch.read("src/backup/sub/b.txt"), "b");
+    assert!(!scratch.exists("src/backup/backup"));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_source_directory_is_followed() {
+    let scratch = Scratch::new("symlinked-source");
+    scratch.write("real/a.txt", "a");
+    scratch.write("real/sub/b.txt", "b");
+    std::os::unix::fs::symlink(scratch.path("real"), scratch.path("link")).unwrap();
+
+    scratch.rcpy(["link", "dst"]).assert_success();
+    assert_eq!(scratch.read("dst/a.txt"), "a");
+    assert_eq!(scratch.read("dst/sub/b.txt"), "b");
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_source_directory_is_refused_with_no_follow_source() {
+    let scratch = Scratch::new("symlinked-source-refused");
+    scratch.write("real/a.txt", "a");
+    std::os::unix::fs::symlink(scratch.path("real"), scratch.path("link")).unwrap();
+
+    let run = scratch.rcpy(["link", "dst", "--no-follow-source"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("is a symlink to a directory"), "{}", run.stderr);
+    assert!(!scratch.exists("dst"));
+}
+
+#[test]
+fn same_source_and_destination_exits_with_an_error() {
+    let scratch = Scratch::new("same-path");
+    scratch.write("a/x.txt", "x");
+
+    let run = scratch.rcpy(["./a", "a"]);
+    assert_eq!(run.code, Some(1), "{}", run.stderr);
+    assert!(run.stderr.contains("Source and destination paths are the same"), "{}", run.stderr);
+    assert_eq!(scratch.read("a/x.txt"), "x");
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_destination_directory_is_written_through() {
+    let scratch = Scratch::new("symlinked-dest-dir");
+    scratch.write("src/a.txt", "a");
+    scratch.mkdir("real");
+    std::os::unix::fs::symlink("real", scratch.path("link")).unwrap();
+
+    scratch.rcpy(["src", "link"]).assert_success();
+    assert_eq!(scratch.read("real/a.txt"), "a");
+    assert!(scratch.path("link").is_symlink());
+
+    let run = scratch.rcpy(["src", "link", "--no-follow-dest"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("is a symlink"), "{}", run.stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_destination_file_is_written_through() {
+    let scratch = Scratch::new("symlinked-dest-file");
+    scratch.write("new.txt", "new");
+    scratch.write("target.txt", "old");
+    std::os::unix::fs::symlink("target.txt", scratch.path("link.txt")).unwrap();
+
+    let refused = scratch.rcpy(["new.txt", "link.txt", "--no-follow-dest"]);
+    refused.assert_failure();
+    assert_eq!(scratch.read("target.txt"), "old");
+
+    scratch.rcpy(["new.txt", "link.txt"]).assert_success();
+    assert_eq!(scratch.read("target.txt"), "new");
+    assert!(scratch.path("link.txt").is_symlink());
+}
+
+#[cfg(unix)]
+#[test]
+fn dereference_copies_a_target_with_several_links_once() {
+    use std::os::unix::fs::MetadataExt;
+
+    let scratch = Scratch::new("dereference-shared-target");
+    scratch.write("src/real.txt", "data");
+    scratch.mkdir("src/sub");
+    std::os::unix::fs::symlink("real.txt", scratch.path("src/one")).unwrap();
+    std::os::unix::fs::symlink("real.txt", scratch.path("src/two")).unwrap();
+    std::os::unix::fs::symlink("../real.txt", scratch.path("src/sub/three")).unwrap();
+
+    scratch.rcpy(["src", "dst", "--dereference"]).assert_success();
+    let inode = |rel: &str| {
+        let path = scratch.path(rel);
+        assert!(!path.is_symlink(), "{} is still a symlink", rel);
+        std::fs::metadata(path).unwrap().ino()
+    };
+    let copied = inode("dst/real.txt");
+    for link in ["dst/one", "dst/two", "dst/sub/three"] {
+        assert_eq!(inode(link), copied, "{} is a second copy of the target", link);
+        assert_eq!(scratch.read(link), "data");
+    }
+}
+
+#[test]
+fn trailing_separators_on_the_source_change_nothing() {
+    let scratch = Scratch::new("trailing-separator");
+    scratch.write("src/a.txt", "a");
+    scratch.write("src/sub/b.txt", "b");
+
+    for flags in [&[][..], &["--no-recursive"], &["--no-recursive", "-s"], &["-s"]] {
+        scratch.rcpy(["src", "plain"].iter().chain(flags)).assert_success();
+        scratch.rcpy(["src/", "slash"].iter().chain(flags)).assert_success();
+        scratch.rcpy(["src//", "slashes"].iter().chain(flags)).assert_success();
+        let expected = tree(&scratch.path("plain"));
+        assert!(expected.contains(&"a.txt".to_string()), "{:?} with {:?}", expected, flags);
+        assert_eq!(tree(&scratch.path("slash")), expected, "src/ with {:?}", flags);
+        assert_eq!(tree(&scratch.path("slashes")), expected, "src// with {:?}", flags);
+        for dst in ["plain", "slash", "slashes"] {
+            std::fs::remove_dir_all(scratch.path(dst)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn strip_components_drops_leading_directories() {
+    let scratch = Scratch::new("strip-components");
+    scratch.write("proj/top.txt", "t");
+    scratch.write("proj/src/main.rs", "m");
+    scratch.write("proj/src/lib/x.rs", "x");
+
+    let run = scratch.rcpy(["proj", "zero", "--strip-components", "0"]);
+    assert_eq!(run.assert_success().stderr, "");
+    assert_eq!(tree(&scratch.path("zero")), ["src/", "src/lib/", "src/lib/x.rs", "src/main.rs", "top.txt"]);
+
+    let run = scratch.rcpy(["proj", "one", "--strip-components", "1"]);
+    run.assert_success();
+    assert_eq!(tree(&scratch.path("one")), ["lib/", "lib/x.rs", "main.rs"]);
+    assert!(run.stderr.contains("Warning: skipping top.txt, nothing is left of it after --strip-components 1"), "{}", run.stderr);
+}
+
+#[test]
+fn strip_components_deeper_than_the_tree_skips_everything() {
+    let scratch = Scratch::new("strip-components-too-deep");
+    scratch.write("proj/top.txt", "t");
+    scratch.write("proj/src/main.rs", "m");
+
+    let run = scratch.rcpy(["proj", "dst", "--strip-components", "5"]);
+    run.assert_success();
+    assert_eq!(run.stderr.matches("Warning: skipping").count(), 2, "{}", run.stderr);
+    assert!(tree(&scratch.path("dst")).is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn a_file_is_never_copied_onto_itself() {
+    let scratch = Scratch::new("same-file");
+    scratch.write("dir/a.txt", "keep");
+    std::os::unix::fs::symlink("dir/a.txt", scratch.path("alias.txt")).unwrap();
+    std::fs::hard_link(scratch.path("dir/a.txt"), scratch.path("hard.txt")).unwrap();
+
+    for (src, dst) in [("./dir/a.txt", "dir/../dir/a.txt"), ("alias.txt", "dir/a.txt"), ("hard.txt", "dir/a.txt")] {
+        let run = scratch.rcpy([src, dst]);
+        run.assert_failure();
+        assert!(run.stderr.contains("same"), "{} onto {}: {}", src, dst, run.stderr);
+        assert_eq!(scratch.read("dir/a.txt"), "keep", "{} onto {}", src, dst);
+    }
+}
+
+#[test]
+fn rename_rules_apply_in_order_to_file_names() {
+    let scratch = Scratch::new("rename");
+    scratch.write("src/IMG_001.jpeg", "1");
+    scratch.write("src/sub.jpeg/IMG_002.jpeg", "2");
+
+    scratch.rcpy(["src", "dst", "--rename", "^IMG_=>photo-", "--rename", r"\.jpeg$=>.jpg"]).assert_success();
+    //Directories keep their names
+    assert_eq!(tree(&scratch.path("dst")), ["photo-001.jpg", "sub.jpeg/", "sub.jpeg/photo-002.jpg"]);
+}
+
+#[test]
+fn rename_collision_is_reported() {
+    let scratch = Scratch::new("rename-collision");
+    scratch.write("src/a.jpg", "jpg");
+    scratch.write("src/a.jpeg", "jpeg");
+
+    let run = scratch.rcpy(["src", "dst", "--rename", r"\.jpeg$=>.jpg"]);
+    run.assert_failure();
+    assert!(run.stdout.contains("1 destination name collision(s)"), "{}", run.stdout);
+    assert!(run.stderr.contains("is already used by"), "{}", run.stderr);
+    assert_eq!(tree(&scratch.path("dst")), ["a.jpg"]);
+
+    scratch.rcpy(["src", "kept", "--rename", r"\.jpeg$=>.jpg", "--collision", "rename"]).assert_success();
+    assert_eq!(tree(&scratch.path("kept")), ["a (2).jpg", "a.jpg"]);
+}
+
+#[test]
+fn invalid_rename_pattern_fails_before_copying() {
+    let scratch = Scratch::new("rename-invalid");
+    scratch.write("src/a.txt", "a");
+
+    let run = scratch.rcpy(["src", "dst", "--rename", "([=>x"]);
+    run.assert_failure();
+    assert!(run.stderr.contains("invalid pattern"), "{}", run.stderr);
+    assert!(!scratch.exists("dst"));
+
+    scratch.rcpy(["src", "dst", "--rename", "no separator"]).assert_failure();
+    assert!(!scratch.exists("dst"));
+}
+
+#[cfg(unix)]
+#[test]
+fn copy_contents_follows_linked_directories_and_stops_at_loops() {
+    let scratch = Scratch::new("copy-contents-loop");
+    scratch.write("src/a/file.txt", "f");
+    scratch.write("elsewhere/outside.txt", "o");
+    std::os::unix::fs::symlink("..", scratch.path("src/a/up")).unwrap();
+    std::os::unix::fs::symlink("../elsewhere", scratch.path("src/linked")).unwrap();
+
+    let run = scratch.rcpy_within(20, ["src", "dst", "--copy-contents"]);
+    run.assert_success();
+    assert!(run.stderr.contains("Skipping symlink loop"), "{}", run.stderr);
+    assert_eq!(tree(&scratch.path("dst")), ["a/", "a/file.txt", "linked/", "linked/outside.txt"]);
+}
+
+#[test]
+fn dash_destination_streams_the_file_to_stdout() {
+    let scratch = Scratch::new("stdout");
+    let contents = "line one\nline two\n".repeat(1000);
+    scratch.write("notes.txt", &contents);
+
+    let run = scratch.rcpy(["notes.txt", "-"]);
+    run.assert_success();
+    //Nothing but the file's bytes, no banner or summary
+    assert_eq!(run.stdout, contents);
+    assert!(!scratch.exists("-"));
+
+    //A filtered out file leaves stdout empty and says why on stderr
+    let run = scratch.rcpy(["notes.txt", "-", "--exclude", "txt"]);
+    run.assert_success();
+    assert_eq!(run.stdout, "");
+    assert!(run.stderr.contains("Nothing to copy: notes.txt is filtered out."), "{}", run.stderr);
+}
+
+#[test]
+fn names_renamed_past_the_destination_limit_are_reported() {
+    let scratch = Scratch::new("name-too-long");
+    scratch.write("src/report-with-a-long-name.txt", "long");
+    scratch.write("src/a.txt", "short");
+    //Prefixing 240 bytes pushes only the longer name over the usual 255 byte limit
+    let rename = format!("^=>{}", "x".repeat(240));
+
+    //Warned about up front, the copy itself still fails on it
+    let run = scratch.rcpy(["src", "warned", "--rename", &rename]);
+    run.assert_failure();
+    assert!(run.stderr.contains("report-with-a-long-name.txt has a 267 byte name, the destination only allows 255 (--skip-too-long leaves such names out)"), "{}", run.stderr);
+
+    let run = scratch.rcpy(["src", "skipped", "--rename", &rename, "--skip-too-long", "--format", "json"]);
+    assert_eq!(run.assert_success().stats()["skipped_too_long"], 1);
+    assert!(run.stderr.contains("has a 267 byte name, the destination only allows 255, skipped"), "{}", run.stderr);
+    assert_eq!(tree(&scratch.path("skipped")), [format!("{}a.txt", "x".repeat(240))]);
+}
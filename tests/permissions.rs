@@ -0,0 +1,230 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{mode, modified, set_mode, Scratch};
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::process::Command;
+
+//Marks directories immutable (chattr +i) for as long as it lives, not even root can chmod them then.
+//None when chattr isn't there or isn't allowed (not root, a filesystem without the attribute)
+#[cfg(target_os = "linux")]
+struct Immutable(Vec<PathBuf>);
+
+#[cfg(target_os = "linux")]
+impl Immutable {
+    fn set(paths: &[PathBuf]) -> Option<Immutable> {
+        let guard = Immutable(paths.to_vec());
+        let set = Command::new("chattr").arg("+i").args(paths).status().is_ok_and(|status| status.success());
+        set.then_some(guard)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Immutable {
+    fn drop(&mut self) {
+        let _ = Command::new("chattr").arg("-i").args(&self.0).status();
+    }
+}
+
+//Source directories plus already existing destination directories that can't take new permissions
+#[cfg(target_os = "linux")]
+fn unchmoddable(scratch: &Scratch, count: usize) -> Option<Immutable> {
+    let dirs: Vec<PathBuf> = (0..count)
+        .map(|i| {
+            scratch.mkdir(&format!("src/d{}", i));
+            scratch.mkdir(&format!("dst/d{}", i))
+        })
+        .collect();
+    let guard = Immutable::set(&dirs);
+    if guard.is_none() {
+        eprintln!("skipped: chattr +i isn't available here");
+    }
+    guard
+}
+
+#[test]
+fn setgid_directory_keeps_its_bit() {
+    let scratch = Scratch::new("setgid-dir");
+    scratch.write("src/shared/a.txt", "a");
+    set_mode(&scratch.path("src/shared"), 0o2775);
+
+    scratch.rcpy(["src", "dst"]).assert_success();
+    assert_eq!(mode(&scratch.path("dst/shared")), 0o2775);
+}
+
+#[test]
+fn sticky_directory_keeps_its_bit() {
+    let scratch = Scratch::new("sticky-dir");
+    scratch.write("src/tmp/a.txt", "a");
+    set_mode(&scratch.path("src/tmp"), 0o1777);
+
+    scratch.rcpy(["src", "dst", "-s"]).assert_success();
+    assert_eq!(mode(&scratch.path("dst/tmp")), 0o1777);
+}
+
+#[test]
+fn chmod_and_chmod_dir_override_the_source_modes() {
+    let scratch = Scratch::new("chmod-octal");
+    scratch.write("src/secret.txt", "s");
+    scratch.write("src/sub/key.pem", "k");
+    set_mode(&scratch.path("src/secret.txt"), 0o644);
+    set_mode(&scratch.path("src/sub/key.pem"), 0o755);
+    set_mode(&scratch.path("src/sub"), 0o755);
+
+    scratch.rcpy(["src", "dst", "--chmod", "600", "--chmod-dir", "700"]).assert_success();
+    assert_eq!(mode(&scratch.path("dst/secret.txt")), 0o600);
+    assert_eq!(mode(&scratch.path("dst/sub/key.pem")), 0o600);
+    assert_eq!(mode(&scratch.path("dst/sub")), 0o700);
+}
+
+#[test]
+fn chmod_rejects_an_invalid_mode() {
+    let scratch = Scratch::new("chmod-invalid");
+    scratch.write("src/a.txt", "a");
+
+    scratch.rcpy(["src", "dst", "--chmod", "999"]).assert_failure();
+    assert!(!scratch.exists("dst"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn permission_failure_is_a_warning_not_an_error() {
+    let scratch = Scratch::new("permission-failure");
+    let Some(_guard) = unchmoddable(&scratch, 1) else { return };
+
+    let run = scratch.rcpy(["src", "dst"]);
+    run.assert_success();
+    assert!(run.stdout.contains("Warning: permissions couldn't be set on 1 file(s)/directory(ies)"), "{}", run.stdout);
+    assert_eq!(run.stderr, "");
+}
+
+#[test]
+fn keep_dest_dir_perms_only_spares_existing_directories() {
+    let scratch = Scratch::new("keep-dest-dir-perms");
+    scratch.write("src/existing/a.txt", "a");
+    scratch.write("src/fresh/b.txt", "b");
+    set_mode(&scratch.path("src/existing"), 0o755);
+    set_mode(&scratch.path("src/fresh"), 0o750);
+    set_mode(&scratch.mkdir("dst/existing"), 0o700);
+
+    scratch.rcpy(["src", "dst", "--keep-dest-dir-perms"]).assert_success();
+    assert_eq!(mode(&scratch.path("dst/existing")), 0o700);
+    assert_eq!(mode(&scratch.path("dst/fresh")), 0o750);
+
+    //Without the flag the existing directory takes the source's mode like before
+    scratch.rcpy(["src", "dst"]).assert_success();
+    assert_eq!(mode(&scratch.path("dst/existing")), 0o755);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn many_permission_failures_make_one_summary_line() {
+    let scratch = Scratch::new("permission-failures");
+    let Some(_guard) = unchmoddable(&scratch, 25) else { return };
+
+    let run = scratch.rcpy(["src", "dst"]);
+    run.assert_success();
+    assert_eq!(run.stdout.matches("permissions couldn't be set").count(), 1, "{}", run.stdout);
+    assert!(run.stdout.contains("on 25 file(s)/directory(ies)"), "{}", run.stdout);
+    assert_eq!(run.stderr, "");
+
+    //--verbose still lists each one
+    let run = scratch.rcpy(["src", "dst", "--verbose"]);
+    assert_eq!(run.assert_success().stderr.matches("Failed to write permissions for").count(), 25, "{}", run.stderr);
+}
+
+#[test]
+fn restrictive_source_directories_get_their_modes_after_the_copy() {
+    let scratch = Scratch::new("restrictive-dirs");
+    scratch.write("src/read-only/a.txt", "a");
+    scratch.write("src/read-only/private/b.txt", "b");
+    scratch.write("src/private-top/c.txt", "c");
+    let dirs = [("read-only/private", 0o500), ("read-only", 0o555), ("private-top", 0o500)];
+    for (dir, dir_mode) in dirs {
+        set_mode(&scratch.path(&format!("src/{}", dir)), dir_mode);
+    }
+
+    for (dst, mode_flag) in [("parallel", None), ("single", Some("-s"))] {
+        scratch.rcpy_unprivileged(["src", dst].into_iter().chain(mode_flag)).assert_success();
+        for (dir, dir_mode) in dirs {
+            assert_eq!(mode(&scratch.path(&format!("{}/{}", dst, dir))), dir_mode, "{}/{}", dst, dir);
+        }
+        assert_eq!(scratch.read(&format!("{}/read-only/private/b.txt", dst)), "b");
+        assert_eq!(scratch.read(&format!("{}/private-top/c.txt", dst)), "c");
+    }
+
+    //Writable again, or the scratch tree can't be removed
+    for root in ["src", "parallel", "single"] {
+        for (dir, _) in dirs.iter().rev() {
+            set_mode(&scratch.path(&format!("{}/{}", root, dir)), 0o755);
+        }
+    }
+}
+
+#[test]
+fn data_only_leaves_new_files_at_the_default_mode() {
+    let scratch = Scratch::new("data-only-modes");
+    let script = scratch.write("src/run.sh", "#!/bin/sh");
+    set_mode(&script, 0o700);
+
+    scratch.rcpy(["src", "dst", "--data-only"]).assert_success();
+    scratch.rcpy(["src", "chmod", "--data-only", "--chmod", "777"]).assert_success();
+    //New files are never created executable, only a copied or --chmod mode could have set those bits
+    assert_eq!(mode(&scratch.path("dst/run.sh")) & 0o111, 0);
+    assert_eq!(mode(&scratch.path("chmod/run.sh")) & 0o111, 0);
+    assert_eq!(scratch.read("dst/run.sh"), "#!/bin/sh");
+}
+
+#[test]
+fn data_only_ignores_modes_and_times_stored_in_archives() {
+    let scratch = Scratch::new("data-only-archive");
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o755);
+    header.set_mtime(1_000_000_000);
+    header.set_size(9);
+    header.set_cksum();
+    let mut plain = tar::Builder::new(std::fs::File::create(scratch.path("in.tar")).unwrap());
+    plain.append_data(&mut header.clone(), "run.sh", &b"#!/bin/sh"[..]).unwrap();
+    plain.finish().unwrap();
+    let gzipped = flate2::write::GzEncoder::new(std::fs::File::create(scratch.path("in.tar.gz")).unwrap(), flate2::Compression::default());
+    let mut gzipped = tar::Builder::new(gzipped);
+    gzipped.append_data(&mut header, "run.sh", &b"#!/bin/sh"[..]).unwrap();
+    gzipped.into_inner().unwrap().finish().unwrap();
+    let stored = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+
+    for archive in ["in.tar", "in.tar.gz"] {
+        let dst = format!("{}-data-only", archive);
+        scratch.rcpy([archive, &dst, "--data-only"]).assert_success();
+        let extracted = scratch.path(&format!("{}/run.sh", dst));
+        assert_eq!(mode(&extracted) & 0o111, 0, "{}", archive);
+        assert_ne!(modified(&extracted), stored, "{}", archive);
+
+        //Without it the archive's mode and time are applied
+        let dst = format!("{}-kept", archive);
+        scratch.rcpy([archive, &dst]).assert_success();
+        let extracted = scratch.path(&format!("{}/run.sh", dst));
+        assert_eq!(mode(&extracted), 0o755, "{}", archive);
+        assert_eq!(modified(&extracted), stored, "{}", archive);
+    }
+}
+
+//Directories that refuse a chmod: any attempt to set permissions would show up as a failure
+#[cfg(target_os = "linux")]
+#[test]
+fn data_only_never_sets_permissions() {
+    let scratch = Scratch::new("data-only-no-chmod");
+    let Some(_guard) = unchmoddable(&scratch, 3) else { return };
+
+    let run = scratch.rcpy(["src", "dst", "--data-only", "--verbose"]);
+    run.assert_success();
+    assert!(!run.stdout.contains("permissions couldn't be set"), "{}", run.stdout);
+    assert!(!run.stderr.contains("Failed to write permissions"), "{}", run.stderr);
+
+    //The same copy without it does try
+    let run = scratch.rcpy(["src", "dst"]);
+    assert!(run.assert_success().stdout.contains("permissions couldn't be set on 3"), "{}", run.stdout);
+}